@@ -0,0 +1,585 @@
+/**
+ * @file compiler.rs
+ * @brief compiles forms to bytecode for the vm
+ *
+ * Special forms are picked out, and macros expanded, once, when a form
+ * is compiled, rather than every time it is evaluated. Anything the
+ * compiler doesn't handle, like def and try, becomes an instruction
+ * that hands the form to the tree-walker, so the two mix freely.
+ *
+ * Compiled code scopes names the way the tree-walker does: a fn sees
+ * the locals of the scope it was made in, not those of whatever
+ * called it.
+ */
+use types::Element;
+use types::{Symbol, Keyword, List, Vec, Map, Local, Interned, nil};
+
+use symbol::{Sym, intern};
+
+use special;
+use special::{Special, special_form};
+
+use eval::{Bindings, check_recur, body_form};
+
+mod types;
+
+/**
+ * @brief one vm instruction
+ *
+ * Jump targets are indexes into the chunk's code. Forms and constants
+ * are indexes into its constants.
+ */
+#[deriving(Clone, Eq)]
+pub enum Op {
+    /// push a constant
+    Const(uint),
+    /// push a local: its name, how many frames out it is, and its slot
+    LoadLocal(Sym, uint, uint),
+    /// push what a symbol names
+    LoadGlobal(Sym),
+    Dup,
+    Pop,
+    Jump(uint),
+    /// pop a test and jump if it passes; the name is for errors
+    JumpIf(~str, uint),
+    /// pop a test and jump if it fails
+    JumpUnless(~str, uint),
+    /// pop the value on top if it matches a case constant, or jump
+    Case(uint, uint),
+    /// throw for a case value that no clause matched
+    NoMatch,
+    /// start a frame of locals, which lasts until the next PopFrame
+    PushFrame,
+    PopFrame,
+    /// pop a value and bind it in the innermost frame
+    Bind(Sym),
+    /// pop that many items into a vector
+    MakeVec(uint),
+    /// pop that many key/value pairs into a map
+    MakeMap(uint),
+    /// push a fn made from a (fn ...) form, in the current namespace
+    MakeFn(uint),
+    /// evaluate a form with the tree-walker
+    Fallback(uint),
+    /// if the fn on top is a macro, evaluate the call form with the
+    /// tree-walker instead, and jump past the call
+    MacroGuard(uint, uint),
+    /// pop a fn and its args and call it: the arg count, the call form,
+    /// how many frames out the scope the call runs in is, and whether
+    /// it is in tail position of a loop
+    Call(uint, uint, uint, bool),
+    /// call in place of the current fn: the arg count and call form
+    TailCall(uint, uint),
+    /// rebind a loop's or fn's names and go back to its start: where
+    /// it starts, how many frames to leave, the arg count, the names,
+    /// and whether they are the fn's own params
+    Recur(uint, uint, uint, ~[Sym], bool),
+    Return
+}
+
+/**
+ * @brief the compiled code of a form or fn body
+ */
+#[deriving(Clone, Eq)]
+pub struct Chunk {
+    code: ~[Op],
+    consts: ~[Element]
+}
+
+/// what a call in tail position hands its result back to
+#[deriving(Eq)]
+enum Chain {
+    // an expression evaluated for its value
+    Expr,
+    // the body of a loop, which runs tail calls itself
+    LoopBody,
+    // the body of the fn being compiled
+    FnBody
+}
+
+/**
+ * @brief where calls in tail position come back to
+ * @param kind:     what is running them
+ * @param depth:    how many frames were pushed when it started
+ */
+struct Tail {
+    kind: Chain,
+    depth: uint
+}
+
+/**
+ * @brief where recur goes back to
+ */
+struct Target {
+    start: uint,
+    // frames pushed at the start
+    depth: uint,
+    names: ~[Sym],
+    is_fn: bool
+}
+
+struct Compiler<'a> {
+    bindings: &'a mut Bindings,
+    code: ~[Op],
+    consts: ~[Element],
+    // frames pushed by let, loop and the like, so far
+    depth: uint
+}
+
+/**
+ * @brief compile a form to run at the toplevel, or wherever the
+ *        bindings' scope is
+ */
+pub fn compile(bindings: &mut Bindings, form: &Element) -> Chunk
+{
+    let mut c = Compiler { bindings: bindings, code: ~[], consts: ~[], depth: 0 };
+    c.compile(form, true, &Tail { kind: Expr, depth: 0 }, None);
+    c.finish()
+}
+
+/**
+ * @brief compile the body of a fn
+ * @param params:   its params, as written
+ * @param body:     the analyzed body
+ */
+pub fn compile_fn(bindings: &mut Bindings, params: &[~str], body: &Element) -> Chunk
+{
+    let names: ~[Sym] = params.iter().filter(|p| p.as_slice() != "&")
+        .map(|p| intern(p.as_slice())).collect();
+    let target = Target { start: 0, depth: 0, names: names, is_fn: true };
+    let mut c = Compiler { bindings: bindings, code: ~[], consts: ~[], depth: 0 };
+    c.compile(body, true, &Tail { kind: FnBody, depth: 0 }, Some(&target));
+    c.finish()
+}
+
+impl<'a> Compiler<'a> {
+    fn finish(self) -> Chunk {
+        let mut code = self.code;
+        code.push(Return);
+        Chunk { code: code, consts: self.consts }
+    }
+    fn emit(&mut self, op: Op) -> uint {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+    fn constant(&mut self, e: Element) -> uint {
+        self.consts.push(e);
+        self.consts.len() - 1
+    }
+    /**
+     * @brief point the jump at ``at`` to the next instruction
+     */
+    fn patch(&mut self, at: uint) {
+        let here = self.code.len();
+        self.code[at] = match self.code[at].clone() {
+            Jump(_) => Jump(here),
+            JumpIf(name, _) => JumpIf(name, here),
+            JumpUnless(name, _) => JumpUnless(name, here),
+            Case(c, _) => Case(c, here),
+            MacroGuard(form, _) => MacroGuard(form, here),
+            op => fail!("patch: {:?} isn't a jump", op)
+        };
+    }
+    /**
+     * @brief compile a form whose value is used by what encloses it
+     */
+    fn compile_arg(&mut self, form: &Element) {
+        let tail = Tail { kind: Expr, depth: self.depth };
+        self.compile(form, false, &tail, None);
+    }
+    /**
+     * @brief compile a form
+     * @param tail:     whether the form is in tail position
+     * @param chain:    where calls in tail position come back to
+     * @param target:   where recur goes, if anywhere
+     */
+    fn compile(&mut self, form: &Element, tail: bool, chain: &Tail, target: Option<&Target>) {
+        let items = match *form {
+            Symbol(ref s) => {
+                self.emit(LoadGlobal(intern(s.as_slice())));
+                return;
+            },
+            Interned(_, sym) => {
+                self.emit(LoadGlobal(sym));
+                return;
+            },
+            Local(_, sym, depth, slot) => {
+                self.emit(LoadLocal(sym, depth, slot));
+                return;
+            },
+            Vec(ref v) => {
+                for x in v.iter() {
+                    self.compile_arg(x);
+                }
+                self.emit(MakeVec(v.len()));
+                return;
+            },
+            Map(ref m) => {
+                for &(ref k, ref v) in m.iter() {
+                    self.compile_arg(k);
+                    self.compile_arg(v);
+                }
+                self.emit(MakeMap(m.len()));
+                return;
+            },
+            List(ref l) if l.len() > 0 => l.to_vec(),
+            _ => {
+                let c = self.constant(form.clone());
+                self.emit(Const(c));
+                return;
+            }
+        };
+        let vals = items.slice_from(1);
+        let compiled = match items[0] {
+            Symbol(ref head) => {
+                let h = head.as_slice();
+                match special_form(h) {
+                    Some(special::If) => self.compile_if(vals, tail, chain, target),
+                    Some(special::Do) => self.compile_do(vals, tail, chain, target),
+                    Some(special::Let) => self.compile_let(vals, tail, chain, target),
+                    Some(special::Loop) => self.compile_loop(vals),
+                    Some(special::Recur) => self.compile_recur(vals, target),
+                    Some(kind @ special::And) | Some(kind @ special::Or) => {
+                        self.compile_and_or(kind, h, vals, tail, chain, target)
+                    },
+                    Some(kind @ special::When) | Some(kind @ special::WhenNot) => {
+                        self.compile_when(kind, h, vals, tail, chain, target)
+                    },
+                    Some(special::Cond) => self.compile_cond(vals, tail, chain, target),
+                    Some(special::Case) => self.compile_case(vals, tail, chain, target),
+                    Some(kind @ special::IfLet) | Some(kind @ special::WhenLet) => {
+                        self.compile_if_let(kind, h, vals, tail, chain, target)
+                    },
+                    Some(special::Quote) if vals.len() == 1 => {
+                        let c = self.constant(vals[0].clone());
+                        self.emit(Const(c));
+                        true
+                    },
+                    Some(special::Fn) => self.compile_fn_form(vals),
+                    // the rest are left to the tree-walker
+                    Some(_) => false,
+                    None => {
+                        // analyze leaves calls that might be to a macro
+                        // alone, so they are expanded here, once
+                        match self.bindings.macroexpand_1(form) {
+                            Ok(Some(expansion)) => {
+                                self.compile(&expansion, tail, chain, target);
+                                true
+                            },
+                            Ok(None) => {
+                                self.compile_call(form, items.as_slice(), tail, chain);
+                                true
+                            },
+                            // let the tree-walker throw it when the form is run
+                            Err(_) => false
+                        }
+                    }
+                }
+            },
+            _ => {
+                self.compile_call(form, items.as_slice(), tail, chain);
+                true
+            }
+        };
+        if !compiled {
+            let c = self.constant(form.clone());
+            self.emit(Fallback(c));
+        }
+    }
+    fn compile_if(&mut self, vals: &[Element], tail: bool, chain: &Tail,
+                  target: Option<&Target>) -> bool {
+        if vals.len() < 2 || vals.len() > 3 {
+            return false;
+        }
+        self.compile_arg(&vals[0]);
+        let test = self.emit(JumpUnless(~"if", 0));
+        self.compile(&vals[1], tail, chain, target);
+        let end = self.emit(Jump(0));
+        self.patch(test);
+        if vals.len() == 3 {
+            self.compile(&vals[2], tail, chain, target);
+        } else {
+            let c = self.constant(nil);
+            self.emit(Const(c));
+        }
+        self.patch(end);
+        true
+    }
+    fn compile_do(&mut self, vals: &[Element], tail: bool, chain: &Tail,
+                  target: Option<&Target>) -> bool {
+        if vals.len() == 0 {
+            let c = self.constant(nil);
+            self.emit(Const(c));
+            return true;
+        }
+        let last = vals.len() - 1;
+        for x in vals.slice_to(last).iter() {
+            self.compile_arg(x);
+            self.emit(Pop);
+        }
+        self.compile(&vals[last], tail, chain, target);
+        true
+    }
+    /**
+     * @brief push a frame and bind a binding vector's names in it
+     * @return the names, or None if the vector isn't well formed
+     */
+    fn compile_bindings(&mut self, binds: &Element) -> Option<~[Sym]> {
+        let binds = match *binds {
+            Vec(ref v) if v.len() % 2 == 0 => v.to_vec(),
+            _ => return None
+        };
+        let mut names: ~[Sym] = ~[];
+        for pair in binds.chunks(2) {
+            match pair[0] {
+                Symbol(ref s) => names.push(intern(s.as_slice())),
+                _ => return None
+            }
+        }
+        self.emit(PushFrame);
+        self.depth += 1;
+        for (pair, name) in binds.chunks(2).zip(names.iter()) {
+            self.compile_arg(&pair[1]);
+            self.emit(Bind(*name));
+        }
+        Some(names)
+    }
+    fn compile_let(&mut self, vals: &[Element], tail: bool, chain: &Tail,
+                   target: Option<&Target>) -> bool {
+        if vals.len() == 0 || self.compile_bindings(&vals[0]).is_none() {
+            return false;
+        }
+        self.compile(&body_form(vals.slice_from(1)), tail, chain, target);
+        self.emit(PopFrame);
+        self.depth -= 1;
+        true
+    }
+    fn compile_loop(&mut self, vals: &[Element]) -> bool {
+        if vals.len() == 0 {
+            return false;
+        }
+        let body = body_form(vals.slice_from(1));
+        if check_recur(self.bindings, &body, true, ~"body of (loop ...)").is_some() {
+            return false;
+        }
+        let outside = self.depth;
+        let names = match self.compile_bindings(&vals[0]) {
+            Some(names) => names,
+            None => return false
+        };
+        let target = Target { start: self.code.len(), depth: self.depth, names: names,
+                              is_fn: false };
+        // calls in tail position of the body run in the scope the
+        // loop started in, as if they were its body
+        let loop_chain = Tail { kind: LoopBody, depth: outside };
+        self.compile(&body, true, &loop_chain, Some(&target));
+        self.emit(PopFrame);
+        self.depth -= 1;
+        true
+    }
+    fn compile_recur(&mut self, vals: &[Element], target: Option<&Target>) -> bool {
+        let target = match target {
+            Some(target) => target,
+            None => return false
+        };
+        for x in vals.iter() {
+            self.compile_arg(x);
+        }
+        let leave = self.depth - target.depth;
+        self.emit(Recur(target.start, leave, vals.len(), target.names.clone(), target.is_fn));
+        true
+    }
+    fn compile_and_or(&mut self, form: Special, head: &str, vals: &[Element], tail: bool,
+                      chain: &Tail, target: Option<&Target>) -> bool {
+        let is_or = form == special::Or;
+        if vals.len() == 0 {
+            let c = self.constant(if is_or { nil } else { ::types::Boolean(true) });
+            self.emit(Const(c));
+            return true;
+        }
+        // the value that stops it is the result, so test a copy
+        let last = vals.len() - 1;
+        let mut exits: ~[uint] = ~[];
+        for x in vals.slice_to(last).iter() {
+            self.compile_arg(x);
+            self.emit(Dup);
+            exits.push(if is_or {
+                self.emit(JumpIf(head.to_owned(), 0))
+            } else {
+                self.emit(JumpUnless(head.to_owned(), 0))
+            });
+            self.emit(Pop);
+        }
+        self.compile(&vals[last], tail, chain, target);
+        for exit in exits.iter() {
+            self.patch(*exit);
+        }
+        true
+    }
+    fn compile_when(&mut self, form: Special, head: &str, vals: &[Element], tail: bool,
+                    chain: &Tail, target: Option<&Target>) -> bool {
+        if vals.len() == 0 {
+            return false;
+        }
+        self.compile_arg(&vals[0]);
+        let test = if form == special::When {
+            self.emit(JumpUnless(head.to_owned(), 0))
+        } else {
+            self.emit(JumpIf(head.to_owned(), 0))
+        };
+        self.compile(&body_form(vals.slice_from(1)), tail, chain, target);
+        let end = self.emit(Jump(0));
+        self.patch(test);
+        let c = self.constant(nil);
+        self.emit(Const(c));
+        self.patch(end);
+        true
+    }
+    fn compile_cond(&mut self, vals: &[Element], tail: bool, chain: &Tail,
+                    target: Option<&Target>) -> bool {
+        if vals.len() % 2 != 0 {
+            return false;
+        }
+        let mut ends: ~[uint] = ~[];
+        let mut exhaustive = false;
+        for clause in vals.chunks(2) {
+            match clause[0] {
+                Keyword(ref k) if *k == ~"else" => {
+                    self.compile(&clause[1], tail, chain, target);
+                    exhaustive = true;
+                    break;
+                },
+                ref test => {
+                    self.compile_arg(test);
+                    let next = self.emit(JumpUnless(~"cond", 0));
+                    self.compile(&clause[1], tail, chain, target);
+                    ends.push(self.emit(Jump(0)));
+                    self.patch(next);
+                }
+            }
+        }
+        if !exhaustive {
+            let c = self.constant(nil);
+            self.emit(Const(c));
+        }
+        for end in ends.iter() {
+            self.patch(*end);
+        }
+        true
+    }
+    fn compile_case(&mut self, vals: &[Element], tail: bool, chain: &Tail,
+                    target: Option<&Target>) -> bool {
+        if vals.len() == 0 {
+            return false;
+        }
+        self.compile_arg(&vals[0]);
+        let clauses = vals.slice_from(1);
+        let mut ends: ~[uint] = ~[];
+        for clause in clauses.chunks(2).filter(|c| c.len() == 2) {
+            // the constants are not evaluated
+            let c = self.constant(clause[0].clone());
+            let next = self.emit(Case(c, 0));
+            self.compile(&clause[1], tail, chain, target);
+            ends.push(self.emit(Jump(0)));
+            self.patch(next);
+        }
+        if clauses.len() % 2 == 1 {
+            self.emit(Pop);
+            self.compile(&clauses[clauses.len() - 1], tail, chain, target);
+        } else {
+            self.emit(NoMatch);
+        }
+        for end in ends.iter() {
+            self.patch(*end);
+        }
+        true
+    }
+    fn compile_if_let(&mut self, form: Special, head: &str, vals: &[Element], tail: bool,
+                      chain: &Tail, target: Option<&Target>) -> bool {
+        let is_if = form == special::IfLet;
+        if vals.len() < 1 || (is_if && (vals.len() < 2 || vals.len() > 3)) {
+            return false;
+        }
+        let (name, test) = match vals[0] {
+            Vec(ref v) if v.len() == 2 => match (v[0], v[1]) {
+                (Symbol(name), test) => (intern(name.as_slice()), test),
+                _ => return false
+            },
+            _ => return false
+        };
+        self.compile_arg(&test);
+        self.emit(Dup);
+        let test = self.emit(JumpUnless(head.to_owned(), 0));
+        self.emit(PushFrame);
+        self.depth += 1;
+        self.emit(Bind(name));
+        let body = if is_if { vals[1].clone() } else { body_form(vals.slice_from(1)) };
+        self.compile(&body, tail, chain, target);
+        self.emit(PopFrame);
+        self.depth -= 1;
+        let end = self.emit(Jump(0));
+        self.patch(test);
+        self.emit(Pop);
+        let otherwise = if is_if && vals.len() == 3 { vals[2].clone() } else { nil };
+        self.compile(&otherwise, tail, chain, target);
+        self.patch(end);
+        true
+    }
+    /**
+     * @brief make the fn once, here, so running the form only has to
+     *        put it in the right namespace
+     */
+    fn compile_fn_form(&mut self, vals: &[Element]) -> bool {
+        match self.bindings.fn_nobind(vals) {
+            Ok(f) => {
+                let c = self.constant(f);
+                self.emit(MakeFn(c));
+                true
+            },
+            // let the tree-walker throw it when the form is run
+            Err(_) => false
+        }
+    }
+    fn compile_call(&mut self, form: &Element, items: &[Element], tail: bool, chain: &Tail) {
+        self.compile_arg(&items[0]);
+        let call = self.constant(form.clone());
+        let guard = self.emit(MacroGuard(call, 0));
+        for x in items.slice_from(1).iter() {
+            self.compile_arg(x);
+        }
+        let argc = items.len() - 1;
+        if tail && chain.kind == FnBody {
+            self.emit(TailCall(argc, call));
+        } else {
+            let leave = self.depth - chain.depth;
+            self.emit(Call(argc, call, leave, tail && chain.kind == LoopBody));
+        }
+        self.patch(guard);
+    }
+}
+
+
+#[test]
+fn test_compile() {
+    let mut bindings = Bindings::new();
+    let chunk = compile(&mut bindings, &::tokenizer::tokenize("(if true 1 2)"));
+    assert!(chunk.code == ~[Const(0), JumpUnless(~"if", 4), Const(1), Jump(5), Const(2), Return]);
+    assert!(chunk.consts == ~[::types::Boolean(true), ::types::Number(1), ::types::Number(2)]);
+    // a name that isn't defined yet might turn out to be a macro
+    let chunk = compile(&mut bindings, &::tokenizer::tokenize("(later 1)"));
+    assert!(chunk.code == ~[LoadGlobal(intern("later")), MacroGuard(0, 4), Const(1),
+                            Call(1, 0, 0, false), Return]);
+    // forms the compiler doesn't handle go to the tree-walker
+    let chunk = compile(&mut bindings, &::tokenizer::tokenize("(def x 1)"));
+    assert!(chunk.code == ~[Fallback(0), Return]);
+}
+
+#[test]
+fn test_compile_recur() {
+    let mut bindings = Bindings::new();
+    let (i, x) = (intern("i"), intern("x"));
+    let body = bindings.analyze_body([~"x"], &::tokenizer::tokenize("(loop [i x] (recur i))"));
+    let chunk = compile_fn(&mut bindings, [~"x"], &body);
+    assert!(chunk.code == ~[PushFrame, LoadLocal(x, 1, 0), Bind(i), LoadLocal(i, 0, 0),
+                            Recur(3, 0, 1, ~[i], false), PopFrame, Return]);
+}
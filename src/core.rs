@@ -0,0 +1,1429 @@
+/**
+ * @file core.rs
+ * @brief native function definitions
+ *
+ * This module contains builtins that need to call back into the
+ * interpreter, like eval and apply, and builtins that build new
+ * functions out of other ones.
+ */
+use std::rc::Rc;
+
+use types::Element;
+use types::{List, Vec, Map, ElementMap, Boolean, Number, EvalError, Lazy, LazySeq, nil};
+use types::{Atom, AtomRef, Keyword, Symbol, String, Character};
+use types::list_of;
+
+use functypes::{NativeFn, NativeFunc};
+
+use persistent::PVec;
+
+use env::Env;
+
+use primitives::seq_items;
+use primitives::{cons, nth, take, dropfn, rangefn};
+use primitives::{equal, concat, count, is_empty, read_string};
+
+use strings::strfn;
+
+use eval::{Bindings, EvalResult, Exception, eval_error};
+
+mod types;
+
+/**
+ * @brief the items of a collection, throwing if it isn't one
+ *
+ * Lazy seqs are realized in full.
+ */
+fn items(bindings: &mut Bindings, name: &str, coll: &Element) -> Result<~[Element], Exception>
+{
+    match seq_items(name, &try_eval!(bindings.realize_all(coll.clone()))) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(Exception::new(e))
+    }
+}
+
+/**
+ * @brief call a builtin from a native, throwing its EvalErrors
+ */
+fn primitive(f: fn(&[Element]) -> Element, args: &[Element]) -> EvalResult
+{
+    match f(args) {
+        EvalError(e) => eval_error(e),
+        result => Ok(result)
+    }
+}
+
+/**
+ * @brief a lazy seq that calls f to realize itself
+ */
+fn lazy(f: ~NativeFn) -> Element
+{
+    Lazy(LazySeq::new(NativeFunc::with_state(f)))
+}
+
+/**
+ * @brief an already realized lazy seq cell
+ */
+fn lazy_cons(first: Element, rest: Element) -> EvalResult
+{
+    Ok(Lazy(LazySeq::cons(first, rest)))
+}
+
+fn is_lazy(e: &Element) -> bool
+{
+    match *e {
+        Lazy(_) => true,
+        _ => false
+    }
+}
+
+
+#[allow(dead_code)]
+pub fn eval_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // evaluate data as code
+    match args {
+        [ref form] => bindings.eval_elem(form.clone()),
+        _ => eval_error(format!("eval: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn apply_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (apply f args... coll), with coll spread out into the arg list
+    if args.len() < 2 {
+        return eval_error(format!("apply: wrong number of args ({:u})", args.len()));
+    }
+    let last = args.len() - 1;
+    let mut spread = args.slice(1, last).to_owned();
+    match try_eval!(bindings.realize_all(args[last].clone())) {
+        List(ref l) => spread.push_all_move(l.to_vec()),
+        Vec(ref v) => spread.push_all_move(v.to_vec()),
+        nil => (),
+        _ => return eval_error(~"apply: last arg must be a list or vector")
+    }
+    bindings.apply(args[0].clone(), spread)
+}
+
+#[allow(dead_code)]
+pub fn read_string_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // record literals are read as the records they stand for
+    let form = try_eval!(primitive(read_string, args));
+    bindings.read_records(form)
+}
+
+#[allow(dead_code)]
+pub fn macroexpand_1_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref form] => match bindings.macroexpand_1(form) {
+            Ok(Some(e)) => Ok(e),
+            Ok(None) => Ok(form.clone()),
+            Err(e) => Err(e)
+        },
+        _ => eval_error(format!("macroexpand-1: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn macroexpand_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref form] => bindings.macroexpand(form.clone()),
+        _ => eval_error(format!("macroexpand: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn macroexpand_all_fn(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref form] => bindings.macroexpand_all(form.clone()),
+        _ => eval_error(format!("macroexpand-all: wrong number of args ({:u})", args.len()))
+    }
+}
+
+
+/// a function with some of its args already supplied
+struct Partial {
+    f: Element,
+    args: ~[Element]
+}
+
+impl NativeFn for Partial {
+    fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult {
+        bindings.apply(self.f.clone(), self.args + args)
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Partial { f: self.f.clone(), args: self.args.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn partial(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    if args.len() < 1 {
+        return eval_error(~"partial: wrong number of args (0)");
+    }
+    Ok(NativeFunc::with_state(~Partial {
+        f: args[0].clone(),
+        args: args.slice_from(1).to_owned()
+    } as ~NativeFn))
+}
+
+/// functions called right to left, each on the last one's result
+struct Comp {
+    fns: ~[Element]
+}
+
+impl NativeFn for Comp {
+    fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult {
+        if self.fns.len() == 0 {
+            // (comp) is identity
+            return match args {
+                [ref x] => Ok(x.clone()),
+                _ => eval_error(format!("wrong number of args ({:u}) passed to fn", args.len()))
+            };
+        }
+        let last = self.fns.len() - 1;
+        let mut result = try_eval!(bindings.apply(self.fns[last].clone(), args.to_owned()));
+        for f in self.fns.slice_to(last).rev_iter() {
+            result = try_eval!(bindings.apply(f.clone(), ~[result]));
+        }
+        Ok(result)
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Comp { fns: self.fns.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn comp(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    Ok(NativeFunc::with_state(~Comp { fns: args.to_owned() } as ~NativeFn))
+}
+
+/// a function that ignores its args
+struct Constantly {
+    value: Element
+}
+
+impl NativeFn for Constantly {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        Ok(self.value.clone())
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Constantly { value: self.value.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn constantly(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref x] => Ok(NativeFunc::with_state(~Constantly { value: x.clone() } as ~NativeFn)),
+        _ => eval_error(format!("constantly: wrong number of args ({:u})", args.len()))
+    }
+}
+
+
+#[allow(dead_code)]
+pub fn map(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (map f coll...), calling f with an item from each coll until the
+    // shortest runs out; f isn't called until the seq is used
+    if args.len() < 2 {
+        return eval_error(format!("map: wrong number of args ({:u})", args.len()));
+    }
+    Ok(lazy(~LazyMap {
+        f: args[0].clone(),
+        colls: args.slice_from(1).to_owned()
+    } as ~NativeFn))
+}
+
+/**
+ * @brief the items of a coll that pred does (or doesn't) pass
+ */
+fn select(name: &str, args: &[Element], keep: bool) -> EvalResult
+{
+    match args {
+        [ref pred, ref coll] => Ok(lazy(~LazyFilter {
+            name: name.to_owned(),
+            pred: pred.clone(),
+            coll: coll.clone(),
+            keep: keep
+        } as ~NativeFn)),
+        _ => eval_error(format!("{:s}: wrong number of args ({:u})", name, args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn filter(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    select("filter", args, true)
+}
+
+#[allow(dead_code)]
+pub fn remove(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    select("remove", args, false)
+}
+
+#[allow(dead_code)]
+pub fn reduce(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (reduce f coll) or (reduce f init coll)
+    let (f, init, coll) = match args {
+        [ref f, ref coll] => {
+            let xs = try_eval!(items(bindings, "reduce", coll));
+            match xs.head_opt() {
+                // (reduce f []) is (f)
+                None => return bindings.apply(f.clone(), ~[]),
+                Some(x) => (f.clone(), x.clone(), list_of(xs.slice_from(1).to_owned()))
+            }
+        },
+        [ref f, ref init, ref coll] => (f.clone(), init.clone(), coll.clone()),
+        _ => return eval_error(format!("reduce: wrong number of args ({:u})", args.len()))
+    };
+    let mut acc = init;
+    for x in try_eval!(items(bindings, "reduce", &coll)).move_iter() {
+        acc = try_eval!(bindings.apply(f.clone(), ~[acc, x]));
+    }
+    Ok(acc)
+}
+
+#[allow(dead_code)]
+pub fn some(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // the first truthy result of pred, or nil
+    match args {
+        [ref pred, ref coll] => {
+            // walk the coll, so an infinite seq can still stop early
+            let mut current = coll.clone();
+            loop {
+                let (x, rest) = match try_eval!(bindings.seq_step("some", &current)) {
+                    Some(cell) => cell,
+                    None => return Ok(nil)
+                };
+                let result = try_eval!(bindings.apply(pred.clone(), ~[x]));
+                if try_eval!(bindings.truthy("some", &result)) {
+                    return Ok(result);
+                }
+                current = rest;
+            }
+        },
+        _ => eval_error(format!("some: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn every(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref pred, ref coll] => {
+            let mut current = coll.clone();
+            loop {
+                let (x, rest) = match try_eval!(bindings.seq_step("every?", &current)) {
+                    Some(cell) => cell,
+                    None => return Ok(Boolean(true))
+                };
+                let result = try_eval!(bindings.apply(pred.clone(), ~[x]));
+                if !try_eval!(bindings.truthy("every?", &result)) {
+                    return Ok(Boolean(false));
+                }
+                current = rest;
+            }
+        },
+        _ => eval_error(format!("every?: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn group_by(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // a map from (f item) to a vector of the items with that result
+    match args {
+        [ref f, ref coll] => {
+            let mut groups = ElementMap::new();
+            for x in try_eval!(items(bindings, "group-by", coll)).move_iter() {
+                let key = try_eval!(bindings.apply(f.clone(), ~[x.clone()]));
+                let group = match groups.find(&key) {
+                    Some(&Vec(ref v)) => v.push(x),
+                    _ => PVec::new().push(x)
+                };
+                groups.insert(key, Vec(group));
+            }
+            Ok(Map(groups))
+        },
+        _ => eval_error(format!("group-by: wrong number of args ({:u})", args.len()))
+    }
+}
+
+
+/// runs the body of a lazy-seq with the locals it was created with
+struct Deferred {
+    form: Element,
+    scope: Rc<Env>
+}
+
+impl NativeFn for Deferred {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        let mut b = bindings.with_scope(self.scope.clone());
+        b.eval_elem(self.form.clone())
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Deferred { form: self.form.clone(), scope: self.scope.clone() } as ~NativeFn
+    }
+}
+
+/**
+ * @brief the thunk of a lazy-seq form
+ * @param form:     the body
+ * @param scope:    the locals the body can see
+ */
+pub fn deferred(form: Element, scope: Rc<Env>) -> Element
+{
+    NativeFunc::with_state(~Deferred { form: form, scope: scope } as ~NativeFn)
+}
+
+#[allow(dead_code)]
+pub fn first(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll] => match try_eval!(bindings.seq_step("first", coll)) {
+            Some((x, _)) => Ok(x),
+            None => Ok(nil)
+        },
+        _ => eval_error(format!("first: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn rest(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll] => match try_eval!(bindings.seq_step("rest", coll)) {
+            Some((_, rest)) => Ok(rest),
+            None => Ok(list_of(~[]))
+        },
+        _ => eval_error(format!("rest: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn next(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // like rest, but nil instead of an empty seq
+    match args {
+        [ref coll] => match try_eval!(bindings.seq_step("next", coll)) {
+            Some((_, rest)) => match try_eval!(bindings.seq_step("next", &rest)) {
+                Some(_) => Ok(rest),
+                None => Ok(nil)
+            },
+            None => Ok(nil)
+        },
+        _ => eval_error(format!("next: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn cons_seq(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        // don't realize a lazy tail
+        [ref x, ref coll] if is_lazy(coll) => lazy_cons(x.clone(), coll.clone()),
+        _ => primitive(cons, args)
+    }
+}
+
+/// the items of a vector from index on
+struct VecItems {
+    items: PVec,
+    index: uint
+}
+
+impl NativeFn for VecItems {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        match vec_step(&self.items, self.index) {
+            Some((x, rest)) => lazy_cons(x, rest),
+            None => Ok(nil)
+        }
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~VecItems { items: self.items.clone(), index: self.index } as ~NativeFn
+    }
+}
+
+/**
+ * @brief the item of a vector at index, and a seq of the ones after it
+ *
+ * The rest shares the vector instead of copying what is left of it,
+ * so stepping through a vector takes O(n) in all.
+ */
+pub fn vec_step(items: &PVec, index: uint) -> Option<(Element, Element)>
+{
+    items.get(index).map(|x| {
+        (x.clone(), lazy(~VecItems { items: items.clone(), index: index + 1 } as ~NativeFn))
+    })
+}
+
+/// the chars of a string from a byte offset on
+struct StrChars {
+    text: Rc<~str>,
+    at: uint
+}
+
+impl NativeFn for StrChars {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        match str_step(self.text.clone(), self.at) {
+            Some((c, rest)) => lazy_cons(c, rest),
+            None => Ok(nil)
+        }
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~StrChars { text: self.text.clone(), at: self.at } as ~NativeFn
+    }
+}
+
+/**
+ * @brief the char of a string at a byte offset, and a seq of the
+ *        ones after it
+ *
+ * Like vec_step, the rest shares the string.
+ */
+pub fn str_step(text: Rc<~str>, at: uint) -> Option<(Element, Element)>
+{
+    if at >= text.borrow().len() {
+        return None;
+    }
+    let range = text.borrow().char_range_at(at);
+    Some((Character(range.ch), lazy(~StrChars { text: text, at: range.next } as ~NativeFn)))
+}
+
+/**
+ * @brief step n items into a lazy seq
+ * @return the items stepped over and the rest of the seq
+ */
+fn walk(bindings: &mut Bindings, name: &str, coll: &Element, n: i64)
+    -> Result<(~[Element], Element), Exception>
+{
+    let mut taken: ~[Element] = ~[];
+    let mut current = coll.clone();
+    while (taken.len() as i64) < n {
+        match try_eval!(bindings.seq_step(name, &current)) {
+            Some((x, rest)) => {
+                taken.push(x);
+                current = rest;
+            },
+            None => break
+        }
+    }
+    Ok((taken, current))
+}
+
+#[allow(dead_code)]
+pub fn nth_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll, Number(i)] | [ref coll, Number(i), _] if is_lazy(coll) && i >= 0 => {
+            let (_, rest) = try_eval!(walk(bindings, "nth", coll, i));
+            match try_eval!(bindings.seq_step("nth", &rest)) {
+                Some((x, _)) => Ok(x),
+                None if args.len() == 3 => Ok(args[2].clone()),
+                None => eval_error(format!("nth: index {:d} out of range", i))
+            }
+        },
+        _ => primitive(nth, try_eval!(realize_args(bindings, args)))
+    }
+}
+
+/// the first n items of a coll
+struct Take {
+    n: i64,
+    coll: Element
+}
+
+impl NativeFn for Take {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        // the coll isn't stepped past the last item taken
+        if self.n <= 0 {
+            return Ok(nil);
+        }
+        match try_eval!(bindings.seq_step("take", &self.coll)) {
+            Some((x, rest)) => lazy_cons(x, lazy(~Take { n: self.n - 1, coll: rest } as ~NativeFn)),
+            None => Ok(nil)
+        }
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Take { n: self.n, coll: self.coll.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn take_seq(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Number(n), ref coll] => Ok(lazy(~Take { n: n, coll: coll.clone() } as ~NativeFn)),
+        _ => primitive(take, args)
+    }
+}
+
+#[allow(dead_code)]
+pub fn drop_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Number(n), ref coll] if is_lazy(coll) => {
+            let (_, rest) = try_eval!(walk(bindings, "drop", coll, n));
+            Ok(rest)
+        },
+        _ => primitive(dropfn, args)
+    }
+}
+
+
+/**
+ * @brief whether two values are equal, stepping through lazy seqs
+ *
+ * A lazy seq equals a list, vector or lazy seq with the same items,
+ * and is only realized as far as the first item that differs.
+ */
+fn seq_equal(bindings: &mut Bindings, a: &Element, b: &Element) -> Result<bool, Exception>
+{
+    if a == b {
+        return Ok(true);
+    }
+    match (a, b) {
+        (&Lazy(_), &Lazy(_)) | (&Lazy(_), &List(_)) | (&List(_), &Lazy(_)) => (),
+        (&Lazy(_), &Vec(_)) | (&Vec(_), &Lazy(_)) => (),
+        _ => return Ok(false)
+    }
+    let mut x = a.clone();
+    let mut y = b.clone();
+    loop {
+        let xs = try_eval!(bindings.seq_step("=", &x));
+        let ys = try_eval!(bindings.seq_step("=", &y));
+        match (xs, ys) {
+            (None, None) => return Ok(true),
+            (Some((fx, rx)), Some((fy, ry))) => {
+                if !try_eval!(seq_equal(bindings, &fx, &fy)) {
+                    return Ok(false);
+                }
+                x = rx;
+                y = ry;
+            },
+            _ => return Ok(false)
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub fn equal_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    if !args.iter().any(|a| is_lazy(a)) {
+        return primitive(equal, args);
+    }
+    for arg in args.slice_from(1).iter() {
+        if !try_eval!(seq_equal(bindings, &args[0], arg)) {
+            return Ok(Boolean(false));
+        }
+    }
+    Ok(Boolean(true))
+}
+
+#[allow(dead_code)]
+pub fn not_equal_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match try_eval!(equal_seq(bindings, args)) {
+        Boolean(b) => Ok(Boolean(!b)),
+        other => Ok(other)
+    }
+}
+
+#[allow(dead_code)]
+pub fn str_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // lazy seqs are printed, so *print-length* bounds how much is realized
+    let mut out = ~"";
+    for arg in args.iter() {
+        match *arg {
+            Lazy(_) => out.push_str(try_eval!(bindings.print_str(arg)).as_slice()),
+            _ => match try_eval!(primitive(strfn, [arg.clone()])) {
+                String(s) => out.push_str(s.as_slice()),
+                other => return Ok(other)
+            }
+        }
+    }
+    Ok(String(out))
+}
+
+/// the items of each coll in turn
+struct LazyConcat {
+    colls: ~[Element]
+}
+
+impl NativeFn for LazyConcat {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        // skip over empty colls in a loop, not by recursing
+        let mut i = 0;
+        while i < self.colls.len() {
+            match try_eval!(bindings.seq_step("concat", &self.colls[i])) {
+                Some((x, rest)) => {
+                    let mut colls = ~[rest];
+                    colls.push_all(self.colls.slice_from(i + 1));
+                    return lazy_cons(x, lazy(~LazyConcat { colls: colls } as ~NativeFn));
+                },
+                None => i += 1
+            }
+        }
+        Ok(nil)
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~LazyConcat { colls: self.colls.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn concat_seq(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    if args.iter().any(|a| is_lazy(a)) {
+        Ok(lazy(~LazyConcat { colls: args.to_owned() } as ~NativeFn))
+    } else {
+        primitive(concat, args)
+    }
+}
+
+#[allow(dead_code)]
+pub fn count_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll] if is_lazy(coll) => {
+            // step through rather than realizing it all at once
+            let mut n = 0;
+            let mut current = coll.clone();
+            loop {
+                match try_eval!(bindings.seq_step("count", &current)) {
+                    Some((_, rest)) => {
+                        n += 1;
+                        current = rest;
+                    },
+                    None => return Ok(Number(n))
+                }
+            }
+        },
+        _ => primitive(count, args)
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_empty_seq(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll] if is_lazy(coll) => {
+            Ok(Boolean(try_eval!(bindings.seq_step("empty?", coll)).is_none()))
+        },
+        _ => primitive(is_empty, args)
+    }
+}
+/**
+ * @brief realize any lazy seqs in args
+ */
+fn realize_args(bindings: &mut Bindings, args: &[Element]) -> Result<~[Element], Exception>
+{
+    let mut out: ~[Element] = ~[];
+    for arg in args.iter() {
+        out.push(try_eval!(bindings.realize_all(arg.clone())));
+    }
+    Ok(out)
+}
+
+/// the unbounded range start, start + step, ...
+struct Range {
+    start: i64,
+    step: i64
+}
+
+impl NativeFn for Range {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        lazy_cons(Number(self.start), lazy(~Range {
+            start: self.start + self.step,
+            step: self.step
+        } as ~NativeFn))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Range { start: self.start, step: self.step } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn range_seq(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (range) counts up forever
+    match args {
+        [] => Ok(lazy(~Range { start: 0, step: 1 } as ~NativeFn)),
+        _ => primitive(rangefn, args)
+    }
+}
+
+/// x, (f x), (f (f x)), ...
+struct Iterate {
+    f: Element,
+    x: Element,
+    step: bool
+}
+
+impl NativeFn for Iterate {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        let x = if self.step {
+            try_eval!(bindings.apply(self.f.clone(), ~[self.x.clone()]))
+        } else {
+            self.x.clone()
+        };
+        lazy_cons(x.clone(), lazy(~Iterate {
+            f: self.f.clone(),
+            x: x,
+            step: true
+        } as ~NativeFn))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Iterate {
+            f: self.f.clone(),
+            x: self.x.clone(),
+            step: self.step
+        } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn iterate(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref f, ref x] => Ok(lazy(~Iterate {
+            f: f.clone(),
+            x: x.clone(),
+            step: false
+        } as ~NativeFn)),
+        _ => eval_error(format!("iterate: wrong number of args ({:u})", args.len()))
+    }
+}
+
+/// x forever
+struct Repeat {
+    x: Element
+}
+
+impl NativeFn for Repeat {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        lazy_cons(self.x.clone(), lazy(~Repeat { x: self.x.clone() } as ~NativeFn))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Repeat { x: self.x.clone() } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn repeat(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (repeat x) is infinite, (repeat n x) is n long
+    match args {
+        [ref x] => Ok(lazy(~Repeat { x: x.clone() } as ~NativeFn)),
+        [Number(n), ref x] => Ok(List(range(0, n).map(|_| x.clone()).collect())),
+        [_, _] => eval_error(~"repeat: count must be a number"),
+        _ => eval_error(format!("repeat: wrong number of args ({:u})", args.len()))
+    }
+}
+
+/// the items of a coll, over and over
+struct Cycle {
+    // shared by every cell, so each step doesn't copy them
+    items: Rc<~[Element]>,
+    index: uint
+}
+
+impl NativeFn for Cycle {
+    fn call(&self, _: &mut Bindings, _: &[Element]) -> EvalResult {
+        let items = self.items.borrow();
+        lazy_cons(items[self.index].clone(), lazy(~Cycle {
+            items: self.items.clone(),
+            index: (self.index + 1) % items.len()
+        } as ~NativeFn))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~Cycle { items: self.items.clone(), index: self.index } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn cycle(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref coll] => {
+            let items = try_eval!(items(bindings, "cycle", coll));
+            if items.len() == 0 {
+                Ok(list_of(~[]))
+            } else {
+                Ok(lazy(~Cycle { items: Rc::new(items), index: 0 } as ~NativeFn))
+            }
+        },
+        _ => eval_error(format!("cycle: wrong number of args ({:u})", args.len()))
+    }
+}
+
+/// f called on the first items of each coll, then the next, ...
+struct LazyMap {
+    f: Element,
+    colls: ~[Element]
+}
+
+impl NativeFn for LazyMap {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        let mut firsts: ~[Element] = ~[];
+        let mut rests: ~[Element] = ~[];
+        for coll in self.colls.iter() {
+            match try_eval!(bindings.seq_step("map", coll)) {
+                Some((x, rest)) => {
+                    firsts.push(x);
+                    rests.push(rest);
+                },
+                None => return Ok(nil)
+            }
+        }
+        let y = try_eval!(bindings.apply(self.f.clone(), firsts));
+        lazy_cons(y, lazy(~LazyMap {
+            f: self.f.clone(),
+            colls: rests
+        } as ~NativeFn))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~LazyMap {
+            f: self.f.clone(),
+            colls: self.colls.clone()
+        } as ~NativeFn
+    }
+}
+
+/// the items of a coll that pred does (or doesn't) pass
+struct LazyFilter {
+    name: ~str,
+    pred: Element,
+    coll: Element,
+    keep: bool
+}
+
+impl NativeFn for LazyFilter {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        // skip over failing items in a loop, not by recursing
+        let mut current = self.coll.clone();
+        loop {
+            let (x, rest) = match try_eval!(bindings.seq_step(self.name, &current)) {
+                Some(cell) => cell,
+                None => return Ok(nil)
+            };
+            let result = try_eval!(bindings.apply(self.pred.clone(), ~[x.clone()]));
+            if try_eval!(bindings.truthy(self.name, &result)) == self.keep {
+                return lazy_cons(x, lazy(~LazyFilter {
+                    name: self.name.clone(),
+                    pred: self.pred.clone(),
+                    coll: rest,
+                    keep: self.keep
+                } as ~NativeFn));
+            }
+            current = rest;
+        }
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~LazyFilter {
+            name: self.name.clone(),
+            pred: self.pred.clone(),
+            coll: self.coll.clone(),
+            keep: self.keep
+        } as ~NativeFn
+    }
+}
+
+/// the items of a coll up to the first that fails pred
+struct TakeWhile {
+    pred: Element,
+    coll: Element
+}
+
+impl NativeFn for TakeWhile {
+    fn call(&self, bindings: &mut Bindings, _: &[Element]) -> EvalResult {
+        let (x, rest) = match try_eval!(bindings.seq_step("take-while", &self.coll)) {
+            Some(cell) => cell,
+            None => return Ok(nil)
+        };
+        let result = try_eval!(bindings.apply(self.pred.clone(), ~[x.clone()]));
+        if try_eval!(bindings.truthy("take-while", &result)) {
+            lazy_cons(x, lazy(~TakeWhile {
+                pred: self.pred.clone(),
+                coll: rest
+            } as ~NativeFn))
+        } else {
+            Ok(nil)
+        }
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~TakeWhile {
+            pred: self.pred.clone(),
+            coll: self.coll.clone()
+        } as ~NativeFn
+    }
+}
+
+#[allow(dead_code)]
+pub fn take_while(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref pred, ref coll] => {
+            Ok(lazy(~TakeWhile {
+                pred: pred.clone(),
+                coll: coll.clone()
+            } as ~NativeFn))
+        },
+        _ => eval_error(format!("take-while: wrong number of args ({:u})", args.len()))
+    }
+}
+
+
+/**
+ * @brief check a new value against an atom's validator
+ */
+fn validate(bindings: &mut Bindings, validator: &Option<Element>, value: &Element)
+    -> Result<(), Exception>
+{
+    match *validator {
+        Some(ref f) => {
+            let valid = try_eval!(bindings.apply(f.clone(), ~[value.clone()]));
+            if !try_eval!(bindings.truthy("validator", &valid)) {
+                return eval_error(~"Invalid reference state");
+            }
+            Ok(())
+        },
+        None => Ok(())
+    }
+}
+
+/**
+ * @brief change an atom's value, then call its watches
+ */
+fn set_atom(bindings: &mut Bindings, a: &AtomRef, value: Element) -> EvalResult
+{
+    try_eval!(validate(bindings, &a.validator(), &value));
+    let old = a.value();
+    a.set_value(value.clone());
+    for (key, f) in a.watches().move_iter() {
+        try_eval!(bindings.apply(f, ~[key, Atom(a.clone()), old.clone(), value.clone()]));
+    }
+    Ok(value)
+}
+
+#[allow(dead_code)]
+pub fn atom(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (atom x) or (atom x :validator f)
+    let validator = match args {
+        [_] => None,
+        [_, Keyword(ref k), ref f] if *k == ~"validator" => Some(f.clone()),
+        [_, _, _] => return eval_error(~"atom: unknown option"),
+        _ => return eval_error(format!("atom: wrong number of args ({:u})", args.len()))
+    };
+    try_eval!(validate(bindings, &validator, &args[0]));
+    let a = AtomRef::new(args[0].clone());
+    a.set_validator(validator);
+    Ok(Atom(a))
+}
+
+#[allow(dead_code)]
+pub fn deref(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Atom(ref a)] => Ok(a.value()),
+        [_] => eval_error(~"deref: expected an atom"),
+        _ => eval_error(format!("deref: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn reset(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Atom(ref a), ref value] => set_atom(bindings, a, value.clone()),
+        [_, _] => eval_error(~"reset!: expected an atom"),
+        _ => eval_error(format!("reset!: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn swap(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (swap! a f args...) sets a to (f @a args...)
+    if args.len() < 2 {
+        return eval_error(format!("swap!: wrong number of args ({:u})", args.len()));
+    }
+    match args[0] {
+        Atom(ref a) => {
+            let value = try_eval!(bindings.apply(args[1].clone(), ~[a.value()] + args.slice_from(2)));
+            set_atom(bindings, a, value)
+        },
+        _ => eval_error(~"swap!: expected an atom")
+    }
+}
+
+#[allow(dead_code)]
+pub fn compare_and_set(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // only set the atom if it still holds the old value
+    match args {
+        [Atom(ref a), ref old, ref new] => {
+            if a.value() != *old {
+                return Ok(Boolean(false));
+            }
+            try_eval!(set_atom(bindings, a, new.clone()));
+            Ok(Boolean(true))
+        },
+        [_, _, _] => eval_error(~"compare-and-set!: expected an atom"),
+        _ => eval_error(format!("compare-and-set!: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn set_validator(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // a nil validator removes it
+    match args {
+        [Atom(ref a), ref f] => {
+            let validator = match *f {
+                nil => None,
+                _ => Some(f.clone())
+            };
+            try_eval!(validate(bindings, &validator, &a.value()));
+            a.set_validator(validator);
+            Ok(nil)
+        },
+        [_, _] => eval_error(~"set-validator!: expected an atom"),
+        _ => eval_error(format!("set-validator!: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn add_watch(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // f is called with the key, the atom, the old value and the new one
+    match args {
+        [Atom(ref a), ref key, ref f] => {
+            a.add_watch(key.clone(), f.clone());
+            Ok(Atom(a.clone()))
+        },
+        [_, _, _] => eval_error(~"add-watch: expected an atom"),
+        _ => eval_error(format!("add-watch: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn remove_watch(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Atom(ref a), ref key] => {
+            a.remove_watch(key);
+            Ok(Atom(a.clone()))
+        },
+        [_, _] => eval_error(~"remove-watch: expected an atom"),
+        _ => eval_error(format!("remove-watch: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn in_ns(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Symbol(ref name)] => {
+            bindings.in_ns(name.as_slice());
+            Ok(nil)
+        },
+        [_] => eval_error(~"in-ns: expected a symbol"),
+        _ => eval_error(format!("in-ns: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn require(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // each arg is a lib, or a [lib :as alias :refer [names...]] spec
+    for spec in args.iter() {
+        try_eval!(bindings.require(spec));
+    }
+    Ok(nil)
+}
+
+
+#[test]
+fn test_eval_apply() {
+    assert!(::eval::eval("(eval '(+ 1 2))") == ::types::Number(3));
+    assert!(::eval::eval("(apply + 1 2 [3 4])") == ::types::Number(10));
+    assert!(::eval::eval("(apply apply + [[1 2]])") == ::types::Number(3));
+    assert!(::eval::eval("(apply (fn [f x] (f x)) [inc 1])") == ::types::Number(2));
+}
+
+#[test]
+fn test_native_calls_back() {
+    // natives can be passed around and called like any other function
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn twice [f x] (f (f x)))");
+    assert!(bindings.eval("(twice (partial + 10) 1)") == Ok(::types::Number(21)));
+    assert!(bindings.eval("((partial apply +) [1 2])") == Ok(::types::Number(3)));
+    assert!(bindings.eval("(macroexpand (read-string \"(not x)\"))")
+            == Ok(::eval::eval("'(if x false true)")));
+    // errors from the called function propagate
+    assert!(bindings.eval("((partial inc) 1 2)")
+            == Err(::types::EvalError(~"wrong number of args (2) passed to fn")));
+}
+
+#[test]
+fn test_partial_comp_constantly() {
+    assert!(::eval::eval("((partial +) 1)") == ::types::Number(1));
+    assert!(::eval::eval("((partial + 1 2) 3 4)") == ::types::Number(10));
+    assert!(::eval::eval("((comp inc inc) 1)") == ::types::Number(3));
+    assert!(::eval::eval("((comp (fn [x] (* x 2)) +) 1 2)") == ::types::Number(6));
+    assert!(::eval::eval("((comp) 5)") == ::types::Number(5));
+    assert!(::eval::eval("((constantly 7) 1 2 3)") == ::types::Number(7));
+    // the captured state survives being looked up and copied
+    let mut bindings = Bindings::new();
+    bindings.eval("(def add5 (partial + 5))");
+    assert!(bindings.eval("(add5 1)") == Ok(::types::Number(6)));
+    assert!(bindings.eval("(= add5 add5)") == Ok(::types::Boolean(true)));
+    assert!(bindings.eval("(let [f add5] (f 2))") == Ok(::types::Number(7)));
+}
+
+#[test]
+fn test_map_filter_remove() {
+    assert!(::eval::eval("(map inc [1 2])") == list_of(~[::types::Number(2), ::types::Number(3)]));
+    assert!(::eval::eval("(map + [1 2 3] '(10 20))") == list_of(~[::types::Number(11),
+                                                            ::types::Number(22)]));
+    assert!(::eval::eval("(map (fn [c] c) \"ab\")") == list_of(~[::types::Character('a'),
+                                                             ::types::Character('b')]));
+    assert!(::eval::eval("(filter (fn [x] (= x 1)) [1 2 1])") == list_of(~[::types::Number(1),
+                                                                     ::types::Number(1)]));
+    assert!(::eval::eval("(remove (fn [x] (= x 1)) [1 2 1])") == list_of(~[::types::Number(2)]));
+    assert!(::eval::eval("(filter nil? [1 nil])") == list_of(~[nil]));
+    assert!(::eval::eval("(map inc 5)") == ::types::EvalError(~"map: expected a collection"));
+}
+
+#[test]
+fn test_reduce() {
+    assert!(::eval::eval("(reduce + [1 2 3])") == ::types::Number(6));
+    assert!(::eval::eval("(reduce + 10 [1 2 3])") == ::types::Number(16));
+    assert!(::eval::eval("(reduce + [])") == ::types::Number(0));
+    assert!(::eval::eval("(reduce conj [] '(1 2))") == ::types::vec_of(~[::types::Number(1),
+                                                          ::types::Number(2)]));
+    assert!(::eval::eval("(reduce (fn [a b] (throw :stop)) [1 2])") == ::types::Keyword(~"stop"));
+}
+
+#[test]
+fn test_some_every() {
+    assert!(::eval::eval("(some (fn [x] (get {:a 1} x)) [:b :a])") == ::types::Number(1));
+    assert!(::eval::eval("(some nil? [1 2])") == nil);
+    assert!(::eval::eval("(every? some? [1 2])") == Boolean(true));
+    assert!(::eval::eval("(every? some? [1 nil])") == Boolean(false));
+    assert!(::eval::eval("(every? nil? [])") == Boolean(true));
+}
+
+#[test]
+fn test_group_by() {
+    assert!(::eval::eval("(= (group-by count [\"a\" \"bc\" \"d\"]) \
+                          {1 [\"a\" \"d\"] 2 [\"bc\"]})") == Boolean(true));
+    assert!(::eval::eval("(group-by inc [])") == Map(ElementMap::new()));
+}
+
+#[test]
+fn test_lazy_seq() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn ints [n] (lazy-seq (cons n (ints (inc n)))))");
+    assert!(bindings.eval("(= (take 3 (ints 5)) '(5 6 7))") == Ok(Boolean(true)));
+    assert!(bindings.eval("(first (lazy-seq nil))") == Ok(nil));
+    assert!(bindings.eval("(count (lazy-seq [1 2]))") == Ok(Number(2)));
+    assert!(bindings.eval("(= (lazy-seq '(1 2)) '(1 2))") == Ok(Boolean(true)));
+    // the body runs when the seq is first needed
+    bindings.eval("(def s (lazy-seq (throw :realized)))");
+    assert!(bindings.eval("(first s)") == Err(::types::Keyword(~"realized")));
+    // and every copy of the seq sees the realized value
+    bindings.eval("(def t (lazy-seq [1]))");
+    assert!(bindings.eval("t").unwrap().to_str() == ~"(...)");
+    bindings.eval("(first t)");
+    assert!(bindings.eval("t").unwrap().to_str() == ~"(1)");
+    // locals are captured
+    assert!(bindings.eval("(first (let [x 2] (lazy-seq [x])))") == Ok(Number(2)));
+}
+
+#[test]
+fn test_infinite_seqs() {
+    assert!(::eval::eval("(take 5 (iterate inc 0))") == list_of(~[Number(0), Number(1), Number(2),
+                                                               Number(3), Number(4)]));
+    assert!(::eval::eval("(take 2 (repeat :x))") == list_of(~[::types::Keyword(~"x"),
+                                                          ::types::Keyword(~"x")]));
+    assert!(::eval::eval("(repeat 2 1)") == list_of(~[Number(1), Number(1)]));
+    assert!(::eval::eval("(take 5 (cycle [1 2]))") == list_of(~[Number(1), Number(2), Number(1),
+                                                            Number(2), Number(1)]));
+    // cycling a long coll doesn't copy it for each item
+    assert!(::eval::eval("(nth (cycle (range 5000)) 10001)") == Number(1));
+    assert!(::eval::eval("(nth (range) 10)") == Number(10));
+    assert!(::eval::eval("(first (drop 3 (range)))") == Number(3));
+    assert!(::eval::eval("(some (fn [x] (= x 7)) (range))") == Boolean(true));
+    assert!(::eval::eval("(every? (fn [x] (= x 0)) (range))") == Boolean(false));
+    // builtins that take seqs only step as far as they need to
+    assert!(::eval::eval("(= (range) [1])") == Boolean(false));
+    assert!(::eval::eval("(= (range) '(0 1))") == Boolean(false));
+    assert!(::eval::eval("(= [1 2] (lazy-seq '(1 2)))") == Boolean(true));
+    assert!(::eval::eval("(= (map inc (lazy-seq [0 1])) [1 2])") == Boolean(true));
+    assert!(::eval::eval("(not= (iterate inc 0) '(1))") == Boolean(true));
+    assert!(::eval::eval("(= (take 2 (range)) (map inc '(-1 0)) '(0 1))") == Boolean(true));
+    assert!(::eval::eval("(take 3 (concat [1] (iterate inc 0)))")
+            == list_of(~[Number(1), Number(0), Number(1)]));
+    assert!(::eval::eval("(= (concat [] (map inc [1 2]) '(4)) '(2 3 4))") == Boolean(true));
+    assert!(::eval::eval("(count (map inc (range 3000)))") == Number(3000));
+    // long lists and seqs are freed without overflowing the stack
+    assert!(::eval::eval("(count (range 1000000))") == Number(1000000));
+    assert!(::eval::eval("(nth (map inc (range)) 1000000)") == Number(1000001));
+    assert!(::eval::eval("[(empty? (range)) (empty? (filter neg? [1]))]")
+            == ::types::vec_of(~[Boolean(false), Boolean(true)]));
+    let mut bindings = Bindings::new();
+    bindings.eval("(def *print-length* 3)");
+    assert!(bindings.eval("(str \"n: \" (range))") == Ok(String(~"n: (0 1 2 ...)")));
+}
+
+#[test]
+fn test_lazy_map_filter() {
+    assert!(::eval::eval("(take 3 (map inc (range)))")
+            == list_of(~[Number(1), Number(2), Number(3)]));
+    assert!(::eval::eval("(take 2 (map + (range) [10 20 30]))")
+            == list_of(~[Number(10), Number(21)]));
+    assert!(::eval::eval("(take 2 (filter (fn [x] (= (% x 3) 0)) (iterate inc 1)))")
+            == list_of(~[Number(3), Number(6)]));
+    assert!(::eval::eval("(take 2 (remove (fn [x] (= (% x 3) 0)) (iterate inc 1)))")
+            == list_of(~[Number(1), Number(2)]));
+    assert!(::eval::eval("(reduce + (take-while (fn [x] (= (% x 10) x)) (range)))") == Number(45));
+    assert!(::eval::eval("(take-while (fn [x] (= x 1)) [1 1 2 1])") == list_of(~[Number(1),
+                                                                           Number(1)]));
+    // the fns still see the locals they were made with when the seq
+    // is realized somewhere else
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn scaled [k] (map (fn [x] (* k x)) (range)))");
+    bindings.eval("(defn multiples [k] (filter (fn [x] (= (% x k) 0)) (range)))");
+    bindings.eval("(defn below [k] (take-while (fn [x] (< x k)) (range)))");
+    bindings.eval("(defn powers [k] (iterate (fn [x] (* k x)) 1))");
+    assert!(bindings.eval("(= (take 3 (scaled 2)) '(0 2 4))") == Ok(Boolean(true)));
+    assert!(bindings.eval("(= (take 3 (multiples 3)) '(0 3 6))") == Ok(Boolean(true)));
+    assert!(bindings.eval("(count (below 4))") == Ok(Number(4)));
+    assert!(bindings.eval("(= (take 3 (powers 3)) '(1 3 9))") == Ok(Boolean(true)));
+    // nothing is called until the seq is used, and then only as far
+    // as it is used, even over a vector
+    bindings.eval("(def calls (atom 0))");
+    bindings.eval("(defn counted [x] (swap! calls inc) x)");
+    bindings.eval("(def s (map counted [1 2 3]))");
+    assert!(bindings.eval("(deref calls)") == Ok(Number(0)));
+    assert!(bindings.eval("(first s)") == Ok(Number(1)));
+    assert!(bindings.eval("(deref calls)") == Ok(Number(1)));
+    bindings.eval("(reset! calls 0)");
+    bindings.eval("(first (filter counted [1 2 3]))");
+    bindings.eval("(first (remove counted [nil 2 3]))");
+    bindings.eval("(first (take-while counted [1 2 3]))");
+    bindings.eval("(first (take 2 (map counted [1 2 3])))");
+    assert!(bindings.eval("(deref calls)") == Ok(Number(4)));
+    assert!(bindings.eval("(= (map inc [0 1]) [1 2])") == Ok(Boolean(true)));
+}
+
+#[test]
+fn test_long_lazy_chains() {
+    // realizing and walking long seqs stays off the Rust stack
+    assert!(::eval::eval("(nth (iterate inc 0) 10000)") == Number(10000));
+    assert!(::eval::eval("(first (filter (fn [x] (= x 10000)) (range)))") == Number(10000));
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn nest [n s] (if (= n 0) s (lazy-seq (nest (dec n) s))))");
+    assert!(bindings.eval("(first (nest 100000 [1]))") == Ok(Number(1)));
+    // stepping through a vector or string doesn't copy what's left of it
+    bindings.eval("(defn walk [s n] (if (empty? s) n (recur (rest s) (inc n))))");
+    assert!(bindings.eval("(walk (reduce conj [] (range 100000)) 0)") == Ok(Number(100000)));
+    assert!(bindings.eval("(walk (apply str (repeat 100000 \"é\")) 0)") == Ok(Number(100000)));
+    assert!(bindings.eval("(str (first (rest \"añb\")) (nth (rest [1 2 3]) 1))")
+            == Ok(String(~"ñ3")));
+}
+
+#[test]
+fn test_print_length() {
+    let mut bindings = Bindings::new();
+    let seq = bindings.eval("(iterate inc 0)").unwrap();
+    // only what's realized is printed
+    assert!(seq.to_str() == ~"(...)");
+    bindings.eval("(def *print-length* 3)");
+    assert!(bindings.print_str(&seq) == Ok(~"(0 1 2 ...)"));
+    assert!(seq.to_str() == ~"(0 1 2 3 ...)");
+    assert!(bindings.print_str(&list_of(~[Number(1), Number(2)])) == Ok(~"(1 2)"));
+    assert!(bindings.print_str(&::types::vec_of(~[Number(1), Number(2), Number(3), Number(4)]))
+            == Ok(~"[1 2 3 ...]"));
+    bindings.eval("(def *print-length* nil)");
+    let seq = bindings.eval("(take 2 (range))").unwrap();
+    assert!(bindings.print_str(&seq) == Ok(~"(0 1)"));
+}
+
+#[test]
+fn test_atom() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def a (atom 1))");
+    assert!(bindings.eval("(deref a)") == Ok(Number(1)));
+    assert!(bindings.eval("@a") == Ok(Number(1)));
+    assert!(bindings.eval("(reset! a 5)") == Ok(Number(5)));
+    assert!(bindings.eval("(swap! a inc)") == Ok(Number(6)));
+    assert!(bindings.eval("(swap! a + 1 2)") == Ok(Number(9)));
+    assert!(bindings.eval("@a") == Ok(Number(9)));
+    // changes are seen through every reference, including from fns
+    bindings.eval("(defn bump [x] (swap! x inc))");
+    bindings.eval("(let [b a] (bump b))");
+    assert!(bindings.eval("@a") == Ok(Number(10)));
+    assert!(bindings.eval("(compare-and-set! a 1 2)") == Ok(Boolean(false)));
+    assert!(bindings.eval("(compare-and-set! a 10 2)") == Ok(Boolean(true)));
+    assert!(bindings.eval("@a") == Ok(Number(2)));
+    assert!(bindings.eval("(deref 1)") == Err(EvalError(~"deref: expected an atom")));
+}
+
+#[test]
+fn test_atom_identity() {
+    assert!(::eval::eval("(= (atom 1) (atom 1))") == Boolean(false));
+    assert!(::eval::eval("(let [a (atom 1)] (= a a))") == Boolean(true));
+    assert!(::eval::eval("(atom [1])").to_str() == ~"#atom[[1]]");
+}
+
+#[test]
+fn test_atom_validator() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def a (atom 1 :validator (fn [x] (= x (% x 10)))))");
+    assert!(bindings.eval("(reset! a 11)") == Err(EvalError(~"Invalid reference state")));
+    assert!(bindings.eval("@a") == Ok(Number(1)));
+    assert!(bindings.eval("(swap! a + 5)") == Ok(Number(6)));
+    assert!(bindings.eval("(atom 11 :validator (fn [x] (= x 1)))")
+            == Err(EvalError(~"Invalid reference state")));
+    assert!(bindings.eval("(set-validator! a nil)") == Ok(nil));
+    assert!(bindings.eval("(reset! a 11)") == Ok(Number(11)));
+    assert!(bindings.eval("(set-validator! a (fn [x] (= x 1)))")
+            == Err(EvalError(~"Invalid reference state")));
+}
+
+#[test]
+fn test_atom_watches() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def a (atom 1))");
+    bindings.eval("(def log (atom []))");
+    bindings.eval("(add-watch a :log (fn [k r old new] (swap! log conj [k old new])))");
+    bindings.eval("(reset! a 2)");
+    bindings.eval("(swap! a inc)");
+    assert!(bindings.eval("@log") == Ok(::eval::eval("[[:log 1 2] [:log 2 3]]")));
+    bindings.eval("(remove-watch a :log)");
+    bindings.eval("(reset! a 4)");
+    assert!(bindings.eval("(count @log)") == Ok(Number(2)));
+}
+
+// all of the above, on the vm
+compiled_suite!(test_eval_apply, test_native_calls_back, test_partial_comp_constantly,
+               test_map_filter_remove, test_reduce, test_some_every, test_group_by, test_lazy_seq,
+               test_infinite_seqs, test_lazy_map_filter, test_long_lazy_chains, test_print_length,
+               test_atom, test_atom_identity, test_atom_validator, test_atom_watches)
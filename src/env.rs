@@ -0,0 +1,216 @@
+/**
+ * @file env.rs
+ * @brief environment frames, which hold the names bound in one scope
+ *
+ * Each frame points to the frame it is nested in, and the outermost
+ * frame holds the toplevel. Frames are reference counted, so entering
+ * a scope only makes one new, empty frame, however much is defined
+ * further out.
+ *
+ * Values are kept in numbered slots, in the order they were bound, so
+ * a local whose frame and slot are known ahead of time can be read
+ * without looking its name up.
+ */
+use std::hashmap::HashMap;
+use std::borrow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use types::Element;
+
+use symbol::Sym;
+
+mod types;
+
+/**
+ * @brief one scope's bindings, and the scope it is nested in
+ *
+ * Clones of the Rc share the frame, so anything that keeps a frame
+ * (like a lazy-seq) sees later inserts into it. Binding a name again
+ * in a local frame uses a new slot, so the old value is still there
+ * for code that was resolved to it.
+ */
+pub struct Env {
+    priv slots: RefCell<~[(Sym, Element)]>,
+    // the slot of each name, for frames too big to search, like the
+    // toplevel; names in these frames are only ever given one slot
+    priv index: Option<RefCell<HashMap<Sym, uint>>>,
+    priv parent: Option<Rc<Env>>
+}
+
+impl Env {
+    /**
+     * @brief an empty frame, nested in parent if there is one
+     */
+    pub fn new(parent: Option<Rc<Env>>) -> Rc<Env> {
+        Rc::new(Env { slots: RefCell::new(~[]), index: None, parent: parent })
+    }
+    /**
+     * @brief an empty frame nested in parent
+     */
+    pub fn nested(parent: &Rc<Env>) -> Rc<Env> {
+        Env::new(Some(parent.clone()))
+    }
+    /**
+     * @brief an empty outermost frame, which finds names by hashing
+     *        them rather than searching its slots
+     */
+    pub fn indexed() -> Rc<Env> {
+        Rc::new(Env {
+            slots: RefCell::new(~[]),
+            index: Some(RefCell::new(HashMap::new())),
+            parent: None
+        })
+    }
+    pub fn parent<'a>(&'a self) -> Option<&'a Rc<Env>> {
+        self.parent.as_ref()
+    }
+    /**
+     * @brief bind key in this frame
+     * @return true if key wasn't bound in this frame before
+     */
+    pub fn insert(&self, key: Sym, value: Element) -> bool {
+        let mut slots = self.slots.borrow_mut();
+        let slots = slots.get();
+        match self.index {
+            Some(ref index) => {
+                let mut index = index.borrow_mut();
+                let index = index.get();
+                match index.find(&key) {
+                    Some(&i) => {
+                        slots[i] = (key, value);
+                        return false;
+                    },
+                    None => ()
+                }
+                index.insert(key, slots.len());
+                slots.push((key, value));
+                true
+            },
+            None => {
+                let fresh = !slots.iter().any(|&(k, _)| k == key);
+                slots.push((key, value));
+                fresh
+            }
+        }
+    }
+    /**
+     * @brief the slot key was last bound in, in this frame
+     */
+    fn slot_of(&self, key: Sym) -> Option<uint> {
+        match self.index {
+            Some(ref index) => index.with(|index| index.find(&key).map(|i| *i)),
+            None => self.slots.with(|slots| slots.iter().rposition(|&(k, _)| k == key))
+        }
+    }
+    /**
+     * @brief what key is bound to in this frame, ignoring its parents
+     */
+    pub fn find(&self, key: Sym) -> Option<Element> {
+        self.slot_of(key).map(|i| self.value_at(i))
+    }
+    pub fn contains_key(&self, key: Sym) -> bool {
+        self.slot_of(key).is_some()
+    }
+    /**
+     * @brief the names bound in this frame, ignoring its parents
+     */
+    pub fn keys(&self) -> ~[Sym] {
+        self.slots.with(|slots| slots.iter().map(|&(k, _)| k).collect())
+    }
+    /**
+     * @brief the innermost binding of key, in this frame or the ones
+     *        it is nested in
+     */
+    pub fn lookup(&self, key: Sym) -> Option<Element> {
+        let mut frame = self;
+        loop {
+            match frame.find(key) {
+                Some(value) => return Some(value),
+                None => ()
+            }
+            frame = match frame.parent {
+                Some(ref parent) => parent.borrow(),
+                None => return None
+            };
+        }
+    }
+    /**
+     * @brief the value in a slot of a frame this one is nested in
+     * @param key:      the name the slot should hold
+     * @param depth:    how many frames out to go; 0 is this frame
+     * @param slot:     the slot in that frame
+     * @return None if there is no such slot, or it holds another name
+     */
+    pub fn get(&self, key: Sym, depth: uint, slot: uint) -> Option<Element> {
+        let mut frame = self;
+        for _ in range(0, depth) {
+            frame = match frame.parent {
+                Some(ref parent) => parent.borrow(),
+                None => return None
+            };
+        }
+        frame.slots.with(|slots| {
+            match slots.get_opt(slot) {
+                Some(&(k, ref value)) if k == key => Some(value.clone()),
+                _ => None
+            }
+        })
+    }
+    fn value_at(&self, slot: uint) -> Element {
+        self.slots.with(|slots| {
+            let (_, ref value) = slots[slot];
+            value.clone()
+        })
+    }
+}
+
+impl Eq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        // a frame is only equal to itself, like an atom
+        borrow::ref_eq(self, other)
+    }
+}
+
+
+#[test]
+fn test_env() {
+    let (a, b, c) = (::symbol::intern("a"), ::symbol::intern("b"), ::symbol::intern("c"));
+    let root = Env::indexed();
+    root.borrow().insert(a, ::types::Number(1));
+    root.borrow().insert(b, ::types::Number(2));
+    let inner = Env::nested(&root);
+    inner.borrow().insert(b, ::types::Number(3));
+    assert!(inner.borrow().lookup(a) == Some(::types::Number(1)));
+    assert!(inner.borrow().lookup(b) == Some(::types::Number(3)));
+    assert!(inner.borrow().find(a) == None);
+    assert!(root.borrow().lookup(b) == Some(::types::Number(2)));
+    assert!(inner.borrow().lookup(c) == None);
+    // frames nested in the same parent see what's added to it
+    let other = Env::nested(&root);
+    assert!(root.borrow().insert(c, ::types::Number(4)));
+    assert!(inner.borrow().lookup(c) == Some(::types::Number(4)));
+    assert!(other.borrow().lookup(c) == Some(::types::Number(4)));
+    // the toplevel replaces a name's value in place
+    assert!(!root.borrow().insert(a, ::types::Number(5)));
+    assert!(root.borrow().keys() == ~[a, b, c]);
+    assert!(root.borrow().get(a, 0, 0) == Some(::types::Number(5)));
+}
+
+#[test]
+fn test_env_slots() {
+    let (x, y) = (::symbol::intern("x"), ::symbol::intern("y"));
+    let outer = Env::new(None);
+    outer.borrow().insert(x, ::types::Number(1));
+    let inner = Env::nested(&outer);
+    inner.borrow().insert(y, ::types::Number(2));
+    // binding a name again in a local frame gives it a new slot
+    assert!(!inner.borrow().insert(y, ::types::Number(3)));
+    assert!(inner.borrow().find(y) == Some(::types::Number(3)));
+    assert!(inner.borrow().get(y, 0, 0) == Some(::types::Number(2)));
+    assert!(inner.borrow().get(y, 0, 1) == Some(::types::Number(3)));
+    assert!(inner.borrow().get(x, 1, 0) == Some(::types::Number(1)));
+    assert!(inner.borrow().get(y, 1, 0) == None);
+    assert!(inner.borrow().get(x, 1, 1) == None);
+    assert!(inner.borrow().get(x, 2, 0) == None);
+}
@@ -2,29 +2,222 @@
  * @file
  * @brief Evaluation module
  */
-use std::hashmap::HashMap;
+use std::hashmap::{HashMap, HashSet};
 use std::iter::Iterator;
+use std::cell::RefCell;
+use std::rc::Rc;
+#[cfg(test)]
+use std::local_data;
 
 use tokenizer::tokenize;
 
 use types::Element;
 use types::{Symbol, Boolean, nil};
-use types::{List, Vec};
-use types::{Function, FuncPrimitive};
+use types::{List, Vec, Map, ElementMap, Keyword, Record};
+use types::{Function, FuncPrimitive, FuncNative};
+use types::{Lazy, LazySeq, Number, String, Local, Interned, Multi, MultiRef};
 use types::EvalError;
+use types::{list_of, vec_of};
 
-use primitives::{add, sub, mul, div, modfn, equal, concat};
+use primitives::{add, sub, mul, div, modfn};
+use primitives::{compare, less, greater, less_equal, greater_equal};
+use primitives::{get, assoc, hash_map, ex_info, ex_data, ex_message};
+use primitives::{boolean, is_true, is_false, is_nil, is_some};
+use primitives::seq_items;
+use primitives::conj;
+use primitives::{reverse, partition, interleave, zipmap, frequencies};
 
-use functypes::{RustFunc, BoundFn, Variable};
+use functypes::{RustFunc, NativeFunc, BoundFn, Variable, Variadic};
+
+use persistent::ConsList;
+
+use env::Env;
+
+use symbol::{Sym, intern, name_of};
+
+use special;
+use special::{Special, special_form};
+
+use compiler::{compile, compile_fn};
+
+use vm;
+
+use namespace::{Namespace, CORE_NS, STRING_NS, USER_NS, split_symbol, qualify};
+
+use core::{eval_fn, apply_fn, read_string_fn};
+use core::{macroexpand_1_fn, macroexpand_fn, macroexpand_all_fn};
+use core::{partial, comp, constantly};
+use core::{map, filter, remove, reduce, some, every, group_by};
+use core::{first, rest, next, cons_seq, nth_seq, take_seq, drop_seq, take_while};
+use core::{range_seq, iterate, repeat, cycle, deferred, vec_step, str_step};
+use core::{equal_seq, not_equal_seq, str_seq, concat_seq, count_seq, is_empty_seq};
+use core::{atom, deref, reset, swap, compare_and_set, set_validator, add_watch, remove_watch};
+use core::{in_ns, require};
+
+use strings::{subs, upper_case, lower_case, split, join, trim};
+use strings::{starts_with, ends_with, index_of, replace, split_lines, is_blank};
+
+use records::{record, is_record, record_type};
+
+use multimethods::{call_multi, prefer_method, remove_method, derive, is_a};
+
+use protocols::{ProtocolRef, protocol_fn};
+
+use math::{abs, min, max, quot, rem, modulo, pow, gcd, lcm};
+use math::{is_even, is_odd, is_zero, is_pos, is_neg};
+use math::{bit_and, bit_or, bit_xor, bit_not, bit_shift_left, bit_shift_right};
 
 mod types;
 
+/**
+ * @brief result of evaluating a form
+ *
+ * Errors, and anything else that is thrown, come back in Err and
+ * unwind evaluation until a ``try`` catches them.
+ */
+pub type EvalResult = Result<Element, Exception>;
+
+/**
+ * @brief a thrown value, and the calls it unwound through
+ */
+#[deriving(Clone, Eq)]
+pub struct Exception {
+    value: Element,
+    trace: ~[TraceFrame]
+}
+
+/**
+ * @brief one call in a stack trace
+ */
+#[deriving(Clone, Eq)]
+pub struct TraceFrame {
+    name: ~str,
+    form: Element
+}
+
+impl Exception {
+    pub fn new(value: Element) -> Exception {
+        Exception { value: value, trace: ~[] }
+    }
+    /**
+     * @brief record that the exception unwound through a call
+     * @param form:     the call form, like (f x y)
+     */
+    pub fn unwound_through(self, form: &Element) -> Exception {
+        let mut ex = self;
+        ex.trace.push(TraceFrame::new(form));
+        ex
+    }
+    /**
+     * @brief the trace as data, innermost call first
+     */
+    pub fn trace_elem(&self) -> Element {
+        Vec(self.trace.iter().map(|frame| {
+            Map(ElementMap::from_pairs(~[(Keyword(~"fn"), ::types::String(frame.name.clone())),
+                                         (Keyword(~"form"), frame.form.clone())]))
+        }).collect())
+    }
+}
+
+impl TraceFrame {
+    fn new(form: &Element) -> TraceFrame {
+        let name = match *form {
+            List(ref l) => match l.first() {
+                Some(&Symbol(ref s)) | Some(&Local(ref s, _, _, _)) | Some(&Interned(ref s, _)) => {
+                    s.clone()
+                },
+                _ => ~"<fn>"
+            },
+            _ => ~"<fn>"
+        };
+        TraceFrame { name: name, form: form.clone() }
+    }
+}
+
+impl ToStr for Exception {
+    fn to_str(&self) -> ~str {
+        let msg = match self.value {
+            EvalError(_) => self.value.to_str(),
+            _ => format!("Uncaught: {:s}", self.value.to_str())
+        };
+        self.trace.iter().fold(msg, |a, frame| {
+            a + format!("\n    at {:s}: {:s}", frame.name, frame.form.to_str())
+        })
+    }
+}
+
+/**
+ * @brief throw an EvalError with the given message
+ */
+pub fn eval_error<T>(msg: ~str) -> Result<T, Exception>
+{
+    Err(Exception::new(EvalError(msg)))
+}
+
+/// like try_eval!, for functions returning Option<Result<..>>
+macro_rules! try_some(
+    ($e:expr) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => return Some(Err(e))
+        }
+    )
+)
+
+/// like try_eval!, but records the call form in the Err's trace
+macro_rules! try_call(
+    ($e:expr, $form:expr) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => return Err(e.unwound_through($form))
+        }
+    )
+)
+
+/**
+ * @brief result of evaluating a form in tail position
+ */
+enum Tail {
+    Done(Element),
+    Recur(~[Element]),
+    Call(~BoundFn, ~[Element], Element)
+}
+
 #[allow(dead_code)]
 /**
  * @brief binding system implementation
  */
 pub struct Bindings {
-    bindings: ~[HashMap<~str, Element>]
+    // the innermost frame of locals; the outermost frame in its chain
+    // is the toplevel, which is keyed by qualified names
+    scope: Rc<Env>,
+    toplevel: Rc<Env>,
+    // values given by binding forms, innermost first
+    dynamic: Option<Rc<Env>>,
+    globals: Rc<RefCell<Globals>>,
+    current_ns: Sym,
+    last_error: Option<Exception>,
+    strict_booleans: bool,
+    // run forms and fns on the vm instead of walking them
+    compiled: bool
+}
+
+/**
+ * @brief what every scope of an interpreter shares
+ */
+struct Globals {
+    dynamic_vars: HashSet<Sym>,
+    private_vars: HashSet<Sym>,
+    namespaces: HashMap<~str, Namespace>,
+    // (namespace, symbol) -> the visible definition the symbol names
+    // there; forgotten whenever definitions or refers change
+    resolved: HashMap<(Sym, Sym), Option<Sym>>,
+    // (child, parent) pairs added by derive
+    hierarchy: ~[(Element, Element)],
+    // the protocols, by qualified name
+    protocols: HashMap<Sym, ProtocolRef>,
+    // the fields each record type declares, by qualified name
+    records: HashMap<~str, ~[Element]>
 }
 
 impl Bindings {
@@ -36,273 +229,2450 @@ impl Bindings {
         binding.insert(~"*", RustFunc::new(mul));
         binding.insert(~"/", RustFunc::new(div));
         binding.insert(~"%", RustFunc::new(modfn));
-        binding.insert(~"=", RustFunc::new(equal));
-        binding.insert(~"concat", RustFunc::new(concat));
-        //binding.insert(~"not", BoundFn::new_macro([~"x"], tokenize("(if x false true)")));
-        //binding.insert(~"if-not", BoundFn::new_macro([~"test", ~"then", ~"else"],
-        //    tokenize("(if (not test) then else)")));
+        binding.insert(~"abs", RustFunc::new(abs));
+        binding.insert(~"min", RustFunc::new(min));
+        binding.insert(~"max", RustFunc::new(max));
+        binding.insert(~"quot", RustFunc::new(quot));
+        binding.insert(~"rem", RustFunc::new(rem));
+        binding.insert(~"mod", RustFunc::new(modulo));
+        binding.insert(~"pow", RustFunc::new(pow));
+        binding.insert(~"gcd", RustFunc::new(gcd));
+        binding.insert(~"lcm", RustFunc::new(lcm));
+        binding.insert(~"even?", RustFunc::new(is_even));
+        binding.insert(~"odd?", RustFunc::new(is_odd));
+        binding.insert(~"zero?", RustFunc::new(is_zero));
+        binding.insert(~"pos?", RustFunc::new(is_pos));
+        binding.insert(~"neg?", RustFunc::new(is_neg));
+        binding.insert(~"bit-and", RustFunc::new(bit_and));
+        binding.insert(~"bit-or", RustFunc::new(bit_or));
+        binding.insert(~"bit-xor", RustFunc::new(bit_xor));
+        binding.insert(~"bit-not", RustFunc::new(bit_not));
+        binding.insert(~"bit-shift-left", RustFunc::new(bit_shift_left));
+        binding.insert(~"bit-shift-right", RustFunc::new(bit_shift_right));
+        binding.insert(~"=", NativeFunc::new(equal_seq));
+        binding.insert(~"not=", NativeFunc::new(not_equal_seq));
+        binding.insert(~"compare", RustFunc::new(compare));
+        binding.insert(~"<", RustFunc::new(less));
+        binding.insert(~">", RustFunc::new(greater));
+        binding.insert(~"<=", RustFunc::new(less_equal));
+        binding.insert(~">=", RustFunc::new(greater_equal));
+        binding.insert(~"concat", NativeFunc::new(concat_seq));
+        binding.insert(~"get", RustFunc::new(get));
+        binding.insert(~"assoc", RustFunc::new(assoc));
+        binding.insert(~"record", RustFunc::new(record));
+        binding.insert(~"record?", RustFunc::new(is_record));
+        binding.insert(~"record-type", RustFunc::new(record_type));
+        binding.insert(~"prefer-method", NativeFunc::new(prefer_method));
+        binding.insert(~"remove-method", NativeFunc::new(remove_method));
+        binding.insert(~"derive", NativeFunc::new(derive));
+        binding.insert(~"isa?", NativeFunc::new(is_a));
+        binding.insert(~"hash-map", RustFunc::new(hash_map));
+        binding.insert(~"ex-info", RustFunc::new(ex_info));
+        binding.insert(~"ex-data", RustFunc::new(ex_data));
+        binding.insert(~"ex-message", RustFunc::new(ex_message));
+        binding.insert(~"boolean", RustFunc::new(boolean));
+        binding.insert(~"true?", RustFunc::new(is_true));
+        binding.insert(~"false?", RustFunc::new(is_false));
+        binding.insert(~"nil?", RustFunc::new(is_nil));
+        binding.insert(~"some?", RustFunc::new(is_some));
+        binding.insert(~"read-string", NativeFunc::new(read_string_fn));
+        binding.insert(~"eval", NativeFunc::new(eval_fn));
+        binding.insert(~"apply", NativeFunc::new(apply_fn));
+        binding.insert(~"macroexpand-1", NativeFunc::new(macroexpand_1_fn));
+        binding.insert(~"macroexpand", NativeFunc::new(macroexpand_fn));
+        binding.insert(~"macroexpand-all", NativeFunc::new(macroexpand_all_fn));
+        binding.insert(~"partial", NativeFunc::new(partial));
+        binding.insert(~"comp", NativeFunc::new(comp));
+        binding.insert(~"constantly", NativeFunc::new(constantly));
+        binding.insert(~"first", NativeFunc::new(first));
+        binding.insert(~"rest", NativeFunc::new(rest));
+        binding.insert(~"next", NativeFunc::new(next));
+        binding.insert(~"cons", NativeFunc::new(cons_seq));
+        binding.insert(~"conj", RustFunc::new(conj));
+        binding.insert(~"nth", NativeFunc::new(nth_seq));
+        binding.insert(~"count", NativeFunc::new(count_seq));
+        binding.insert(~"empty?", NativeFunc::new(is_empty_seq));
+        binding.insert(~"range", NativeFunc::new(range_seq));
+        binding.insert(~"take", NativeFunc::new(take_seq));
+        binding.insert(~"drop", NativeFunc::new(drop_seq));
+        binding.insert(~"reverse", RustFunc::new(reverse));
+        binding.insert(~"partition", RustFunc::new(partition));
+        binding.insert(~"interleave", RustFunc::new(interleave));
+        binding.insert(~"zipmap", RustFunc::new(zipmap));
+        binding.insert(~"frequencies", RustFunc::new(frequencies));
+        binding.insert(~"map", NativeFunc::new(map));
+        binding.insert(~"filter", NativeFunc::new(filter));
+        binding.insert(~"remove", NativeFunc::new(remove));
+        binding.insert(~"reduce", NativeFunc::new(reduce));
+        binding.insert(~"some", NativeFunc::new(some));
+        binding.insert(~"every?", NativeFunc::new(every));
+        binding.insert(~"group-by", NativeFunc::new(group_by));
+        binding.insert(~"take-while", NativeFunc::new(take_while));
+        binding.insert(~"iterate", NativeFunc::new(iterate));
+        binding.insert(~"repeat", NativeFunc::new(repeat));
+        binding.insert(~"cycle", NativeFunc::new(cycle));
+        binding.insert(~"atom", NativeFunc::new(atom));
+        binding.insert(~"deref", NativeFunc::new(deref));
+        binding.insert(~"reset!", NativeFunc::new(reset));
+        binding.insert(~"swap!", NativeFunc::new(swap));
+        binding.insert(~"compare-and-set!", NativeFunc::new(compare_and_set));
+        binding.insert(~"set-validator!", NativeFunc::new(set_validator));
+        binding.insert(~"add-watch", NativeFunc::new(add_watch));
+        binding.insert(~"remove-watch", NativeFunc::new(remove_watch));
+        binding.insert(~"*print-length*", nil);
+        binding.insert(~"not", BoundFn::new_macro([~"x"], tokenize("`(if ~x false true)")));
+        binding.insert(~"if-not", BoundFn::new_macro([~"test", ~"then", ~"&", ~"else"],
+            tokenize("`(if (not ~test) ~then ~@else)")));
         binding.insert(~"inc", BoundFn::new([~"x"], tokenize("(+ x 1)")));
         binding.insert(~"dec", BoundFn::new([~"x"], tokenize("(- x 1)")));
-        Bindings { bindings: ~[binding] }
+        binding.insert(~"in-ns", NativeFunc::new(in_ns));
+        binding.insert(~"require", NativeFunc::new(require));
+        binding.insert(~"str", NativeFunc::new(str_seq));
+        binding.insert(~"subs", RustFunc::new(subs));
+        let mut string: HashMap<~str, Element> = HashMap::new();
+        string.insert(~"upper-case", RustFunc::new(upper_case));
+        string.insert(~"lower-case", RustFunc::new(lower_case));
+        string.insert(~"split", RustFunc::new(split));
+        string.insert(~"join", RustFunc::new(join));
+        string.insert(~"trim", RustFunc::new(trim));
+        string.insert(~"starts-with?", RustFunc::new(starts_with));
+        string.insert(~"ends-with?", RustFunc::new(ends_with));
+        string.insert(~"index-of", RustFunc::new(index_of));
+        string.insert(~"replace", RustFunc::new(replace));
+        string.insert(~"split-lines", RustFunc::new(split_lines));
+        string.insert(~"blank?", RustFunc::new(is_blank));
+        // the builtins all live in rusp.core, except the string
+        // library, which lives in rusp.string
+        let toplevel = Env::indexed();
+        for (k, v) in binding.move_iter() {
+            let v = defined_in(v, intern(CORE_NS));
+            toplevel.borrow().insert(intern(qualify(CORE_NS, k).as_slice()), v);
+        }
+        for (k, v) in string.move_iter() {
+            toplevel.borrow().insert(intern(qualify(STRING_NS, k).as_slice()), v);
+        }
+        let mut dynamic_vars = HashSet::new();
+        dynamic_vars.insert(intern(qualify(CORE_NS, "*print-length*").as_slice()));
+        let mut namespaces = HashMap::new();
+        namespaces.insert(CORE_NS.to_owned(), Namespace::new(CORE_NS));
+        namespaces.insert(STRING_NS.to_owned(), Namespace::new(STRING_NS));
+        namespaces.insert(USER_NS.to_owned(), Namespace::new(USER_NS));
+        let mut bindings = Bindings {
+            scope: toplevel.clone(),
+            toplevel: toplevel,
+            dynamic: None,
+            globals: Rc::new(RefCell::new(Globals {
+                dynamic_vars: dynamic_vars,
+                private_vars: HashSet::new(),
+                namespaces: namespaces,
+                resolved: HashMap::new(),
+                hierarchy: ~[],
+                protocols: HashMap::new(),
+                records: HashMap::new()
+            })),
+            current_ns: intern(USER_NS),
+            last_error: None,
+            strict_booleans: false,
+            compiled: false
+        };
+        if compiled_by_default() {
+            bindings.set_compiled(true);
+        }
+        bindings
     }
     #[allow(dead_code)]
+    /**
+     * @brief a new scope, nested in this one
+     *
+     * Only an empty frame is made; everything further out is shared.
+     */
     pub fn push(&self) -> Bindings {
-        Bindings { bindings: ~[HashMap::new()] + self.bindings }
+        self.with_scope(Env::nested(&self.scope))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief only accept booleans as the test of a conditional
+     * @param strict:   false gives Clojure truthiness (only nil and
+     *                  false are falsey)
+     */
+    pub fn set_strict_booleans(&mut self, strict: bool) {
+        self.strict_booleans = strict;
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief compile forms to bytecode and run them on the vm, rather
+     *        than walking them
+     *
+     * Fns that are already defined are compiled too, so calls to them
+     * stay on the vm.
+     */
+    pub fn set_compiled(&mut self, compiled: bool) {
+        self.compiled = compiled;
+        if !compiled {
+            return;
+        }
+        let toplevel = self.toplevel.clone();
+        let current_ns = self.current_ns;
+        for q in toplevel.borrow().keys().move_iter() {
+            match toplevel.borrow().find(q) {
+                Some(Function(f)) => if !f.is_macro && f.code.is_none() {
+                    let mut f = f;
+                    // resolve the body's names where it was defined
+                    self.current_ns = f.ns.unwrap_or(current_ns);
+                    let params = f.param_names();
+                    f.f = self.analyze_body(params, &f.f);
+                    f.code = Some(Rc::new(compile_fn(self, params, &f.f)));
+                    toplevel.borrow().insert(q, Function(f));
+                },
+                _ => ()
+            }
+        }
+        self.current_ns = current_ns;
     }
     #[allow(dead_code)]
     pub fn insert(&mut self, key: &str, value: Element) -> bool {
-        self.bindings[0].insert(key.to_owned(), value)
+        self.scope.borrow().insert(intern(key), value)
     }
     #[allow(dead_code)]
     pub fn get(&self, e: &str) -> Element {
-        let s = e.to_owned();
-        for map in self.bindings.iter() {
-            if map.contains_key(&s) {
-                return map.get(&s).clone();
-            }
+        match self.lookup(intern(e)) {
+            Some(value) => value,
+            None => EvalError(~"Not in scope")
         }
-        return EvalError(~"Not in scope");
     }
     #[allow(dead_code)]
     pub fn contains_key(&self, e: &str) -> bool {
-        for map in self.bindings.iter() {
-            if map.contains_key(&e.to_owned()) {
-                return true;
-            }
+        self.lookup(intern(e)).is_some()
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief find what a name is bound to
+     *
+     * Locals come first, then values given by binding forms, then
+     * the toplevel.
+     */
+    fn lookup(&self, sym: Sym) -> Option<Element> {
+        // stop short of the toplevel, which has qualified names
+        let mut frame: &Env = self.scope.borrow();
+        loop {
+            frame = match frame.parent() {
+                Some(parent) => match frame.find(sym) {
+                    Some(value) => return Some(value),
+                    None => parent.borrow()
+                },
+                None => break
+            };
         }
-        return false;
+        let q = match self.resolve_visible(sym) {
+            Some(q) => q,
+            None => return None
+        };
+        let rebound = match self.dynamic {
+            Some(ref dynamic) => dynamic.borrow().lookup(q),
+            None => None
+        };
+        rebound.or_else(|| self.toplevel.borrow().find(q))
     }
     #[allow(dead_code)]
-    fn eval_form(&mut self, form: &[Element]) -> Element
-    {
-        //println!("eval_form({:u}): {:?}", self.bindings.len(), form);
-        let mut b = self.push();
-        if form.len() == 0 {
-            return List(form.to_owned());
-        }
-        let vals: ~[Element] = form.slice_from(1).to_owned();
-        //println!("eval({:u}): finished expanding: {:?}", self.bindings.len(), vals_expanded);
-        match form[0] {
-            Symbol(ref sym) => {
-                // lookup in bindings
-                let symclone = sym.clone();
-                if symclone == ~"if" {
-                    // if is a special case
-                    self.if_fn(vals)
-                } else if symclone == ~"def" {
-                    // bind to toplevel
-                    self.def(vals)
-                } else if symclone == ~"defn" {
-                    // bind a function to toplevel
-                    self.defn(vals)
-                } else if symclone == ~"fn" {
-                    // create a fn (don't bind it though)
-                    self.fn_nobind(vals)
-                } else if symclone == ~"defmacro" {
-                    self.defmacro(vals)
-                } else if b.contains_key(sym.to_owned()) {
-                    let bound = b.get(sym.to_owned()).clone();
-                    //println!("eval({:u}): sym {:?} resolves to {:?}",
-                    //        self.bindings.len(), sym, bound);
-                    let vals_expanded = vals.map(|x| b.eval_elem(x.clone()));
-                    self.eval_form(~[bound] + vals_expanded)
+    /**
+     * @brief evaluate a symbol
+     */
+    pub fn eval_symbol(&self, sym: Sym) -> EvalResult {
+        match self.lookup(sym) {
+            Some(bound) => Ok(bound),
+            None => {
+                let name = name_of(sym);
+                if self.resolve(name.as_slice()).is_some() {
+                    eval_error(format!("{:s} is not public", name))
                 } else {
-                    EvalError(~"Symbol Not defined")
+                    eval_error(~"Symbol Not defined")
                 }
+            }
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the definition a symbol names, if the current namespace
+     *        may use it
+     *
+     * The answer is remembered until definitions or refers change, so
+     * the names only have to be put together once.
+     */
+    fn resolve_visible(&self, sym: Sym) -> Option<Sym> {
+        let key = (self.current_ns, sym);
+        match self.globals.borrow().with(|g| g.resolved.find(&key).map(|q| *q)) {
+            Some(q) => return q,
+            None => ()
+        }
+        let q = match self.resolve(name_of(sym).as_slice()) {
+            Some(ref q) if self.is_visible(q.as_slice()) => Some(intern(q.as_slice())),
+            _ => None
+        };
+        self.globals.borrow().with_mut(|g| g.resolved.insert(key, q));
+        q
+    }
+    #[allow(dead_code)]
+    fn forget_resolved(&self) {
+        self.globals.borrow().with_mut(|g| g.resolved.clear());
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the qualified name of the definition a symbol refers to
+     * @return None if there's no such definition
+     *
+     * ``ns/name`` is looked up in ns, or in the namespace ns is an alias
+     * for. A plain name is looked up in the current namespace, then
+     * in what it refers, then in rusp.core.
+     */
+    pub fn resolve(&self, sym: &str) -> Option<~str> {
+        let current_ns = self.current_ns();
+        let candidates = self.globals.borrow().with(|g| {
+            let current = g.namespaces.get(&current_ns);
+            match split_symbol(sym) {
+                Some((ns, name)) => {
+                    let ns = current.alias(ns).unwrap_or(ns.to_owned());
+                    ~[Some(qualify(ns.as_slice(), name))]
+                },
+                None => ~[Some(qualify(current_ns.as_slice(), sym)),
+                          current.referred(sym),
+                          Some(qualify(CORE_NS, sym))]
+            }
+        });
+        let toplevel = self.toplevel.borrow();
+        candidates.move_iter().filter_map(|q| q)
+            .find(|q| toplevel.contains_key(intern(q.as_slice())))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief whether the current namespace may use a definition
+     */
+    fn is_visible(&self, q: &str) -> bool {
+        !self.is_private(q) || q.starts_with(qualify(self.current_ns().as_slice(), "").as_slice())
+    }
+    #[allow(dead_code)]
+    fn is_private(&self, q: &str) -> bool {
+        self.globals.borrow().with(|g| g.private_vars.contains(&intern(q)))
+    }
+    #[allow(dead_code)]
+    fn is_dynamic(&self, q: &str) -> bool {
+        self.globals.borrow().with(|g| g.dynamic_vars.contains(&intern(q)))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief whether child is parent, or derives from it
+     *
+     * Vectors are compared item by item, so [:square :square] isa
+     * [:rect :shape] if :square derives from both.
+     */
+    pub fn isa(&self, child: &Element, parent: &Element) -> bool {
+        if child == parent {
+            return true;
+        }
+        match (child, parent) {
+            (&Vec(ref c), &Vec(ref p)) => {
+                return c.len() == p.len() && c.iter().zip(p.iter()).all(|(x, y)| self.isa(x, y));
             },
-            List(ref l) => {
-                let vals_expanded = vals.map(|x| b.eval_elem(x.clone()));
-                let newform = ~[b.eval_form(*l)] + vals_expanded;
-                b.eval_form(newform)
+            _ => ()
+        }
+        let parents = self.globals.borrow().with(|g| {
+            let mut parents: ~[Element] = ~[];
+            for &(ref c, ref p) in g.hierarchy.iter() {
+                if c == child {
+                    parents.push(p.clone());
+                }
+            }
+            parents
+        });
+        parents.iter().any(|p| self.isa(p, parent))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief make child isa parent, for every namespace
+     */
+    pub fn derive(&mut self, child: Element, parent: Element) {
+        self.globals.borrow().with_mut(|g| g.hierarchy.push((child.clone(), parent.clone())));
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the qualified name of a record type
+     * @param name:     the name it was defined with, which is looked up
+     *                  in the current namespace, or its qualified name
+     */
+    pub fn record_type_name(&self, name: &str) -> Option<~str> {
+        let q = format!("{:s}.{:s}", self.current_ns(), name);
+        self.globals.borrow().with(|g| {
+            if g.records.contains_key(&q) {
+                Some(q.clone())
+            } else if g.records.contains_key(&name.to_owned()) {
+                Some(name.to_owned())
+            } else {
+                None
+            }
+        })
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief give the record literals in a form that was just read
+     *        their types' qualified names and declared fields
+     *
+     * Like (map->Name {...}), declared fields missing from a literal
+     * are nil. A literal of a type that isn't defined is an error.
+     */
+    pub fn read_records(&self, form: Element) -> EvalResult {
+        // only the parts of the form with a literal in them are rebuilt
+        if !has_record(&form) {
+            return Ok(form);
+        }
+        match form {
+            List(l) => {
+                let mut items: ~[Element] = ~[];
+                for x in l.iter() {
+                    items.push(try_eval!(self.read_records(x.clone())));
+                }
+                Ok(list_of(items))
             },
-            FuncPrimitive(ref fptr) => {
-                // lookup name, pass the rest of the list in
-                let vals_expanded = vals.map(|x| b.eval_elem(x.clone()));
-                let f = fptr.f;
-                f(vals_expanded)
+            Vec(v) => {
+                let mut items: ~[Element] = ~[];
+                for x in v.iter() {
+                    items.push(try_eval!(self.read_records(x.clone())));
+                }
+                Ok(vec_of(items))
             },
-            Function(ref fptr) => {
-                for (arg, val) in fptr.bindings.iter().zip(vals.iter()) {
-                    match arg {
-                        &Variable(ref s) => b.insert(s.to_owned(), val.clone()),
-                        _ => return EvalError(~"Variadic not implemented")
-                    };
+            Map(m) => {
+                let mut pairs: ~[(Element, Element)] = ~[];
+                for &(ref k, ref v) in m.iter() {
+                    pairs.push((try_eval!(self.read_records(k.clone())),
+                                try_eval!(self.read_records(v.clone()))));
+                }
+                Ok(Map(ElementMap::from_pairs(pairs)))
+            },
+            Record(name, fields) => {
+                let q = match self.record_type_name(name.as_slice()) {
+                    Some(q) => q,
+                    None => return eval_error(format!("#{:s}: no such record type", name))
+                };
+                let declared = self.globals.borrow().with(|g| g.records.get(&q).clone());
+                let fields = try_eval!(self.read_records(Map(fields)));
+                match record([Symbol(q), vec_of(declared), fields]) {
+                    EvalError(e) => eval_error(e),
+                    r => Ok(r)
                 }
-                // TODO: different behavior for macros?
-                b.eval_elem(fptr.f.clone())
+            },
+            other => Ok(other)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief implement a protocol method for a type
+     * @param protocol:     the protocol's name, resolved like any symbol
+     * @param type_name:    a type like number or vector, or a record type's name
+     * @param method:       the method to implement
+     * @param f:            what to call, which can be a RustFunc or NativeFunc
+     */
+    pub fn extend_protocol(&mut self, protocol: &str, type_name: &str, method: &str, f: Element)
+                           -> Result<(), Exception> {
+        let found = match self.resolve(protocol) {
+            Some(q) => self.globals.borrow().with(|g| {
+                g.protocols.find(&intern(q.as_slice())).map(|p| p.clone())
+            }),
+            None => None
+        };
+        match found {
+            Some(ref p) if p.has_method(method) => {
+                p.extend(type_name, method, f);
+                Ok(())
+            },
+            Some(_) => eval_error(format!("{:s} is not a method of {:s}", method, protocol)),
+            None => eval_error(format!("{:s} is not a protocol", protocol))
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief add a definition to the current namespace
+     * @param flags:    metadata from the def, like dynamic and private
+     *
+     * Definitions go in the toplevel frame, which every scope shares,
+     * so a def inside a function outlasts the call.
+     */
+    pub fn define(&mut self, name: &str, value: Element, flags: &[~str]) {
+        let q = intern(qualify(self.current_ns().as_slice(), name).as_slice());
+        self.globals.borrow().with_mut(|g| {
+            if flags.contains(&~"dynamic") {
+                g.dynamic_vars.insert(q);
             }
-            _ => EvalError(~"Failed to evaluate form")
-
+            if flags.contains(&~"private") {
+                g.private_vars.insert(q);
+            }
+        });
+        self.toplevel.borrow().insert(q, value);
+        self.forget_resolved();
+    }
+    #[allow(dead_code)]
+    pub fn current_ns(&self) -> ~str {
+        name_of(self.current_ns)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief switch to a namespace, creating it if needed
+     */
+    pub fn in_ns(&mut self, name: &str) {
+        self.globals.borrow().with_mut(|g| {
+            if !g.namespaces.contains_key(&name.to_owned()) {
+                g.namespaces.insert(name.to_owned(), Namespace::new(name));
+            }
+        });
+        self.current_ns = intern(name);
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief make another namespace's definitions available here
+     * @param spec:     lib, or [lib :as alias :refer [names...]]
+     *
+     * ``:refer :all`` refers every public definition in lib.
+     */
+    pub fn require(&mut self, spec: &Element) -> Result<(), Exception> {
+        let (lib, opts) = match *spec {
+            Symbol(ref lib) => (lib.clone(), ~[]),
+            Vec(ref v) if v.len() > 0 => match v[0] {
+                Symbol(ref lib) => (lib.clone(), v.iter().skip(1).map(|x| x.clone()).collect()),
+                _ => return eval_error(~"require: expected a namespace name")
+            },
+            _ => return eval_error(~"require: expected a namespace name")
+        };
+        if !self.globals.borrow().with(|g| g.namespaces.contains_key(&lib)) {
+            return eval_error(format!("require: no namespace named {:s}", lib));
+        }
+        if opts.len() % 2 != 0 {
+            return eval_error(~"require: options must come in pairs");
         }
+        let prefix = qualify(lib.as_slice(), "");
+        for opt in opts.chunks(2) {
+            // alias -> namespace name, from :as
+            let mut aliases: ~[(~str, ~str)] = ~[];
+            // short name -> qualified name, for everything referred
+            let mut refers: ~[(~str, ~str)] = ~[];
+            match (&opt[0], &opt[1]) {
+                (&Keyword(ref k), &Symbol(ref alias)) if *k == ~"as" => {
+                    aliases.push((alias.clone(), lib.clone()));
+                },
+                (&Keyword(ref k), &Keyword(ref all)) if *k == ~"refer" && *all == ~"all" => {
+                    for q in self.toplevel.borrow().keys().move_iter().map(|q| name_of(q)) {
+                        if q.starts_with(prefix.as_slice()) && !self.is_private(q.as_slice()) {
+                            refers.push((q.slice_from(prefix.len()).to_owned(), q.clone()));
+                        }
+                    }
+                },
+                (&Keyword(ref k), &Vec(ref names)) if *k == ~"refer" => {
+                    for name in names.iter() {
+                        let name = match *name {
+                            Symbol(ref n) => n.clone(),
+                            _ => return eval_error(~"require: :refer expects symbols")
+                        };
+                        let q = qualify(lib.as_slice(), name.as_slice());
+                        if !self.toplevel.borrow().contains_key(intern(q.as_slice())) {
+                            return eval_error(format!("require: {:s} does not exist", q));
+                        }
+                        if self.is_private(q.as_slice()) {
+                            return eval_error(format!("require: {:s} is not public", q));
+                        }
+                        refers.push((name, q));
+                    }
+                },
+                _ => return eval_error(~"require: unknown option")
+            }
+            let current_ns = self.current_ns();
+            self.globals.borrow().with_mut(|g| {
+                let ns = g.namespaces.get_mut(&current_ns);
+                for &(ref alias, ref lib) in aliases.iter() {
+                    ns.add_alias(alias.as_slice(), lib.as_slice());
+                }
+                for &(ref name, ref q) in refers.iter() {
+                    ns.refer(name.as_slice(), q.as_slice());
+                }
+            });
+            self.forget_resolved();
+        }
+        Ok(())
     }
     #[allow(dead_code)]
-    pub fn eval_elem(&mut self, form: Element) -> Element
+    fn eval_form(&mut self, form: ConsList) -> EvalResult
     {
-        // evaluate a single element, entry-point into eval_form
-        match form {
-            List(l) => self.eval_form(l),
-            Vec(v) => Vec(v.map(|x| self.eval_elem(x.clone()))),
-            Symbol(ref sym) => {
-                // lookup in bindings
-                if self.contains_key(sym.to_owned()) {
-                    let bound = self.get(sym.to_owned()).clone();
-                    //println!("eval({:u}): sym {:?} resolves to {:?}",
-                    //        self.bindings.len(), sym, bound);
-                    bound
-                } else {
-                    EvalError(~"Symbol Not defined")
-                }
-            }
-            _ => form
+        //println!("eval_form: {:?}", form);
+        if form.is_empty() {
+            return Ok(List(form));
+        }
+        match try_eval!(self.eval_tail(List(form))) {
+            Done(e) => Ok(e),
+            Recur(_) => eval_error(~"recur: can only be used inside loop or fn"),
+            Call(f, args, call) => Ok(try_call!(self.call_fn(f, args), &call))
         }
     }
     #[allow(dead_code)]
-    pub fn eval(&mut self, s: &str) -> Element
+    /**
+     * @brief evaluate a special form
+     * @param form:     which special form it is
+     * @param sym:      name of the special form
+     * @param vals:     the unevaluated args
+     * @return None for the conditional forms, which conditional_tail runs
+     */
+    fn eval_special(&mut self, form: Special, sym: &str, vals: &[Element])
+        -> Option<EvalResult>
     {
-        // tokenize, then eval, a string
-        let parsed = tokenize(s);
-        self.eval_elem(parsed)
+        let result = match form {
+            // if is a special case
+            special::If => self.if_fn(vals),
+            // bind to toplevel
+            special::Def => self.def(vals),
+            // bind a function to toplevel
+            special::Defn => self.defn(vals),
+            // a function only its own namespace can use
+            special::DefnPrivate => self.define_fn(vals, false, true),
+            special::Ns => self.ns_fn(vals),
+            // create a fn (don't bind it though)
+            special::Fn => self.fn_nobind(vals),
+            special::Defmacro => self.defmacro(vals),
+            special::Defmulti => self.defmulti(vals),
+            special::Defmethod => self.defmethod(vals),
+            special::Defprotocol => self.defprotocol(vals),
+            // implement protocols for one type
+            special::ExtendType => self.extend_fn("extend-type", vals, true),
+            // implement one protocol for several types
+            special::ExtendProtocol => self.extend_fn("extend-protocol", vals, false),
+            // a record type, with its constructors and predicate
+            special::Defrecord => self.defrecord(vals),
+            // evaluate forms in order, returning the last
+            special::Do => self.do_fn(vals),
+            // bind locals in a new scope
+            special::Let => self.let_fn(vals),
+            special::Loop => self.loop_fn(vals),
+            // recur in tail position is caught by eval_tail
+            special::Recur => eval_error(~"recur: can only be used inside loop or fn"),
+            // return the form unevaluated
+            special::Quote => match vals {
+                [ref quoted] => Ok(quoted.clone()),
+                _ => eval_error(format!("quote: wrong number of args ({:u})", vals.len()))
+            },
+            // rebind dynamic vars while the body runs
+            special::Binding => self.binding_fn(vals),
+            // the body runs when the seq is first needed, with the
+            // locals it could see here
+            special::LazySeq => {
+                Ok(Lazy(LazySeq::new(deferred(body_form(vals), self.scope.clone()))))
+            },
+            // build a form from a template
+            special::SyntaxQuote => self.syntax_quote_fn(vals),
+            special::Unquote | special::UnquoteSplicing => {
+                eval_error(format!("{:s}: can only be used inside syntax-quote", sym))
+            },
+            special::Throw => self.throw_fn(vals),
+            special::Try => self.try_fn(vals),
+            special::Stacktrace => self.stacktrace_fn(vals),
+            special::Catch | special::Finally => {
+                eval_error(format!("{:s}: can only be used inside try", sym))
+            },
+            special::And | special::Or | special::When | special::WhenNot | special::Cond
+                | special::Case | special::IfLet | special::WhenLet => return None
+        };
+        Some(result)
     }
     #[allow(dead_code)]
     /**
-     * @brief ``if`` function
-     * @param list: list of elements: [BOOL TRUE-FORM FALSE-FORM]
+     * @brief call an evaluated function with evaluated args
+     * @param f:        the function
+     * @param args:     the args to pass in
+     *
+     * An EvalError returned by a builtin is thrown.
      */
-    fn if_fn(&mut self, list: &[Element]) -> Element
+    pub fn apply(&mut self, f: Element, args: ~[Element]) -> EvalResult
     {
-        // builtin if function
-        let list_len = list.len();
-        if list_len > 3 || list_len < 2 {
-            return EvalError(format!("if: wrong number of args ({:u})", list_len));
-        }
-        let rest = list.slice_from(1);
-        match self.eval_elem(list[0].clone()) {
-            Boolean(true) => self.eval_elem(rest[0].clone()),
-            Boolean(false) if list_len > 2 => self.eval_elem(rest[1].clone()),
-            Boolean(false) if list_len == 2 => nil,
-            _ => EvalError(~"if: first element must be boolean")
+        match f {
+            FuncPrimitive(ref fptr) => {
+                // builtins only know about eager collections
+                let mut realized: ~[Element] = ~[];
+                for arg in args.move_iter() {
+                    realized.push(try_eval!(self.realize_all(arg)));
+                }
+                let f = fptr.f;
+                match f(realized) {
+                    EvalError(e) => eval_error(e),
+                    result => Ok(result)
+                }
+            },
+            FuncNative(ref fptr) => fptr.call(self, args),
+            Function(ref fptr) if fptr.is_macro => eval_error(~"can't call a macro as a function"),
+            Function(fptr) => self.call_fn(fptr, args),
+            Multi(m) => call_multi(self, &m, args),
+            Keyword(k) => {
+                // a keyword looks itself up in a map or record
+                if args.len() < 1 || args.len() > 2 {
+                    return eval_error(format!(":{:s}: wrong number of args ({:u})", k, args.len()));
+                }
+                let mut lookup = ~[args[0].clone(), Keyword(k)];
+                lookup.push_all(args.slice_from(1));
+                match get(lookup) {
+                    EvalError(e) => eval_error(e),
+                    result => Ok(result)
+                }
+            },
+            _ => eval_error(~"Failed to evaluate form")
         }
     }
     #[allow(dead_code)]
     /**
-     * @brief Bind a value, allowing it to be called later
-     * @param vals      list of elements: [name value]
+     * @brief realize the first cell of a lazy seq
+     * @return the first item and the rest, or None if it's empty
+     *
+     * A thunk may return another lazy seq; those are followed in a
+     * loop rather than by recursing, and all of them get the result.
      */
-    fn def(&mut self, vals: &[Element]) -> Element
+    pub fn realize(&mut self, seq: &LazySeq) -> Result<Option<(Element, Element)>, Exception>
     {
-        // bind a value, allowing it to be called later
-        if vals.len() != 2 {
-            EvalError(~"expected 2 args")
-        } else {
-            let (name, form) = (vals[0].clone(), self.eval_elem(vals[1].clone()));
-            match name {
-                Symbol(s) => {
-                    let toplevel = self.bindings.len() - 1;
-                    self.bindings[toplevel].insert(s, form);
-                    nil
+        let mut waiting: ~[LazySeq] = ~[];
+        let mut current = seq.clone();
+        let cell;
+        loop {
+            match current.peek() {
+                Some(c) => {
+                    cell = c;
+                    break;
                 },
-                _ => EvalError(~"first arg not of type symbol")
+                None => ()
             }
+            let thunk = current.thunk().unwrap();
+            waiting.push(current.clone());
+            current = match try_eval!(self.apply(thunk, ~[])) {
+                Lazy(next) => next,
+                other => {
+                    cell = try_eval!(self.seq_step("lazy-seq", &other));
+                    break;
+                }
+            };
         }
+        for s in waiting.iter() {
+            s.set_realized(cell.clone());
+        }
+        Ok(cell)
     }
     #[allow(dead_code)]
     /**
-     * @brief Bind a function, allowing it to be called later.
-     * @param vals      list of elements: [name [arg1 arg2 ...] form]
+     * @brief split any seqable into its first item and the rest
+     * @param name:     name of the calling function, for errors
+     * @param coll:     the collection
+     * @return None if coll is empty
      */
-    fn defn(&mut self, vals: &[Element]) -> Element {
-        // bind a function, allowing it to be called later.
-        // Syntactic sugar for
-        //      (def NAME (fn [ARG1, ...] FORM))
-        // TODO: when defmacro works, use that instead
-        if vals.len() != 3 {
-            EvalError(~"expexted 3 args")
-        } else {
-            let name = match vals[0].clone() {
-                Symbol(s) => s,
-                _ => return EvalError(~"name must be a symbol")
-            };
-            let args_v = match vals[1].clone() {
-                Vec(v) => v,
-                _ => return EvalError(~"args must be a vector")
-            };
-            let mut args: ~[~str] = ~[];
-            for i in args_v.iter() {
-                match i.clone() {
-                    Symbol(s) => args.push(s.clone()),
-                    _ => return EvalError(~"args must be symbols")
-                }
+    pub fn seq_step(&mut self, name: &str, coll: &Element)
+        -> Result<Option<(Element, Element)>, Exception>
+    {
+        match *coll {
+            Lazy(ref seq) => self.realize(seq),
+            // lists share their tails, so this doesn't copy
+            List(ref l) => Ok(l.first().map(|x| (x.clone(), List(l.rest())))),
+            // and the rest of a vector or string is a view into it
+            Vec(ref v) => Ok(vec_step(v, 0)),
+            String(ref text) => Ok(str_step(Rc::new(text.clone()), 0)),
+            _ => match seq_items(name, coll) {
+                Ok(items) => Ok(items.head_opt().map(|x| {
+                    (x.clone(), list_of(items.slice_from(1).to_owned()))
+                })),
+                Err(e) => Err(Exception::new(e))
             }
-            let form = vals[2].clone();
-            let toplevel = self.bindings.len() - 1;
-            self.bindings[toplevel].insert(name, BoundFn::new(args, form));
-            nil
         }
     }
     #[allow(dead_code)]
     /**
-     * @brief create a fn without binding it to a variable
-     * @param vals:     list of elements: [[arg1 arg2 ...] form]
+     * @brief realize the whole of a lazy seq as a list
+     *
+     * Anything that isn't a lazy seq is returned as is.
      */
-    fn fn_nobind(&mut self, vals: &[Element]) -> Element {
-        // Create a function (don't bind it to a variable)
-        // TODO: define in terms of defmacro when defmacro works
-        if vals.len() != 2 {
-            EvalError(~"expected 2 args")
-        } else {
-            let (args_wrapped, form) = (vals[0].clone(), vals[1].clone());
-            let args_v: ~[Element] = match args_wrapped {
-                Vec(v) => v,
-                _ => return EvalError(~"args must be in a vector")
-            };
-            let mut args: ~[~str] = ~[];
-            for i in args_v.iter() {
-                match i.clone() {
-                    Symbol(s) => args.push(s.clone()),
-                    _ => return EvalError(~"args must be symbols")
-                }
+    pub fn realize_all(&mut self, coll: Element) -> EvalResult
+    {
+        let mut current = match coll {
+            Lazy(_) => coll,
+            _ => return Ok(coll)
+        };
+        let mut items: ~[Element] = ~[];
+        loop {
+            match try_eval!(self.seq_step("realize", &current)) {
+                Some((first, rest)) => {
+                    items.push(first);
+                    current = rest;
+                },
+                None => break
             }
-            BoundFn::new(args, form)
         }
+        Ok(list_of(items))
     }
     #[allow(dead_code)]
     /**
-     * @brief create a macro and bind it
-     * @param vals:     list of elements: [name [arg1 arg2 ...] form]
+     * @brief print a value for the REPL
+     *
+     * Lazy seqs are realized for printing, and only the first
+     * ``*print-length*`` items of a top level seq are printed.
      */
-    fn defmacro(&mut self, vals: &[Element]) -> Element {
-        // Create a macro and bind it.
-        // TODO: implement
-        println("WARN: defmacro not implemented yet, using defn instead");
-        self.defn(vals)
+    pub fn print_str(&mut self, value: &Element) -> Result<~str, Exception>
+    {
+        let limit = match self.get("*print-length*") {
+            Number(n) if n >= 0 => Some(n as uint),
+            _ => None
+        };
+        let (open, close) = match (value, limit) {
+            (&Lazy(_), _) | (&List(_), Some(_)) => ("(", ")"),
+            (&Vec(_), Some(_)) => ("[", "]"),
+            _ => return Ok(value.to_str())
+        };
+        let mut items: ~[~str] = ~[];
+        let mut current = value.clone();
+        loop {
+            let step = try_eval!(self.seq_step("print", &current));
+            match step {
+                Some(_) if limit == Some(items.len()) => {
+                    items.push(~"...");
+                    break;
+                },
+                Some((first, rest)) => {
+                    items.push(try_eval!(self.print_str(&first)));
+                    current = rest;
+                },
+                None => break
+            }
+        }
+        Ok(format!("{:s}{:s}{:s}", open, items.connect(" "), close))
     }
-}
-
-
-#[allow(dead_code)]
-pub fn eval(s: &str) -> Element
-{
-    // one-off eval function. Good if you just need a
-    // one-liner
-    let mut bindings = Bindings::new();
-    bindings.eval(s)
-}
-
-
-
-#[test]
-fn test_basic_eval() {
-    assert!(eval("1") == ::types::Number(1));
-    assert!(eval("1\n") == ::types::Number(1));
-    assert!(eval("") == ::types::nil);
-    assert!(eval("\n") == ::types::nil);
-    assert!(eval("()") == List(~[]));
-    assert!(eval("()\n") == List(~[]));
-    assert!(eval("[]") == Vec(~[]));
-    assert!(eval("\"\"") == ::types::String(~""));
-    assert!(eval("\"test string\"") == ::types::String(~"test string"));
+    #[allow(dead_code)]
+    /**
+     * @brief give a new fn the namespace and locals it was made in
+     *
+     * Other values are returned as they are.
+     */
+    pub fn close_over(&self, f: Element) -> Element {
+        match defined_in(f, self.current_ns) {
+            Function(mut fptr) => {
+                // the toplevel isn't kept, since the fn may be defined
+                // in it, and every fn sees it anyway
+                fptr.env = match self.scope.borrow().parent() {
+                    Some(_) => Some(self.scope.clone()),
+                    None => None
+                };
+                Function(fptr)
+            },
+            other => other
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the frame a call to f runs in: a new one, nested in the
+     *        scope f was made in
+     */
+    pub fn fn_scope(&self, f: &BoundFn) -> Rc<Env> {
+        match f.env {
+            Some(ref env) => Env::nested(env),
+            None => Env::nested(&self.toplevel)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief a copy of these bindings, with a saved scope in place of
+     *        the current one
+     */
+    pub fn with_scope(&self, scope: Rc<Env>) -> Bindings {
+        Bindings {
+            scope: scope,
+            toplevel: self.toplevel.clone(),
+            dynamic: self.dynamic.clone(),
+            globals: self.globals.clone(),
+            current_ns: self.current_ns,
+            last_error: self.last_error.clone(),
+            strict_booleans: self.strict_booleans,
+            compiled: self.compiled
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief a new scope, in place of this one
+     *
+     * Used by recur, so anything that kept the old frame still sees
+     * the old values.
+     */
+    fn renew(&self) -> Bindings {
+        match self.scope.borrow().parent() {
+            Some(parent) => self.with_scope(Env::nested(parent)),
+            None => fail!("renew: can't replace the toplevel")
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief call a bound function, running any tail calls it makes
+     * @param f:        the function
+     * @param args:     the evaluated args
+     */
+    fn call_fn(&mut self, f: ~BoundFn, args: ~[Element]) -> EvalResult
+    {
+        if self.compiled && f.code.is_some() {
+            return vm::call(self, f, args);
+        }
+        let mut b = self.with_scope(self.fn_scope(f));
+        let params = try_eval!(b.bind_args(f, args));
+        self.trampoline(b, params, f.f.clone())
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief bind args to a function's params in the current scope
+     * @return the names of the params that were bound
+     */
+    pub fn bind_args(&mut self, f: &BoundFn, args: &[Element]) -> Result<~[Sym], Exception>
+    {
+        let mut params: ~[Sym] = ~[];
+        let mut bound = 0;
+        match f.ns {
+            Some(ns) => self.current_ns = ns,
+            None => ()
+        }
+        for arg in f.bindings.iter() {
+            match arg {
+                &Variable(ref s) => {
+                    if bound >= args.len() {
+                        break;
+                    }
+                    let param = intern(s.as_slice());
+                    self.scope.borrow().insert(param, args[bound].clone());
+                    bound += 1;
+                    params.push(param);
+                },
+                &Variadic(ref s) => {
+                    // the rest of the args get collected into a list
+                    let param = intern(s.as_slice());
+                    self.scope.borrow().insert(param, list_of(args.slice_from(bound).to_owned()));
+                    bound = args.len();
+                    params.push(param);
+                }
+            };
+        }
+        if params.len() != f.bindings.len() || bound != args.len() {
+            return eval_error(format!("wrong number of args ({:u}) passed to fn", args.len()));
+        }
+        Ok(params)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief run a macro on its unevaluated args
+     * @param f:        the macro
+     * @param vals:     the unevaluated args from the call
+     * @return the form the macro expands to
+     *
+     * Args from analyzed code get their plain symbols back, since the
+     * expansion can move them into other frames.
+     */
+    fn expand_macro(&mut self, f: &BoundFn, vals: &[Element]) -> EvalResult
+    {
+        let vals: ~[Element] = vals.iter().map(|v| unanalyzed(v)).collect();
+        let mut b = self.with_scope(self.fn_scope(f));
+        let params = try_eval!(b.bind_args(f, vals));
+        self.trampoline(b, params, f.f.clone())
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief expand a form once, if it is a macro call
+     * @return None if the form isn't a macro call
+     */
+    pub fn macroexpand_1(&mut self, form: &Element) -> Result<Option<Element>, Exception>
+    {
+        let l = match *form {
+            List(ref l) if l.len() > 0 => l.to_vec(),
+            _ => return Ok(None)
+        };
+        let sym = match l[0] {
+            Symbol(ref s) => intern(s.as_slice()),
+            _ => return Ok(None)
+        };
+        match self.lookup(sym) {
+            Some(Function(ref f)) if f.is_macro => {
+                let expansion = try_eval!(self.expand_macro(&**f, l.slice_from(1)));
+                Ok(Some(expansion))
+            },
+            _ => Ok(None)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief expand a form until it is no longer a macro call
+     */
+    pub fn macroexpand(&mut self, form: Element) -> EvalResult
+    {
+        let mut form = form;
+        loop {
+            match try_eval!(self.macroexpand_1(&form)) {
+                Some(expansion) => form = expansion,
+                None => return Ok(form)
+            }
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief expand a form and every form nested inside it
+     */
+    pub fn macroexpand_all(&mut self, form: Element) -> EvalResult
+    {
+        match try_eval!(self.macroexpand(form)) {
+            List(l) => {
+                match l.first() {
+                    // templates are data until they're evaluated
+                    Some(&Symbol(ref s)) if *s == ~"syntax-quote" || *s == ~"quote" => {
+                        return Ok(List(l.clone()));
+                    },
+                    _ => ()
+                }
+                let mut out: ~[Element] = ~[];
+                for x in l.iter() {
+                    out.push(try_eval!(self.macroexpand_all(x.clone())));
+                }
+                Ok(list_of(out))
+            },
+            Vec(v) => {
+                let mut out: ~[Element] = ~[];
+                for x in v.iter() {
+                    out.push(try_eval!(self.macroexpand_all(x.clone())));
+                }
+                Ok(vec_of(out))
+            },
+            expanded => Ok(expanded)
+        }
+    }
+    #[allow(dead_code)]
+    pub fn eval_elem(&mut self, form: Element) -> EvalResult
+    {
+        // evaluate a single element, entry-point into eval_form
+        match form {
+            List(l) => self.eval_form(l),
+            Vec(v) => Ok(vec_of(try_eval!(self.eval_args(v.to_vec())))),
+            Map(m) => {
+                let mut evald = ElementMap::new();
+                for &(ref k, ref v) in m.iter() {
+                    let key = try_eval!(self.eval_elem(k.clone()));
+                    let value = try_eval!(self.eval_elem(v.clone()));
+                    evald.insert(key, value);
+                }
+                Ok(Map(evald))
+            },
+            Symbol(ref sym) => self.eval_symbol(intern(sym.as_slice())),
+            Interned(_, id) => self.eval_symbol(id),
+            Local(ref s, id, depth, slot) => {
+                // analyze worked out where the local is, so its name
+                // isn't looked up
+                match self.scope.borrow().get(id, depth, slot) {
+                    Some(value) => Ok(value),
+                    None => eval_error(format!("{:s}: local isn't in the slot it was given", *s))
+                }
+            },
+            _ => Ok(form)
+        }
+    }
+    #[allow(dead_code)]
+    pub fn eval(&mut self, s: &str) -> Result<Element, Element>
+    {
+        // tokenize, then eval, a string. Anything thrown is kept
+        // around as *e, along with its trace.
+        let result = match self.read_records(tokenize(s)) {
+            Ok(form) => {
+                let parsed = self.analyze(&form, &mut ~[]);
+                if self.compiled {
+                    let chunk = compile(self, &parsed);
+                    vm::eval(self, chunk)
+                } else {
+                    self.eval_elem(parsed)
+                }
+            },
+            Err(ex) => Err(ex)
+        };
+        match result {
+            Ok(e) => Ok(e),
+            Err(ex) => {
+                let e = intern(qualify(CORE_NS, "*e").as_slice());
+                if self.toplevel.borrow().insert(e, ex.value.clone()) {
+                    self.forget_resolved();
+                }
+                self.last_error = Some(ex.clone());
+                Err(ex.value)
+            }
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the last exception that escaped to the top level
+     */
+    pub fn last_error<'a>(&'a self) -> Option<&'a Exception> {
+        self.last_error.as_ref()
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief resolve the symbols in a fn body, before it is first run
+     * @param params:   the fn's params, which are the only locals it
+     *                  knows about
+     * @param body:     the body form
+     */
+    pub fn analyze_body(&self, params: &[~str], body: &Element) -> Element {
+        let frame: ~[~str] = params.iter().filter(|p| p.as_slice() != "&")
+            .map(|p| p.clone()).collect();
+        self.analyze(body, &mut ~[frame])
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief resolve the symbols in a form, ahead of evaluating it
+     * @param form:     the form
+     * @param scope:    the names of the locals form can see, one frame
+     *                  per scope, innermost last, each in slot order
+     *
+     * Locals become Local, which knows which frame and slot the value
+     * will be in, and other symbols are interned. Data, and calls that
+     * might be to a macro, are left alone, since what they mean isn't
+     * known until they are evaluated.
+     */
+    fn analyze(&self, form: &Element, scope: &mut ~[~[~str]]) -> Element {
+        let items = match *form {
+            Symbol(ref s) => return match find_local(scope.as_slice(), s.as_slice()) {
+                Some((depth, slot)) => Local(s.clone(), intern(s.as_slice()), depth, slot),
+                None => Interned(s.clone(), intern(s.as_slice()))
+            },
+            Vec(ref v) => return vec_of(self.analyze_all(v.to_vec(), scope)),
+            Map(ref m) => {
+                let mut pairs: ~[(Element, Element)] = ~[];
+                for &(ref k, ref v) in m.iter() {
+                    pairs.push((self.analyze(k, scope), self.analyze(v, scope)));
+                }
+                return Map(ElementMap::from_pairs(pairs));
+            },
+            List(ref l) if l.len() > 0 => l.to_vec(),
+            _ => return form.clone()
+        };
+        let head = match items[0] {
+            Symbol(ref s) => s.clone(),
+            _ => return list_of(self.analyze_all(items, scope))
+        };
+        let h = head.as_slice();
+        let vals = items.slice_from(1);
+        let mut out = ~[items[0].clone()];
+        let kind = special_form(h);
+        match kind {
+            Some(special::Quote) | Some(special::SyntaxQuote) | Some(special::Unquote)
+                | Some(special::UnquoteSplicing) | Some(special::Fn) | Some(special::Defn)
+                | Some(special::DefnPrivate) | Some(special::Defmacro) | Some(special::Ns)
+                | Some(special::Defrecord) | Some(special::Defmethod)
+                | Some(special::Defprotocol) | Some(special::ExtendType)
+                | Some(special::ExtendProtocol) | Some(special::Stacktrace)
+                | Some(special::Catch) | Some(special::Finally) => {
+                // data, or fn bodies, which are analyzed when the fn is made
+                return form.clone();
+            },
+            Some(special::Def) | Some(special::Defmulti) if vals.len() > 0 => {
+                out.push_all(vals.init());
+                out.push(self.analyze(vals.last(), scope));
+            },
+            Some(special::Let) | Some(special::Loop) => {
+                // the bindings go in one new frame, and each value can
+                // see the ones bound before it
+                let binds = match vals.head_opt() {
+                    Some(&Vec(ref v)) if v.len() % 2 == 0 => v.to_vec(),
+                    _ => return form.clone()
+                };
+                let mut analyzed: ~[Element] = ~[];
+                scope.push(~[]);
+                for pair in binds.chunks(2) {
+                    let name = match pair[0] {
+                        Symbol(ref s) => s.clone(),
+                        _ => {
+                            scope.pop();
+                            return form.clone();
+                        }
+                    };
+                    analyzed.push(pair[0].clone());
+                    analyzed.push(self.analyze(&pair[1], scope));
+                    let last = scope.len() - 1;
+                    scope[last].push(name);
+                }
+                out.push(vec_of(analyzed));
+                out.push_all_move(self.analyze_all(vals.slice_from(1), scope));
+                scope.pop();
+            },
+            Some(special::IfLet) | Some(special::WhenLet) => {
+                // only the body sees the name; the else branch doesn't
+                let (name, test) = match vals.head_opt() {
+                    Some(&Vec(ref v)) if v.len() == 2 => match (v[0], v[1]) {
+                        (Symbol(name), test) => (name, test),
+                        _ => return form.clone()
+                    },
+                    _ => return form.clone()
+                };
+                let test = self.analyze(&test, scope);
+                out.push(vec_of(~[Symbol(name.clone()), test]));
+                let split = if kind == Some(special::IfLet) && vals.len() > 2 {
+                    2
+                } else {
+                    vals.len()
+                };
+                scope.push(~[name]);
+                out.push_all_move(self.analyze_all(vals.slice(1, split), scope));
+                scope.pop();
+                out.push_all_move(self.analyze_all(vals.slice_from(split), scope));
+            },
+            Some(special::Try) => {
+                for x in vals.iter() {
+                    let analyzed = match (clause_of(x, "catch"), clause_of(x, "finally")) {
+                        (Some(c), _) => match c.head_opt() {
+                            Some(&Symbol(ref name)) => {
+                                let mut clause = ~[Symbol(~"catch"), c[0].clone()];
+                                scope.push(~[name.clone()]);
+                                clause.push_all_move(self.analyze_all(c.slice_from(1), scope));
+                                scope.pop();
+                                list_of(clause)
+                            },
+                            _ => x.clone()
+                        },
+                        (_, Some(body)) => {
+                            let mut clause = ~[Symbol(~"finally")];
+                            clause.push_all_move(self.analyze_all(body, scope));
+                            list_of(clause)
+                        },
+                        _ => self.analyze(x, scope)
+                    };
+                    out.push(analyzed);
+                }
+            },
+            Some(special::Case) if vals.len() > 0 => {
+                // the constants are data, but the results and the default
+                // are code
+                out.push(self.analyze(&vals[0], scope));
+                let clauses = vals.slice_from(1);
+                for (i, x) in clauses.iter().enumerate() {
+                    if i % 2 == 0 && i + 1 < clauses.len() {
+                        out.push(x.clone());
+                    } else {
+                        out.push(self.analyze(x, scope));
+                    }
+                }
+            },
+            Some(special::Binding) => {
+                // the names are dynamic vars, not locals
+                let binds = match vals.head_opt() {
+                    Some(&Vec(ref v)) => v.to_vec(),
+                    _ => return form.clone()
+                };
+                let mut analyzed: ~[Element] = ~[];
+                for (i, x) in binds.iter().enumerate() {
+                    analyzed.push(if i % 2 == 0 { x.clone() } else { self.analyze(x, scope) });
+                }
+                out.push(vec_of(analyzed));
+                out.push_all_move(self.analyze_all(vals.slice_from(1), scope));
+            },
+            Some(_) => out.push_all_move(self.analyze_all(vals, scope)),
+            None if find_local(scope.as_slice(), h).is_some() => {
+                return list_of(self.analyze_all(items, scope));
+            },
+            None if self.names_function(h) => {
+                out[0] = Interned(head.clone(), intern(h));
+                out.push_all_move(self.analyze_all(vals, scope));
+            },
+            // a macro, or something not defined yet
+            None => return form.clone()
+        }
+        list_of(out)
+    }
+    #[allow(dead_code)]
+    fn analyze_all(&self, forms: &[Element], scope: &mut ~[~[~str]]) -> ~[Element] {
+        let mut out: ~[Element] = ~[];
+        for x in forms.iter() {
+            out.push(self.analyze(x, scope));
+        }
+        out
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief whether sym is defined, as something other than a macro
+     */
+    fn names_function(&self, sym: &str) -> bool {
+        let q = match self.resolve(sym) {
+            Some(q) => q,
+            None => return false
+        };
+        match self.toplevel.borrow().find(intern(q.as_slice())) {
+            Some(Function(ref f)) => !f.is_macro,
+            Some(_) => true,
+            None => false
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief ``if`` function
+     * @param list: list of elements: [BOOL TRUE-FORM FALSE-FORM]
+     */
+    fn if_fn(&mut self, list: &[Element]) -> EvalResult
+    {
+        // builtin if function
+        let branch = try_eval!(self.if_branch(list));
+        self.eval_elem(branch)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate the test of an ``if``, and pick a branch
+     * @param list: list of elements: [BOOL TRUE-FORM FALSE-FORM]
+     * @return the unevaluated form of the chosen branch
+     */
+    fn if_branch(&mut self, list: &[Element]) -> EvalResult
+    {
+        let list_len = list.len();
+        if list_len > 3 || list_len < 2 {
+            return eval_error(format!("if: wrong number of args ({:u})", list_len));
+        }
+        let rest = list.slice_from(1);
+        let test = try_eval!(self.eval_elem(list[0].clone()));
+        if try_eval!(self.truthy("if", &test)) {
+            Ok(rest[0].clone())
+        } else if list_len > 2 {
+            Ok(rest[1].clone())
+        } else {
+            Ok(nil)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief Bind a value, allowing it to be called later
+     * @param vals      list of elements: [name value]
+     */
+    fn def(&mut self, vals: &[Element]) -> EvalResult
+    {
+        // bind a value, allowing it to be called later
+        let (flags, vals) = split_meta(vals);
+        if vals.len() != 2 {
+            eval_error(~"expected 2 args")
+        } else {
+            let name = vals[0].clone();
+            let form = try_eval!(self.eval_elem(vals[1].clone()));
+            match name {
+                Symbol(s) => {
+                    self.define(s, form, flags);
+                    Ok(nil)
+                },
+                _ => eval_error(~"first arg not of type symbol")
+            }
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief Bind a function, allowing it to be called later.
+     * @param vals      list of elements: [name [arg1 arg2 ...] form ...]
+     */
+    fn defn(&mut self, vals: &[Element]) -> EvalResult {
+        // bind a function, allowing it to be called later.
+        // Syntactic sugar for
+        //      (def NAME (fn [ARG1, ...] FORM))
+        self.define_fn(vals, false, false)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief create a fn without binding it to a variable
+     * @param vals:     list of elements: [[arg1 arg2 ...] form ...]
+     */
+    pub fn fn_nobind(&mut self, vals: &[Element]) -> EvalResult {
+        // Create a function (don't bind it to a variable)
+        if vals.len() < 2 {
+            eval_error(~"expected at least 2 args")
+        } else {
+            let form = body_form(vals.slice_from(1));
+            let args = try_eval!(parse_params(&vals[0]));
+            match check_recur(self, &form, true, ~"body of (fn ...)") {
+                Some(e) => return Err(Exception::new(e)),
+                None => ()
+            }
+            let form = self.analyze_body(args, &form);
+            let f = self.compile_body(BoundFn::new(args, form.clone()), args, &form);
+            Ok(self.close_over(f))
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief give a new fn its bytecode, if the vm is on
+     */
+    fn compile_body(&mut self, f: Element, params: &[~str], body: &Element) -> Element {
+        if !self.compiled {
+            return f;
+        }
+        match f {
+            Function(mut fptr) => {
+                fptr.code = Some(Rc::new(compile_fn(self, params, body)));
+                Function(fptr)
+            },
+            f => f
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief create a macro and bind it
+     * @param vals:     list of elements: [name [arg1 arg2 ...] form ...]
+     */
+    fn defmacro(&mut self, vals: &[Element]) -> EvalResult {
+        // Create a macro and bind it. Macros get their args
+        // unevaluated, and the form they return is evaluated
+        // in place of the call.
+        self.define_fn(vals, true, false)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief create a multimethod and bind it
+     * @param vals:     list of elements: [name dispatch-fn]
+     */
+    fn defmulti(&mut self, vals: &[Element]) -> EvalResult {
+        match vals {
+            [Symbol(ref name), ref dispatch] => {
+                let dispatch = try_eval!(self.eval_elem(dispatch.clone()));
+                self.define(name.as_slice(), Multi(MultiRef::new(name.as_slice(), dispatch)), []);
+                Ok(nil)
+            },
+            [_, _] => eval_error(~"defmulti: name must be a symbol"),
+            _ => eval_error(format!("defmulti: wrong number of args ({:u})", vals.len()))
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief add a method to a multimethod
+     * @param vals:     list of elements: [name dispatch-value [arg1 arg2 ...] form ...]
+     *
+     * A method for a dispatch value that already has one replaces it.
+     */
+    fn defmethod(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() < 4 {
+            return eval_error(format!("defmethod: wrong number of args ({:u})", vals.len()));
+        }
+        let m = match try_eval!(self.eval_elem(vals[0].clone())) {
+            Multi(m) => m,
+            _ => return eval_error(format!("defmethod: {:s} is not a multimethod",
+                                           vals[0].to_str()))
+        };
+        let value = try_eval!(self.eval_elem(vals[1].clone()));
+        let f = try_eval!(self.fn_nobind(vals.slice_from(2)));
+        m.add_method(value, f);
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief create a protocol and bind its fns
+     * @param vals:     list of elements: [Name (method [this arg ...]) ...]
+     */
+    fn defprotocol(&mut self, vals: &[Element]) -> EvalResult {
+        let name = match vals.head_opt() {
+            Some(&Symbol(ref name)) => name.clone(),
+            _ => return eval_error(~"defprotocol: name must be a symbol")
+        };
+        let mut methods: ~[~str] = ~[];
+        for sig in vals.slice_from(1).iter() {
+            let items = match *sig {
+                List(ref l) => l.to_vec(),
+                // a doc string
+                ::types::String(_) => ~[],
+                _ => return eval_error(~"defprotocol: methods must look like (name [this ...])")
+            };
+            match items.as_slice() {
+                [] => (),
+                [Symbol(ref m), Vec(ref params), ..] if params.len() > 0 => methods.push(m.clone()),
+                [Symbol(ref m), Vec(_), ..] => {
+                    return eval_error(format!("defprotocol: {:s} must take at least one arg", m));
+                },
+                _ => return eval_error(~"defprotocol: methods must look like (name [this ...])")
+            }
+        }
+        let q = qualify(self.current_ns().as_slice(), name.as_slice());
+        let protocol = ProtocolRef::new(name.as_slice(), methods.clone());
+        self.globals.borrow().with_mut(|g| {
+            g.protocols.insert(intern(q.as_slice()), protocol.clone());
+        });
+        self.define(name.as_slice(), protocol.to_map(q.as_slice()), []);
+        for m in methods.iter() {
+            self.define(m.as_slice(), protocol_fn(&protocol, m.as_slice()), []);
+        }
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief implement protocol methods
+     * @param form:     extend-type or extend-protocol, for errors
+     * @param vals:     [type Protocol (method [this ...] ...) ... Protocol ...] if by_type,
+     *                  otherwise [Protocol type (method [this ...] ...) ... type ...]
+     */
+    fn extend_fn(&mut self, form: &str, vals: &[Element], by_type: bool) -> EvalResult {
+        let (fixed_kind, other_kind) = if by_type {
+            ("type", "protocol")
+        } else {
+            ("protocol", "type")
+        };
+        let fixed = match vals.head_opt() {
+            Some(&Symbol(ref s)) => s.clone(),
+            _ => return eval_error(format!("{:s}: expected a {:s}", form, fixed_kind))
+        };
+        let mut other: Option<~str> = None;
+        for x in vals.slice_from(1).iter() {
+            let items = match *x {
+                Symbol(ref s) => {
+                    // the protocol or type the implementations after it are for
+                    other = Some(s.clone());
+                    ~[]
+                },
+                List(ref l) => l.to_vec(),
+                _ => return eval_error(format!("{:s}: expected a {:s} or an implementation",
+                                               form, other_kind))
+            };
+            if items.len() > 0 {
+                let method = match items[0] {
+                    Symbol(ref m) => m.clone(),
+                    _ => return eval_error(format!("{:s}: implementations must look like \
+                                                    (name [this ...] ...)", form))
+                };
+                let named = match other {
+                    Some(ref o) => o.clone(),
+                    None => return eval_error(format!("{:s}: expected a {:s} before {:s}",
+                                                      form, other_kind, method))
+                };
+                let f = try_eval!(self.fn_nobind(items.slice_from(1)));
+                let (protocol, type_name) = if by_type {
+                    (named, fixed.clone())
+                } else {
+                    (fixed.clone(), named)
+                };
+                // record types go by their qualified names
+                let type_name = self.record_type_name(type_name.as_slice()).unwrap_or(type_name);
+                match self.extend_protocol(protocol.as_slice(), type_name.as_slice(),
+                                           method.as_slice(), f) {
+                    Err(Exception { value: EvalError(e), .. }) => {
+                        return eval_error(format!("{:s}: {:s}", form, e));
+                    },
+                    Err(e) => return Err(e),
+                    Ok(()) => ()
+                }
+            }
+        }
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief define a record type
+     * @param vals:     list of elements: [Name [field1 field2 ...]]
+     *
+     * Binds ->Name, which takes the fields in order, map->Name, which
+     * takes a map of them, and the predicate Name?.
+     */
+    fn defrecord(&mut self, vals: &[Element]) -> EvalResult {
+        let (name, fields) = match vals {
+            [Symbol(ref name), Vec(ref fields)] => (name.clone(), fields.to_vec()),
+            [_, _] => return eval_error(~"defrecord: expected a name and a vector of fields"),
+            _ => return eval_error(format!("defrecord: wrong number of args ({:u})", vals.len()))
+        };
+        let mut params: ~[~str] = ~[];
+        for field in fields.iter() {
+            match *field {
+                Symbol(ref s) if *s != ~"&" && !params.contains(s) => params.push(s.clone()),
+                _ => return eval_error(~"defrecord: fields must be distinct symbols")
+            }
+        }
+        // the type is qualified, so records defined in other
+        // namespaces with the same name are different types
+        let q = format!("{:s}.{:s}", self.current_ns(), name);
+        let type_name = list_of(~[Symbol(~"quote"), Symbol(q.clone())]);
+        let declared: ~[Element] = params.iter().map(|p| Keyword(p.clone())).collect();
+        self.globals.borrow().with_mut(|g| g.records.insert(q.clone(), declared.clone()));
+        let keys = vec_of(declared);
+        let mut given = ElementMap::new();
+        for p in params.iter() {
+            given.insert(Keyword(p.clone()), Symbol(p.clone()));
+        }
+        let make = |m: Element| {
+            list_of(~[Symbol(qualify(CORE_NS, "record")), type_name.clone(), keys.clone(), m])
+        };
+        let is_type = list_of(~[Symbol(qualify(CORE_NS, "=")),
+                                list_of(~[Symbol(qualify(CORE_NS, "record-type")),
+                                          Symbol(~"x")]),
+                                type_name.clone()]);
+        let defs = ~[(format!("->{:s}", name), params.clone(), make(Map(given))),
+                     (format!("map->{:s}", name), ~[~"m"], make(Symbol(~"m"))),
+                     (format!("{:s}?", name), ~[~"x"], is_type)];
+        for &(ref fn_name, ref args, ref body) in defs.iter() {
+            let form = self.analyze_body(args.as_slice(), body);
+            let f = self.compile_body(BoundFn::new(args.as_slice(), form.clone()),
+                                      args.as_slice(), &form);
+            let f = self.close_over(f);
+            self.define(fn_name.as_slice(), f, []);
+        }
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief bind a function or macro to toplevel
+     * @param vals      list of elements: [name [arg1 arg2 ...] form ...]
+     * @param is_macro  whether to create a macro
+     * @param private   whether only its own namespace may use it
+     */
+    fn define_fn(&mut self, vals: &[Element], is_macro: bool, private: bool) -> EvalResult {
+        let (mut flags, vals) = split_meta(vals);
+        if private {
+            flags.push(~"private");
+        }
+        if vals.len() < 3 {
+            return eval_error(~"expected at least 3 args");
+        }
+        let name = match vals[0].clone() {
+            Symbol(s) => s,
+            _ => return eval_error(~"name must be a symbol")
+        };
+        let args = try_eval!(parse_params(&vals[1]));
+        let form = body_form(vals.slice_from(2));
+        let position = if is_macro { ~"body of (defmacro ...)" } else { ~"body of (defn ...)" };
+        match check_recur(self, &form, true, position) {
+            Some(e) => return Err(Exception::new(e)),
+            None => ()
+        }
+        let form = self.analyze_body(args, &form);
+        let f = if is_macro {
+            BoundFn::new_macro(args, form)
+        } else {
+            self.compile_body(BoundFn::new(args, form.clone()), args, &form)
+        };
+        let f = self.close_over(f);
+        self.define(name, f, flags);
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate forms in order, returning the value of the last
+     * @param vals:     list of elements: [form1 form2 ...]
+     */
+    fn do_fn(&mut self, vals: &[Element]) -> EvalResult {
+        let mut result = nil;
+        for form in vals.iter() {
+            result = try_eval!(self.eval_elem(form.clone()));
+        }
+        Ok(result)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief bind locals sequentially into a new scope
+     * @param name:     the form doing the binding, for error messages
+     * @param binds:    the binding vector: [name1 val1 name2 val2 ...]
+     * @return the new scope and the bound names, in order
+     */
+    fn bind_scope(&mut self, name: &str, binds: &Element)
+                  -> Result<(Bindings, ~[Sym]), Exception> {
+        // each binding can see the ones before it, so bind them one
+        // at a time into the new scope. A name bound twice gets a new
+        // slot, so analyzed code made in between, like a lazy-seq,
+        // still reads the old value.
+        let binds = match *binds {
+            Vec(ref v) => v.to_vec(),
+            _ => return eval_error(format!("{:s}: bindings must be a vector", name))
+        };
+        if binds.len() % 2 != 0 {
+            return eval_error(format!("{:s}: bindings must come in pairs", name));
+        }
+        let mut b = self.push();
+        let mut names: ~[Sym] = ~[];
+        for pair in binds.chunks(2) {
+            let sym = match pair[0] {
+                Symbol(ref s) => intern(s.as_slice()),
+                _ => return eval_error(format!("{:s}: binding names must be symbols", name))
+            };
+            let value = try_eval!(b.eval_elem(pair[1].clone()));
+            b.scope.borrow().insert(sym, value);
+            names.push(sym);
+        }
+        Ok((b, names))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief bind locals sequentially, then evaluate the body
+     * @param vals:     list of elements: [[name1 val1 name2 val2 ...] form ...]
+     */
+    fn let_fn(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() < 1 {
+            return eval_error(~"let: expected a binding vector");
+        }
+        let (mut b, _) = try_eval!(self.bind_scope("let", &vals[0]));
+        b.do_fn(vals.slice_from(1))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief build a form from a template, filling in unquoted parts
+     * @param vals:     list of elements: [template]
+     */
+    fn syntax_quote_fn(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() != 1 {
+            return eval_error(format!("syntax-quote: wrong number of args ({:u})", vals.len()));
+        }
+        self.syntax_quote(&vals[0])
+    }
+    #[allow(dead_code)]
+    fn syntax_quote(&mut self, form: &Element) -> EvalResult {
+        match *form {
+            List(ref l) => {
+                if l.len() == 2 && l[0] == Symbol(~"unquote") {
+                    return self.eval_elem(l[1].clone());
+                }
+                Ok(list_of(try_eval!(self.syntax_quote_seq(l.to_vec()))))
+            },
+            Vec(ref v) => Ok(vec_of(try_eval!(self.syntax_quote_seq(v.to_vec())))),
+            _ => Ok(form.clone())
+        }
+    }
+    #[allow(dead_code)]
+    fn syntax_quote_seq(&mut self, items: &[Element]) -> Result<~[Element], Exception> {
+        let mut out: ~[Element] = ~[];
+        for item in items.iter() {
+            match *item {
+                List(ref l) if l.len() == 2 && l[0] == Symbol(~"unquote-splicing") => {
+                    match try_eval!(self.eval_elem(l[1].clone())) {
+                        List(spliced) => out.push_all_move(spliced.to_vec()),
+                        Vec(spliced) => out.push_all_move(spliced.to_vec()),
+                        nil => (),
+                        _ => return eval_error(~"unquote-splicing: value is not a collection")
+                    }
+                },
+                _ => out.push(try_eval!(self.syntax_quote(item)))
+            }
+        }
+        Ok(out)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief switch to a namespace, and set up what it requires
+     * @param vals:     list of elements: [name (:require spec...)...]
+     */
+    fn ns_fn(&mut self, vals: &[Element]) -> EvalResult {
+        let name = match vals.head_opt() {
+            Some(&Symbol(ref name)) => name.clone(),
+            _ => return eval_error(~"ns: expected a namespace name")
+        };
+        self.in_ns(name);
+        for clause in vals.slice_from(1).iter() {
+            match *clause {
+                List(ref l) if l.len() > 0 && l[0] == Keyword(~"require") => {
+                    for spec in l.iter().skip(1) {
+                        try_eval!(self.require(spec));
+                    }
+                },
+                _ => return eval_error(~"ns: unknown clause")
+            }
+        }
+        Ok(nil)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief give dynamic vars new values for the extent of a body
+     * @param vals:     list of elements: [[name value ...] body...]
+     *
+     * Functions called from the body see the new values too. The body
+     * runs with a new dynamic frame, so the old values are back once it
+     * returns or throws.
+     */
+    fn binding_fn(&mut self, vals: &[Element]) -> EvalResult {
+        let binds = match vals.head_opt() {
+            Some(&Vec(ref v)) => v.to_vec(),
+            _ => return eval_error(~"binding: expected a binding vector")
+        };
+        if binds.len() % 2 != 0 {
+            return eval_error(~"binding: bindings must come in pairs");
+        }
+        // the values are all evaluated before any are bound
+        let frame = Env::new(self.dynamic.clone());
+        for pair in binds.chunks(2) {
+            let name = match pair[0] {
+                Symbol(ref s) => s.clone(),
+                _ => return eval_error(~"binding: binding names must be symbols")
+            };
+            let q = match self.resolve(name) {
+                Some(q) if self.is_dynamic(q.as_slice()) => q,
+                _ => return eval_error(format!("binding: can't rebind non-dynamic var {:s}", name))
+            };
+            frame.borrow().insert(intern(q.as_slice()), try_eval!(self.eval_elem(pair[1].clone())));
+        }
+        // no new frame for locals, so the body's locals are where
+        // analyze expects them
+        let mut b = self.with_scope(self.scope.clone());
+        b.dynamic = Some(frame);
+        b.eval_elem(body_form(vals.slice_from(1)))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief throw a value, unwinding until a ``try`` catches it
+     * @param vals:     list of elements: [value]
+     */
+    fn throw_fn(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() != 1 {
+            return eval_error(format!("throw: wrong number of args ({:u})", vals.len()));
+        }
+        Err(Exception::new(try_eval!(self.eval_elem(vals[0].clone()))))
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate a body, handling anything it throws
+     * @param vals:     list of elements: [form ... (catch name form ...) (finally form ...)]
+     *
+     * The finally forms run however the body exits, but their value is
+     * discarded unless they throw.
+     */
+    fn try_fn(&mut self, vals: &[Element]) -> EvalResult {
+        // split the body from the catch and finally clauses, which
+        // have to come last and in that order
+        let mut body: ~[Element] = ~[];
+        let mut catch_clause: Option<~[Element]> = None;
+        let mut finally_clause: Option<~[Element]> = None;
+        for form in vals.iter() {
+            match (clause_of(form, "catch"), clause_of(form, "finally")) {
+                (Some(c), _) => {
+                    if catch_clause.is_some() || finally_clause.is_some() {
+                        return eval_error(~"try: catch must come once, before finally");
+                    }
+                    match c.head_opt() {
+                        Some(&Symbol(_)) => (),
+                        _ => return eval_error(~"catch: expected a name to bind")
+                    }
+                    catch_clause = Some(c);
+                },
+                (_, Some(f)) => {
+                    if finally_clause.is_some() {
+                        return eval_error(~"try: only one finally allowed");
+                    }
+                    finally_clause = Some(f);
+                },
+                _ => {
+                    if catch_clause.is_some() || finally_clause.is_some() {
+                        return eval_error(~"try: body forms must come before catch and finally");
+                    }
+                    body.push(form.clone());
+                }
+            }
+        }
+        let result = match self.do_fn(body) {
+            Err(thrown) => match catch_clause {
+                Some(c) => {
+                    let name = match c[0] {
+                        Symbol(ref s) => s.clone(),
+                        _ => fail!("catch binding was checked above")
+                    };
+                    let mut b = self.push();
+                    b.insert(name, thrown.value);
+                    b.do_fn(c.slice_from(1))
+                },
+                None => Err(thrown)
+            },
+            ok => ok
+        };
+        match finally_clause {
+            Some(f) => { try_eval!(self.do_fn(f)); },
+            None => ()
+        }
+        result
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief the trace of the last exception that escaped to the top level
+     * @param vals:     list of elements: []
+     */
+    fn stacktrace_fn(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() != 0 {
+            return eval_error(format!("stacktrace: wrong number of args ({:u})", vals.len()));
+        }
+        match self.last_error {
+            Some(ref ex) => Ok(ex.trace_elem()),
+            None => Ok(nil)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief bind locals, then evaluate the body until it stops recurring
+     * @param vals:     list of elements: [[name1 val1 name2 val2 ...] form ...]
+     */
+    fn loop_fn(&mut self, vals: &[Element]) -> EvalResult {
+        if vals.len() < 1 {
+            return eval_error(~"loop: expected a binding vector");
+        }
+        let body = body_form(vals.slice_from(1));
+        match check_recur(self, &body, true, ~"body of (loop ...)") {
+            Some(e) => return Err(Exception::new(e)),
+            None => ()
+        }
+        let (b, names) = try_eval!(self.bind_scope("loop", &vals[0]));
+        self.trampoline(b, names, body)
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate a loop or fn body until it stops calling or recurring
+     * @param frame:    the scope the body runs in, pushed from self
+     * @param params:   the names that recur rebinds
+     * @param body:     the body form
+     *
+     * recur binds the params, in order, in a new frame in place of
+     * the old one, so they land in the slots analyze gave them. A call
+     * in tail position replaces the frame with a new one pushed from
+     * self. Neither grows the stack or the scope chain.
+     */
+    fn trampoline(&self, frame: Bindings, params: ~[Sym], body: Element) -> EvalResult {
+        let mut frame = frame;
+        let mut params = params;
+        let mut body = body;
+        // a tail call replaces the frame of its caller, so only the
+        // latest one shows up in a trace
+        let mut tail_call: Option<Element> = None;
+        loop {
+            let step = match frame.eval_tail(body.clone()) {
+                Ok(step) => step,
+                Err(e) => return match tail_call {
+                    Some(ref call) => Err(e.unwound_through(call)),
+                    None => Err(e)
+                }
+            };
+            match step {
+                Done(e) => return Ok(e),
+                Recur(args) => {
+                    if args.len() != params.len() {
+                        let msg = format!("recur: expected {:u} args, got {:u}",
+                                          params.len(), args.len());
+                        return eval_error(msg);
+                    }
+                    frame = frame.renew();
+                    for (param, arg) in params.iter().zip(args.move_iter()) {
+                        frame.scope.borrow().insert(*param, arg);
+                    }
+                },
+                Call(f, args, call) => {
+                    let mut b = self.with_scope(self.fn_scope(f));
+                    params = try_call!(b.bind_args(f, args), &call);
+                    frame = b;
+                    body = f.f.clone();
+                    tail_call = Some(call);
+                }
+            }
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate a form that is in tail position
+     * @param form:     the form to evaluate
+     *
+     * Follows ``if``, ``do`` and ``let`` down to the form in tail
+     * position. A ``recur`` there hands back its evaluated args, and
+     * a call to a bound fn hands back the fn and its evaluated args,
+     * so the caller can run them without nesting another eval.
+     */
+    fn eval_tail(&mut self, form: Element) -> Result<Tail, Exception> {
+        let l = match form {
+            List(ref l) if l.len() > 0 => l.to_vec(),
+            _ => return Ok(Done(try_eval!(self.eval_elem(form.clone()))))
+        };
+        let vals = l.slice_from(1);
+        let (head, kind) = match l[0] {
+            Symbol(ref s) => (s.as_slice(), special_form(s.as_slice())),
+            _ => ("", None)
+        };
+        match kind {
+            Some(special::Recur) => return Ok(Recur(try_eval!(self.eval_args(vals)))),
+            Some(special::If) => {
+                let branch = try_eval!(self.if_branch(vals));
+                return self.eval_tail(branch);
+            },
+            Some(special::Do) if vals.len() > 0 => {
+                let last = vals.len() - 1;
+                try_eval!(self.do_fn(vals.slice_to(last)));
+                return self.eval_tail(vals[last].clone());
+            },
+            Some(special::Let) if vals.len() > 0 => {
+                let (mut b, _) = try_eval!(self.bind_scope("let", &vals[0]));
+                return b.eval_tail(body_form(vals.slice_from(1)));
+            },
+            Some(other) => {
+                match self.conditional_tail(other, head, vals) {
+                    Some(result) => return result,
+                    None => ()
+                }
+                match self.eval_special(other, head, vals) {
+                    Some(result) => return Ok(Done(try_eval!(result))),
+                    None => ()
+                }
+            },
+            None => ()
+        }
+        let f = try_eval!(self.eval_elem(l[0].clone()));
+        match f {
+            Function(ref fptr) if fptr.is_macro => {
+                // the expansion takes the place of the call, so
+                // it is still in tail position
+                let expansion = try_call!(self.expand_macro(&**fptr, vals), &form);
+                return self.eval_tail(expansion);
+            },
+            _ => ()
+        }
+        let args = try_eval!(self.eval_args(vals));
+        match f {
+            Function(fptr) => Ok(Call(fptr, args, form.clone())),
+            _ => Ok(Done(try_call!(self.apply(f, args), &form)))
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate the conditional forms built on ``if``
+     * @param form:     which special form it is
+     * @param head:     name of the form
+     * @param vals:     the form's arguments
+     *
+     * Only the tests that are needed get evaluated, and the chosen
+     * branch is returned unevaluated so it stays in tail position.
+     * Returns None if form isn't a conditional form.
+     */
+    fn conditional_tail(&mut self, form: Special, head: &str, vals: &[Element])
+        -> Option<Result<Tail, Exception>>
+    {
+        match form {
+            special::And | special::Or => {
+                // and stops at the first false value, or at the first true one
+                let stop_on = form == special::Or;
+                if vals.len() == 0 {
+                    return Some(Ok(Done(if stop_on { nil } else { Boolean(true) })));
+                }
+                let last = vals.len() - 1;
+                for x in vals.slice_to(last).iter() {
+                    let value = try_some!(self.eval_elem(x.clone()));
+                    if try_some!(self.truthy(head, &value)) == stop_on {
+                        return Some(Ok(Done(value)));
+                    }
+                }
+                Some(self.eval_tail(vals[last].clone()))
+            },
+            special::When | special::WhenNot => {
+                if vals.len() == 0 {
+                    return Some(eval_error(format!("{:s}: expected a test", head)));
+                }
+                let test = try_some!(self.eval_elem(vals[0].clone()));
+                if try_some!(self.truthy(head, &test)) == (form == special::When) {
+                    Some(self.eval_tail(body_form(vals.slice_from(1))))
+                } else {
+                    Some(Ok(Done(nil)))
+                }
+            },
+            special::Cond => {
+                if vals.len() % 2 != 0 {
+                    return Some(eval_error(~"cond: expected an even number of forms"));
+                }
+                for clause in vals.chunks(2) {
+                    let pass = match clause[0] {
+                        Keyword(ref k) if *k == ~"else" => true,
+                        ref test => {
+                            let value = try_some!(self.eval_elem(test.clone()));
+                            try_some!(self.truthy(head, &value))
+                        }
+                    };
+                    if pass {
+                        return Some(self.eval_tail(clause[1].clone()));
+                    }
+                }
+                Some(Ok(Done(nil)))
+            },
+            special::Case => {
+                if vals.len() == 0 {
+                    return Some(eval_error(~"case: expected an expression"));
+                }
+                let value = try_some!(self.eval_elem(vals[0].clone()));
+                let clauses = vals.slice_from(1);
+                for clause in clauses.chunks(2).filter(|c| c.len() == 2) {
+                    // constants are not evaluated; a list groups several of them
+                    let matches = match clause[0] {
+                        List(ref options) => options.iter().any(|o| *o == value),
+                        ref constant => *constant == value
+                    };
+                    if matches {
+                        return Some(self.eval_tail(clause[1].clone()));
+                    }
+                }
+                if clauses.len() % 2 == 1 {
+                    Some(self.eval_tail(clauses[clauses.len() - 1].clone()))
+                } else {
+                    Some(eval_error(format!("case: no clause matching {:s}", value.to_str())))
+                }
+            },
+            special::IfLet | special::WhenLet => {
+                let is_if = form == special::IfLet;
+                if vals.len() < 1 || (is_if && (vals.len() < 2 || vals.len() > 3)) {
+                    return Some(eval_error(format!("{:s}: wrong number of args ({:u})",
+                                                   head, vals.len())));
+                }
+                let (name, test) = match vals[0] {
+                    Vec(ref v) if v.len() == 2 => match v[0] {
+                        Symbol(ref name) => (name.clone(), v[1].clone()),
+                        _ => return Some(eval_error(format!("{:s}: expected a symbol to bind",
+                                                            head)))
+                    },
+                    _ => return Some(eval_error(format!(
+                        "{:s}: expected a binding vector [name test]", head)))
+                };
+                let value = try_some!(self.eval_elem(test));
+                if try_some!(self.truthy(head, &value)) {
+                    let mut b = self.push();
+                    b.insert(name, value);
+                    let body = if is_if { vals[1].clone() } else { body_form(vals.slice_from(1)) };
+                    Some(b.eval_tail(body))
+                } else if is_if && vals.len() == 3 {
+                    Some(self.eval_tail(vals[2].clone()))
+                } else {
+                    Some(Ok(Done(nil)))
+                }
+            },
+            _ => None
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief decide whether a test passed
+     * @param name:     name of the form doing the test, for errors
+     * @param value:    the evaluated test
+     *
+     * Only nil and false fail, unless strict booleans are on, in which
+     * case anything that isn't a Boolean is an error.
+     */
+    pub fn truthy(&self, name: &str, value: &Element) -> Result<bool, Exception> {
+        match *value {
+            Boolean(b) => Ok(b),
+            _ if self.strict_booleans => eval_error(format!("{:s}: test must be boolean", name)),
+            nil => Ok(false),
+            _ => Ok(true)
+        }
+    }
+    #[allow(dead_code)]
+    /**
+     * @brief evaluate each arg of a call, stopping at the first error
+     */
+    fn eval_args(&mut self, vals: &[Element]) -> Result<~[Element], Exception> {
+        let mut args: ~[Element] = ~[];
+        for x in vals.iter() {
+            args.push(try_eval!(self.eval_elem(x.clone())));
+        }
+        Ok(args)
+    }
+}
+
+/**
+ * @brief make sure ``recur`` only appears in tail position
+ * @param form:     the form to check
+ * @param tail:     whether form is in tail position
+ * @param position: description of where form is, for error messages
+ *
+ * Bodies of nested ``fn`` and ``loop`` forms are their own recur
+ * targets, and are checked when they are evaluated. Macro calls are
+ * expanded first, so a recur a macro puts in tail position is fine.
+ */
+pub fn check_recur(b: &mut Bindings, form: &Element, tail: bool, position: ~str)
+    -> Option<Element>
+{
+    let l = match *form {
+        List(ref l) if l.len() > 0 => l.to_vec(),
+        Vec(ref v) => {
+            for x in v.iter() {
+                match check_recur(b, x, false, position.clone()) {
+                    Some(e) => return Some(e),
+                    None => ()
+                }
+            }
+            return None;
+        },
+        _ => return None
+    };
+    // loop bodies are checked after analyze has resolved their calls
+    let head = match l[0] {
+        Symbol(ref s) | Local(ref s, _, _, _) | Interned(ref s, _) => s.as_slice(),
+        _ => ""
+    };
+    let kind = special_form(head);
+    let vals = l.slice_from(1);
+    // pick out which args inherit the tail position
+    let tail_from = match kind {
+        Some(special::Recur) => {
+            if !tail {
+                return Some(EvalError(format!("recur: not in tail position ({:s})", position)));
+            }
+            vals.len()
+        },
+        Some(special::Fn) | Some(special::Defn) | Some(special::DefnPrivate)
+            | Some(special::Defmacro) | Some(special::SyntaxQuote) | Some(special::Quote)
+            | Some(special::LazySeq) => return None,
+        Some(special::Loop) => {
+            // only the bindings belong to the enclosing target
+            return match vals.head_opt() {
+                Some(binds) => check_recur(b, binds, false, ~"bindings of (loop ...)"),
+                None => None
+            };
+        },
+        Some(special::If) | Some(special::IfLet) => if tail { 1 } else { vals.len() },
+        Some(special::Do) | Some(special::Let) | Some(special::And) | Some(special::Or)
+            | Some(special::When) | Some(special::WhenNot) | Some(special::WhenLet) => {
+            if tail && vals.len() > 0 { vals.len() - 1 } else { vals.len() }
+        },
+        Some(special::Cond) => {
+            // tests are never in tail position, results inherit it
+            for (i, x) in vals.iter().enumerate() {
+                let pos = if i % 2 == 0 {
+                    format!("test {:u} of (cond ...)", i / 2 + 1)
+                } else {
+                    format!("argument {:u} of (cond ...)", i + 1)
+                };
+                match check_recur(b, x, tail && i % 2 == 1, pos) {
+                    Some(e) => return Some(e),
+                    None => ()
+                }
+            }
+            return None;
+        },
+        Some(special::Case) => {
+            // the constants are not evaluated, so only check the results
+            let clauses = vals.slice_from(if vals.len() > 0 { 1 } else { 0 });
+            for (i, x) in clauses.iter().enumerate() {
+                if i % 2 == 1 || i == clauses.len() - 1 {
+                    let pos = format!("argument {:u} of (case ...)", i + 2);
+                    match check_recur(b, x, tail, pos) {
+                        Some(e) => return Some(e),
+                        None => ()
+                    }
+                }
+            }
+            return match vals.head_opt() {
+                Some(expr) => check_recur(b, expr, false, ~"test of (case ...)"),
+                None => None
+            };
+        },
+        _ => {
+            match l[0] {
+                Symbol(_) => match b.macroexpand_1(form) {
+                    Ok(Some(expansion)) => return check_recur(b, &expansion, tail, position),
+                    // errors are left for when the form is evaluated
+                    _ => ()
+                },
+                _ => ()
+            }
+            vals.len()
+        }
+    };
+    for (i, x) in vals.iter().enumerate() {
+        let pos = match kind {
+            Some(special::If) | Some(special::When) | Some(special::WhenNot) if i == 0 => {
+                format!("test of ({:s} ...)", head)
+            },
+            Some(special::Let) | Some(special::IfLet) | Some(special::WhenLet) if i == 0 => {
+                format!("bindings of ({:s} ...)", head)
+            },
+            Some(special::Recur) => format!("argument {:u} of (recur ...)", i + 1),
+            Some(special::Do) | Some(special::Let) => {
+                format!("non-final form {:u} of ({:s} ...)", i + 1, head)
+            },
+            _ if head == "" => format!("element {:u} of a call", i + 2),
+            _ => format!("argument {:u} of ({:s} ...)", i + 1, head)
+        };
+        match check_recur(b, x, i >= tail_from, pos) {
+            Some(e) => return Some(e),
+            None => ()
+        }
+    }
+    if head == "" {
+        return check_recur(b, &l[0], false, ~"function position of a call");
+    }
+    None
+}
+
+
+/**
+ * @brief a form with the symbols analyze resolved made plain again
+ */
+fn unanalyzed(form: &Element) -> Element
+{
+    match *form {
+        Local(ref s, _, _, _) | Interned(ref s, _) => Symbol(s.clone()),
+        List(ref l) => list_of(l.iter().map(|x| unanalyzed(x)).collect()),
+        Vec(ref v) => vec_of(v.iter().map(|x| unanalyzed(x)).collect()),
+        Map(ref m) => {
+            Map(ElementMap::from_pairs(m.iter().map(|&(ref k, ref v)| {
+                (unanalyzed(k), unanalyzed(v))
+            }).collect()))
+        },
+        _ => form.clone()
+    }
+}
+
+/**
+ * @brief whether the reader made a record literal anywhere in a form
+ */
+fn has_record(form: &Element) -> bool
+{
+    match *form {
+        Record(..) => true,
+        List(ref l) => l.iter().any(|x| has_record(x)),
+        Vec(ref v) => v.iter().any(|x| has_record(x)),
+        Map(ref m) => m.iter().any(|&(ref k, ref v)| has_record(k) || has_record(v)),
+        _ => false
+    }
+}
+
+/**
+ * @brief mark a fn as resolving its names in a namespace
+ *
+ * Other values are returned as they are.
+ */
+pub fn defined_in(f: Element, ns: Sym) -> Element
+{
+    match f {
+        Function(mut fptr) => {
+            fptr.ns = Some(ns);
+            Function(fptr)
+        },
+        other => other
+    }
+}
+
+/**
+ * @brief split the ``^:flag`` metadata off the front of a def
+ * @return the flag names, and the rest of the def
+ */
+fn split_meta<'a>(vals: &'a [Element]) -> (~[~str], &'a [Element])
+{
+    let mut flags: ~[~str] = ~[];
+    let mut rest = vals;
+    loop {
+        match rest.head_opt() {
+            Some(&Symbol(ref s)) if s.starts_with("^:") => flags.push(s.slice_from(2).to_owned()),
+            _ => break
+        }
+        rest = rest.slice_from(1);
+    }
+    (flags, rest)
+}
+
+
+/**
+ * @brief read the param names out of a fn's arg vector
+ * @param args:     the arg vector: [arg1 arg2 ... & rest]
+ */
+fn parse_params(args: &Element) -> Result<~[~str], Exception>
+{
+    let args_v = match *args {
+        Vec(ref v) => v,
+        _ => return eval_error(~"args must be in a vector")
+    };
+    let mut names: ~[~str] = ~[];
+    for i in args_v.iter() {
+        match *i {
+            Symbol(ref s) => names.push(s.clone()),
+            _ => return eval_error(~"args must be symbols")
+        }
+    }
+    match names.position_elem(&~"&") {
+        Some(i) if i + 2 != names.len() =>
+            eval_error(~"& must be followed by exactly one arg"),
+        _ => Ok(names)
+    }
+}
+
+
+/**
+ * @brief pick apart a clause of a ``try``, like (catch e ...)
+ * @param form:     the form that might be a clause
+ * @param name:     the kind of clause to look for
+ * @return the rest of the clause, if form is that kind of clause
+ */
+fn clause_of(form: &Element, name: &str) -> Option<~[Element]>
+{
+    match *form {
+        List(ref l) if l.len() > 0 && l[0] == Symbol(name.to_owned()) =>
+            Some(l.iter().skip(1).map(|x| x.clone()).collect()),
+        _ => None
+    }
+}
+
+
+/**
+ * @brief where a local will be found
+ * @param scope:    the names bound in each frame, innermost last
+ * @param name:     the local
+ * @return how many frames out it is, and its slot there
+ */
+fn find_local(scope: &[~[~str]], name: &str) -> Option<(uint, uint)>
+{
+    for (depth, frame) in scope.rev_iter().enumerate() {
+        match frame.iter().rposition(|n| n.as_slice() == name) {
+            Some(slot) => return Some((depth, slot)),
+            None => ()
+        }
+    }
+    None
+}
+
+/**
+ * @brief wrap a function body in an implicit ``do``
+ * @param forms:    the body forms of a fn, defn or let
+ */
+pub fn body_form(forms: &[Element]) -> Element
+{
+    match forms.len() {
+        0 => nil,
+        1 => forms[0].clone(),
+        _ => list_of(~[Symbol(~"do")] + forms)
+    }
+}
+
+
+#[allow(dead_code)]
+pub fn eval(s: &str) -> Element
+{
+    // one-off eval function. Good if you just need a
+    // one-liner. Anything thrown comes back as the value, and a lazy
+    // seq is realized, since nothing could realize it afterwards.
+    let mut bindings = Bindings::new();
+    let value = match bindings.eval(s) {
+        Ok(e) => bindings.realize_all(e),
+        Err(e) => return e
+    };
+    match value {
+        Ok(e) => e,
+        Err(ex) => ex.value
+    }
+}
+
+// set in a test's task to make every Bindings it makes compiled
+#[cfg(test)]
+local_data_key!(COMPILED_DEFAULT: bool)
+
+/**
+ * @brief make every Bindings this task makes from now on compiled
+ */
+#[cfg(test)]
+pub fn compile_by_default()
+{
+    local_data::set(COMPILED_DEFAULT, true);
+}
+
+#[cfg(test)]
+fn compiled_by_default() -> bool
+{
+    local_data::get(COMPILED_DEFAULT, |compiled| compiled.map_or(false, |c| *c))
+}
+
+#[cfg(not(test))]
+fn compiled_by_default() -> bool
+{
+    false
+}
+
+
+
+#[test]
+fn test_basic_eval() {
+    assert!(eval("1") == ::types::Number(1));
+    assert!(eval("1\n") == ::types::Number(1));
+    assert!(eval("") == ::types::nil);
+    assert!(eval("\n") == ::types::nil);
+    assert!(eval("()") == list_of(~[]));
+    assert!(eval("()\n") == list_of(~[]));
+    assert!(eval("[]") == vec_of(~[]));
+    assert!(eval("\"\"") == ::types::String(~""));
+    assert!(eval("\"test string\"") == ::types::String(~"test string"));
     assert!(eval("\"(+ 1 1)\"") == ::types::String(~"(+ 1 1)"));
-    assert!(eval("[(+ 1 1)]") == Vec(~[::types::Number(2)]));
+    assert!(eval("[(+ 1 1)]") == vec_of(~[::types::Number(2)]));
 }
 
 #[test]
@@ -319,15 +2689,15 @@ fn test_def() {
     let mut bindings = Bindings::new();
     // basic assignment
     bindings.eval("(def a 5)");
-    assert!(bindings.eval("a") == ::types::Number(5));
-    assert!(bindings.eval("(+ a 5)") == ::types::Number(10));
-    assert!(bindings.eval("(inc a)") == ::types::Number(6));
+    assert!(bindings.eval("a") == Ok(::types::Number(5)));
+    assert!(bindings.eval("(+ a 5)") == Ok(::types::Number(10)));
+    assert!(bindings.eval("(inc a)") == Ok(::types::Number(6)));
     // test eager evaluation of form
     bindings.eval("(def x (+ 5 6))");
-    assert!(bindings.eval("x") == ::types::Number(11));
+    assert!(bindings.eval("x") == Ok(::types::Number(11)));
     // check for weird self-assign conditions
     bindings.eval("(def a (+ a 1))");
-    assert!(bindings.eval("a") == ::types::Number(6));
+    assert!(bindings.eval("a") == Ok(::types::Number(6)));
 }
 
 #[test]
@@ -337,19 +2707,659 @@ fn test_fn() {
     assert!(eval("((fn [x] (+ x 5)) 6)") == ::types::Number(11));
     // bind fn to var and test
     bindings.eval("(def f (fn [x] (+ x 1)))");
-    assert!(bindings.eval("(f 5)") == ::types::Number(6));
+    assert!(bindings.eval("(f 5)") == Ok(::types::Number(6)));
     // Test function recursion
     bindings.eval("(def fac (fn [x] (if (= x 0) 1 (* x (fac (dec x))))))");
-    assert!(bindings.eval("(fac 5)") == ::types::Number(120));
+    assert!(bindings.eval("(fac 5)") == Ok(::types::Number(120)));
 }
 
 #[test]
 fn test_defn() {
     let mut bindings = Bindings::new();
     bindings.eval("(defn plus [x y] (+ x y))");
-    assert!(bindings.eval("(plus 5 6)") == ::types::Number(11));
+    assert!(bindings.eval("(plus 5 6)") == Ok(::types::Number(11)));
     bindings.eval("(defn plus [x] (+ x 1))");
-    assert!(bindings.eval("(plus 5)") == ::types::Number(6));
+    assert!(bindings.eval("(plus 5)") == Ok(::types::Number(6)));
+}
+
+
+
+#[test]
+fn test_do() {
+    assert!(eval("(do)") == ::types::nil);
+    assert!(eval("(do 1)") == ::types::Number(1));
+    assert!(eval("(do 1 2 (+ 1 2))") == ::types::Number(3));
+    let mut bindings = Bindings::new();
+    assert!(bindings.eval("(do (def a 1) (def b 2) (+ a b))") == Ok(::types::Number(3)));
+    assert!(bindings.eval("a") == Ok(::types::Number(1)));
+}
+
+#[test]
+fn test_let() {
+    assert!(eval("(let [a 1] a)") == ::types::Number(1));
+    assert!(eval("(let [a 1 b (+ a 1)] b)") == ::types::Number(2));
+    assert!(eval("(let [a 1 b 2] (+ a b) (* a b))") == ::types::Number(2));
+    assert!(eval("(let [] 5)") == ::types::Number(5));
+    assert!(eval("(let [a 1] (let [a 2] a))") == ::types::Number(2));
+    assert!(eval("(let [a 1 b] a)") == EvalError(~"let: bindings must come in pairs"));
+    assert!(eval("(let (a 1) a)") == EvalError(~"let: bindings must be a vector"));
+    // locals don't leak out of the let
+    let mut bindings = Bindings::new();
+    bindings.eval("(let [zz 1] zz)");
+    assert!(bindings.eval("zz") == Err(EvalError(~"Symbol Not defined")));
+}
+
+#[test]
+fn test_fn_body() {
+    assert!(eval("((fn [x] 1 (+ x 1)) 5)") == ::types::Number(6));
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn f [x] (def seen x) (* x 2))");
+    assert!(bindings.eval("(f 4)") == Ok(::types::Number(8)));
+    assert!(bindings.eval("seen") == Ok(::types::Number(4)));
+    bindings.eval("(defn g [x] (let [y (inc x)] (* y y)))");
+    assert!(bindings.eval("(g 2)") == Ok(::types::Number(9)));
+}
+
+#[test]
+fn test_loop() {
+    assert!(eval("(loop [i 0] (if (= i 10) i (recur (inc i))))") == ::types::Number(10));
+    assert!(eval("(loop [i 5 acc 1] (if (= i 0) acc (recur (dec i) (* acc i))))")
+            == ::types::Number(120));
+    // recur inside a let and a do is still in tail position
+    assert!(eval("(loop [i 0] (let [j (inc i)] (do 1 (if (= j 3) j (recur j)))))")
+            == ::types::Number(3));
+    // enough iterations to overflow the stack without recur
+    assert!(eval("(loop [i 100000] (if (= i 0) 0 (recur (dec i))))") == ::types::Number(0));
+    assert!(eval("(loop [i 0] (if (= i 1) i (recur)))")
+            == EvalError(~"recur: expected 1 args, got 0"));
+}
+
+#[test]
+fn test_fn_recur() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn count-down [x] (if (= x 0) true (recur (dec x))))");
+    assert!(bindings.eval("(count-down 100000)") == Ok(::types::Boolean(true)));
+    assert!(eval("((fn [x acc] (if (= x 0) acc (recur (dec x) (+ acc x)))) 4 0)")
+            == ::types::Number(10));
+}
+
+#[test]
+fn test_recur_position() {
+    assert!(eval("(recur 1)") == EvalError(~"recur: can only be used inside loop or fn"));
+    assert!(eval("(loop [i 0] (+ 1 (recur i)))")
+            == EvalError(~"recur: not in tail position (argument 2 of (+ ...))"));
+    assert!(eval("(loop [i 0] (if (recur i) 1 2))")
+            == EvalError(~"recur: not in tail position (test of (if ...))"));
+    assert!(eval("(loop [i 0] (do (recur i) 1))")
+            == EvalError(~"recur: not in tail position (non-final form 1 of (do ...))"));
+    assert!(eval("(fn [x] (recur x) x)")
+            == EvalError(~"recur: not in tail position (non-final form 1 of (do ...))"));
+    // macros are expanded before the check
+    assert!(eval("(loop [i 0] (if-not (= i 3) (recur (inc i)) i))") == ::types::Number(3));
+    assert!(eval("(loop [i 0] (if-not (recur i) 1 2))")
+            == EvalError(~"recur: not in tail position (test of (if ...))"));
+}
+
+#[test]
+fn test_tail_calls() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn countdown [x] (if (= x 0) true (countdown (dec x))))");
+    assert!(bindings.eval("(countdown 1000000)") == Ok(::types::Boolean(true)));
+    // tail position inside let and do
+    bindings.eval("(defn countdown2 [x] (let [y (dec x)] (do 1 (if (= y 0) y (countdown2 y)))))");
+    assert!(bindings.eval("(countdown2 100000)") == Ok(::types::Number(0)));
+    // mutual recursion
+    bindings.eval("(defn my-even [x] (if (= x 0) true (my-odd (dec x))))");
+    bindings.eval("(defn my-odd [x] (if (= x 0) false (my-even (dec x))))");
+    assert!(bindings.eval("(my-even 100001)") == Ok(::types::Boolean(false)));
+    assert!(bindings.eval("(my-odd 100001)") == Ok(::types::Boolean(true)));
+}
+
+#[test]
+fn test_apply_evaluated_args() {
+    // args are only evaluated once, so a list value isn't re-evaluated as a form
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn ident [x] x)");
+    assert!(bindings.eval("(ident (concat [1] [2]))")
+            == Ok(list_of(~[::types::Number(1), ::types::Number(2)])));
+    assert!(bindings.eval("(= (concat [1] [2]) (concat [1 2]))") == Ok(Boolean(true)));
+}
+
+#[test]
+fn test_variadic_fn() {
+    assert!(eval("((fn [& xs] xs) 1 2 3)")
+            == list_of(~[::types::Number(1), ::types::Number(2), ::types::Number(3)]));
+    assert!(eval("((fn [x & xs] xs) 1)") == list_of(~[]));
+    assert!(eval("((fn [x y] x) 1)") == EvalError(~"wrong number of args (1) passed to fn"));
+    assert!(eval("((fn [x] x) 1 2)") == EvalError(~"wrong number of args (2) passed to fn"));
+    assert!(eval("(fn [x &] x)") == EvalError(~"& must be followed by exactly one arg"));
+}
+
+#[test]
+fn test_syntax_quote() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def a 5)");
+    bindings.eval("(def xs [1 2])");
+    assert!(bindings.eval("`a") == Ok(Symbol(~"a")));
+    assert!(bindings.eval("`(+ a ~a)")
+            == Ok(list_of(~[Symbol(~"+"), Symbol(~"a"), ::types::Number(5)])));
+    assert!(bindings.eval("`(+ ~@xs)")
+            == Ok(list_of(~[Symbol(~"+"), ::types::Number(1), ::types::Number(2)])));
+    assert!(bindings.eval("`[~(+ a 1)]") == Ok(vec_of(~[::types::Number(6)])));
+    assert!(bindings.eval("~a") == Err(EvalError(~"unquote: can only be used inside syntax-quote")));
+}
+
+#[test]
+fn test_defmacro() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defmacro unless [test & body] `(if ~test nil (do ~@body)))");
+    assert!(bindings.eval("(unless false 1 2)") == Ok(::types::Number(2)));
+    assert!(bindings.eval("(unless true 1 2)") == Ok(nil));
+    // args are not evaluated before the macro sees them
+    bindings.eval("(defmacro first-form [x & more] `(quote-ish ~x))");
+    assert!(bindings.eval("(macroexpand-1 `(first-form (+ 1 2)))")
+            == Ok(list_of(~[Symbol(~"quote-ish"),
+                         list_of(~[Symbol(~"+"), ::types::Number(1), ::types::Number(2)])])));
+    // macro expansions in tail position don't grow the stack
+    bindings.eval("(defmacro my-if [t a b] `(if ~t ~a ~b))");
+    bindings.eval("(defn countdown [x] (my-if (= x 0) 0 (countdown (dec x))))");
+    assert!(bindings.eval("(countdown 100000)") == Ok(::types::Number(0)));
+}
+
+#[test]
+fn test_not_if_not() {
+    assert!(eval("(not true)") == Boolean(false));
+    assert!(eval("(not (= 1 2))") == Boolean(true));
+    assert!(eval("(if-not (= 1 2) 1 2)") == ::types::Number(1));
+    assert!(eval("(if-not (= 1 1) 1 2)") == ::types::Number(2));
+    assert!(eval("(if-not (= 1 1) 1)") == nil);
+}
+
+#[test]
+fn test_macroexpand() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defmacro my-not [x] `(not ~x))");
+    assert!(bindings.eval("(macroexpand-1 `(my-not true))")
+            == Ok(list_of(~[Symbol(~"not"), Boolean(true)])));
+    assert!(bindings.eval("(macroexpand `(my-not true))")
+            == Ok(list_of(~[Symbol(~"if"), Boolean(true), Boolean(false), Boolean(true)])));
+    assert!(bindings.eval("(macroexpand `(+ 1 2))")
+            == Ok(list_of(~[Symbol(~"+"), ::types::Number(1), ::types::Number(2)])));
+    assert!(bindings.eval("(macroexpand-all `(+ 1 (my-not true)))")
+            == Ok(list_of(~[Symbol(~"+"), ::types::Number(1),
+                            list_of(~[Symbol(~"if"), Boolean(true),
+                                      Boolean(false), Boolean(true)])])));
+}
+
+#[test]
+fn test_errors_short_circuit() {
+    // the real cause comes through instead of a complaint from +
+    assert!(eval("(+ 1 undefined)") == EvalError(~"Symbol Not defined"));
+    let mut bindings = Bindings::new();
+    assert!(bindings.eval("(do (def a 1) (undefined) (def a 2))")
+            == Err(EvalError(~"Symbol Not defined")));
+    assert!(bindings.eval("a") == Ok(::types::Number(1)));
+    assert!(bindings.eval("[1 (- ) 3]") == Err(EvalError(~"-: Wrong number of args (0)")));
+}
+
+#[test]
+fn test_throw() {
+    assert!(eval("(throw 5)") == ::types::Number(5));
+    let mut bindings = Bindings::new();
+    assert!(bindings.eval("(+ 1 (throw {:a 1}))")
+            == Err(::types::Map(::types::ElementMap::from_pairs(~[(::types::Keyword(~"a"),
+                                                                   ::types::Number(1))]))));
+    assert!(bindings.eval("(throw)") == Err(EvalError(~"throw: wrong number of args (0)")));
+}
+
+#[test]
+fn test_try_catch_finally() {
+    let mut bindings = Bindings::new();
+    assert!(bindings.eval("(try 1 2)") == Ok(::types::Number(2)));
+    assert!(bindings.eval("(try (throw 5) (catch e (+ e 1)))") == Ok(::types::Number(6)));
+    assert!(bindings.eval("(try (undefined) (catch e e))")
+            == Ok(EvalError(~"Symbol Not defined")));
+    assert!(bindings.eval("(try (throw (ex-info \"bad\" {:code 7})) (catch e (get (ex-data e) :code)))")
+            == Ok(::types::Number(7)));
+    assert!(bindings.eval("(try (throw (ex-info \"bad\" {})) (catch e (ex-message e)))")
+            == Ok(::types::String(~"bad")));
+    // finally always runs, but doesn't change the result
+    bindings.eval("(def cleaned false)");
+    assert!(bindings.eval("(try 1 (finally (def cleaned true)))") == Ok(::types::Number(1)));
+    assert!(bindings.eval("cleaned") == Ok(Boolean(true)));
+    bindings.eval("(def cleaned false)");
+    assert!(bindings.eval("(try (throw 1) (finally (def cleaned true)))")
+            == Err(::types::Number(1)));
+    assert!(bindings.eval("cleaned") == Ok(Boolean(true)));
+    bindings.eval("(def cleaned false)");
+    assert!(bindings.eval("(try (throw 1) (catch e 2) (finally (def cleaned true)))")
+            == Ok(::types::Number(2)));
+    assert!(bindings.eval("cleaned") == Ok(Boolean(true)));
+    // rethrowing from catch, and nested trys
+    assert!(bindings.eval("(try (try (throw 1) (catch e (throw (+ e 1)))) (catch e e))")
+            == Ok(::types::Number(2)));
+    assert!(bindings.eval("(try (finally 1) 2)")
+            == Err(EvalError(~"try: body forms must come before catch and finally")));
+    assert!(bindings.eval("(catch e 1)") == Err(EvalError(~"catch: can only be used inside try")));
+}
+
+#[test]
+fn test_stacktrace() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn inner [x] (/ x 0))");
+    bindings.eval("(defn middle [x] (+ 1 (inner x)))");
+    bindings.eval("(defn outer [x] (middle x))");
+    assert!(bindings.eval("(outer 5)") == Err(EvalError(~"/: Divide by zero")));
+    let ex = bindings.last_error().unwrap().clone();
+    let names: ~[~str] = ex.trace.iter().map(|f| f.name.clone()).collect();
+    // middle was tail called from outer, which doesn't grow the stack,
+    // but the trace still has an entry for each call
+    assert!(names == ~[~"/", ~"inner", ~"middle", ~"outer"]);
+    assert!(ex.trace[1].form == ::tokenizer::tokenize("(inner x)"));
+    assert!(ex.to_str().starts_with("Eval Error: /: Divide by zero\n    at /: (/ x 0)"));
+    // the thrown value and its trace can be inspected afterwards
+    assert!(bindings.eval("*e") == Ok(EvalError(~"/: Divide by zero")));
+    assert!(bindings.eval("(get (get (stacktrace) 0) :fn)") == Ok(::types::String(~"/")));
+    assert!(bindings.eval("(get (get (stacktrace) 3) :form)")
+            == Ok(::tokenizer::tokenize("(outer 5)")));
+}
+
+#[test]
+fn test_and_or() {
+    assert!(eval("(and)") == ::types::Boolean(true));
+    assert!(eval("(or)") == ::types::nil);
+    assert!(eval("(and true (= 1 1))") == ::types::Boolean(true));
+    assert!(eval("(and true false true)") == ::types::Boolean(false));
+    assert!(eval("(and true 5)") == ::types::Number(5));
+    assert!(eval("(or false 5)") == ::types::Number(5));
+    assert!(eval("(or false false)") == ::types::Boolean(false));
+    // only the needed args are evaluated
+    assert!(eval("(and false (throw 1))") == ::types::Boolean(false));
+    assert!(eval("(or true (throw 1))") == ::types::Boolean(true));
+    assert!(eval("(and 1 2)") == ::types::Number(2));
+    assert!(eval("(and 1 nil 2)") == ::types::nil);
+    assert!(eval("(or nil false)") == ::types::Boolean(false));
+    assert!(eval("(or nil 0)") == ::types::Number(0));
+}
+
+#[test]
+fn test_cond() {
+    assert!(eval("(cond)") == ::types::nil);
+    assert!(eval("(cond (= 1 2) 1 (= 1 1) 2 :else 3)") == ::types::Number(2));
+    assert!(eval("(cond false 1 :else 3)") == ::types::Number(3));
+    assert!(eval("(cond false 1)") == ::types::nil);
+    assert!(eval("(cond true 1 (throw 2) 2)") == ::types::Number(1));
+    assert!(eval("(cond true)") == EvalError(~"cond: expected an even number of forms"));
+}
+
+#[test]
+fn test_when() {
+    assert!(eval("(when true 1 2)") == ::types::Number(2));
+    assert!(eval("(when false (throw 1))") == ::types::nil);
+    assert!(eval("(when-not false 1 2)") == ::types::Number(2));
+    assert!(eval("(when-not true 1)") == ::types::nil);
+    assert!(eval("(when)") == EvalError(~"when: expected a test"));
+}
+
+#[test]
+fn test_case() {
+    assert!(eval("(case 2 1 :a 2 :b :c)") == ::types::Keyword(~"b"));
+    assert!(eval("(case 5 1 :a :none)") == ::types::Keyword(~"none"));
+    assert!(eval("(case (+ 1 2) (1 3 5) :odd (2 4) :even)") == ::types::Keyword(~"odd"));
+    // constants are not evaluated
+    assert!(eval("(case 1 (throw 1) :a 1 :b)") == ::types::Keyword(~"b"));
+    assert!(eval("(case 5 1 :a)") == EvalError(~"case: no clause matching 5"));
+}
+
+#[test]
+fn test_if_let() {
+    assert!(eval("(if-let [x (= 1 1)] x 0)") == ::types::Boolean(true));
+    assert!(eval("(if-let [x (= 1 2)] 1 0)") == ::types::Number(0));
+    assert!(eval("(if-let [x (= 1 2)] 1)") == ::types::nil);
+    assert!(eval("(when-let [x (= 1 1)] 1 x)") == ::types::Boolean(true));
+    assert!(eval("(when-let [x (= 1 2)] (throw 1))") == ::types::nil);
+    assert!(eval("(if-let [x] 1)") == EvalError(~"if-let: expected a binding vector [name test]"));
+    assert!(eval("(if-let [x true])") == EvalError(~"if-let: wrong number of args (1)"));
+}
+
+#[test]
+fn test_conditional_tail_position() {
+    assert!(eval("(loop [i 0] (cond (= i 5) i :else (recur (inc i))))") == ::types::Number(5));
+    assert!(eval("(loop [i 0] (when (= i i) (if (= i 3) i (recur (inc i)))))")
+            == ::types::Number(3));
+    assert!(eval("(loop [i 0] (case i 4 i (recur (inc i))))") == ::types::Number(4));
+    assert!(eval("(loop [i 0] (cond (recur i) 1))")
+            == EvalError(~"recur: not in tail position (test 1 of (cond ...))"));
+    assert!(eval("(loop [i 0] (when (recur i) 1))")
+            == EvalError(~"recur: not in tail position (test of (when ...))"));
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn count-to [x n] (cond (= x n) x :else (count-to (inc x) n)))");
+    assert!(bindings.eval("(count-to 0 100000)") == Ok(::types::Number(100000)));
+}
+
+#[test]
+fn test_truthiness() {
+    assert!(eval("(if nil 1 2)") == ::types::Number(2));
+    assert!(eval("(if 0 1 2)") == ::types::Number(1));
+    assert!(eval("(if [] 1 2)") == ::types::Number(1));
+    assert!(eval("(if (get {:k 5} :k) 1 2)") == ::types::Number(1));
+    assert!(eval("(if (get {:k 5} :j) 1 2)") == ::types::Number(2));
+    assert!(eval("(when 1 2)") == ::types::Number(2));
+    assert!(eval("(cond nil 1 \"s\" 2)") == ::types::Number(2));
+    assert!(eval("(if-let [x (get {:k 5} :k)] x 0)") == ::types::Number(5));
+    assert!(eval("(when-let [x (get {:k 5} :j)] x)") == ::types::nil);
+    assert!(eval("(not nil)") == ::types::Boolean(true));
+    assert!(eval("(not 1)") == ::types::Boolean(false));
+}
+
+#[test]
+fn test_strict_booleans() {
+    let mut bindings = Bindings::new();
+    bindings.set_strict_booleans(true);
+    assert!(bindings.eval("(if true 1 2)") == Ok(::types::Number(1)));
+    assert!(bindings.eval("(if nil 1 2)") == Err(EvalError(~"if: test must be boolean")));
+    assert!(bindings.eval("(and 1 2)") == Err(EvalError(~"and: test must be boolean")));
+    assert!(bindings.eval("(cond 1 2)") == Err(EvalError(~"cond: test must be boolean")));
+    // the setting carries into function bodies
+    bindings.eval("(defn pick [x] (if x 1 2))");
+    assert!(bindings.eval("(pick 0)") == Err(EvalError(~"if: test must be boolean")));
+    assert!(bindings.eval("(boolean 0)") == Ok(::types::Boolean(true)));
+}
+
+#[test]
+fn test_quote() {
+    assert!(eval("(quote a)") == ::types::Symbol(~"a"));
+    assert!(eval("'a") == ::types::Symbol(~"a"));
+    assert!(eval("'(+ 1 2)") == ::types::list_of(~[::types::Symbol(~"+"),
+                                                 ::types::Number(1),
+                                                 ::types::Number(2)]));
+    assert!(eval("'(throw x)") == ::types::list_of(~[::types::Symbol(~"throw"),
+                                                  ::types::Symbol(~"x")]));
+    assert!(eval("(quote)") == EvalError(~"quote: wrong number of args (0)"));
+    assert!(eval("(macroexpand-all '(if-not a b '(not c)))")
+            == eval("'(if (if a false true) b (quote (not c)))"));
+}
+
+#[test]
+fn test_eval() {
+    assert!(eval("(eval '(+ 1 2))") == ::types::Number(3));
+    assert!(eval("(eval (read-string \"(* 2 3)\"))") == ::types::Number(6));
+    assert!(eval("(eval 5)") == ::types::Number(5));
+    let mut bindings = Bindings::new();
+    bindings.eval("(def form '(inc x))");
+    assert!(bindings.eval("(let [x 1] (eval form))") == Ok(::types::Number(2)));
+    assert!(bindings.eval("(eval)") == Err(EvalError(~"eval: wrong number of args (0)")));
+}
+
+#[test]
+fn test_apply() {
+    assert!(eval("(apply + 1 2 [3 4])") == ::types::Number(10));
+    assert!(eval("(apply + [])") == ::types::Number(0));
+    assert!(eval("(apply + '(1 2))") == ::types::Number(3));
+    assert!(eval("(apply + 1 nil)") == ::types::Number(1));
+    assert!(eval("(apply (fn [a & more] more) 1 [2 3])")
+            == ::types::list_of(~[::types::Number(2), ::types::Number(3)]));
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn sum3 [a b c] (+ a b c))");
+    assert!(bindings.eval("(apply sum3 [1 2 3])") == Ok(::types::Number(6)));
+    assert!(bindings.eval("(apply sum3 [1 2])")
+            == Err(EvalError(~"wrong number of args (2) passed to fn")));
+    assert!(bindings.eval("(apply + 1 2)")
+            == Err(EvalError(~"apply: last arg must be a list or vector")));
+}
+
+#[test]
+fn test_binding() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def ^:dynamic *out-width* 80)");
+    bindings.eval("(defn width [] *out-width*)");
+    assert!(bindings.eval("(binding [*out-width* 120] (width))") == Ok(::types::Number(120)));
+    assert!(bindings.eval("(width)") == Ok(::types::Number(80)));
+    assert!(bindings.eval("(binding [*out-width* 1] (binding [*out-width* 2] (width)))")
+            == Ok(::types::Number(2)));
+    // the values are evaluated before any are bound
+    assert!(bindings.eval("(binding [*out-width* 1 *print-length* *out-width*] *print-length*)")
+            == Ok(::types::Number(80)));
+    // locals still shadow the var
+    assert!(bindings.eval("(let [*out-width* 5] (binding [*out-width* 1] *out-width*))")
+            == Ok(::types::Number(5)));
+    // redefining keeps the var dynamic
+    bindings.eval("(def *out-width* 90)");
+    assert!(bindings.eval("(binding [*out-width* 1] (width))") == Ok(::types::Number(1)));
+}
+
+#[test]
+fn test_binding_restored_on_error() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def ^:dynamic *x* 1)");
+    assert!(bindings.eval("(try (binding [*x* 2] (throw *x*)) (catch e e))")
+            == Ok(::types::Number(2)));
+    assert!(bindings.eval("*x*") == Ok(::types::Number(1)));
+    assert!(bindings.eval("(binding [*x* 3] (throw :oops))") == Err(::types::Keyword(~"oops")));
+    assert!(bindings.eval("*x*") == Ok(::types::Number(1)));
+}
+
+#[test]
+fn test_binding_errors() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(def y 1)");
+    assert!(bindings.eval("(binding [y 2] y)")
+            == Err(EvalError(~"binding: can't rebind non-dynamic var y")));
+    assert!(bindings.eval("(binding [y] y)")
+            == Err(EvalError(~"binding: bindings must come in pairs")));
+    assert!(bindings.eval("(binding y y)")
+            == Err(EvalError(~"binding: expected a binding vector")));
+}
+
+#[test]
+fn test_namespaces() {
+    let mut bindings = Bindings::new();
+    assert!(bindings.current_ns() == ~"user");
+    bindings.eval("(ns foo.bar)");
+    assert!(bindings.current_ns() == ~"foo.bar");
+    bindings.eval("(def x 1)");
+    bindings.eval("(defn add-x [n] (+ n x))");
+    bindings.eval("(in-ns 'user)");
+    assert!(bindings.eval("x") == Err(EvalError(~"Symbol Not defined")));
+    assert!(bindings.eval("(foo.bar/add-x 2)") == Ok(Number(3)));
+    // fns resolve their names where they were defined
+    bindings.eval("(def x 10)");
+    assert!(bindings.eval("(foo.bar/add-x 2)") == Ok(Number(3)));
+    assert!(bindings.eval("x") == Ok(Number(10)));
+    // builtins live in rusp.core, and can be shadowed
+    assert!(bindings.eval("(rusp.core/+ 1 2)") == Ok(Number(3)));
+    bindings.eval("(defn inc [n] n)");
+    assert!(bindings.eval("(inc 1)") == Ok(Number(1)));
+    assert!(bindings.eval("(rusp.core/inc 1)") == Ok(Number(2)));
+}
+
+#[test]
+fn test_require() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(ns foo.bar)");
+    bindings.eval("(def x 1)");
+    bindings.eval("(def y 2)");
+    bindings.eval("(ns user (:require [foo.bar :as fb :refer [x]]))");
+    assert!(bindings.eval("[x fb/y foo.bar/y]") == Ok(vec_of(~[Number(1), Number(2), Number(2)])));
+    assert!(bindings.eval("y") == Err(EvalError(~"Symbol Not defined")));
+    bindings.eval("(ns other)");
+    bindings.eval("(require '[foo.bar :refer :all])");
+    assert!(bindings.eval("(+ x y)") == Ok(Number(3)));
+    assert!(bindings.eval("(require 'nope)")
+            == Err(EvalError(~"require: no namespace named nope")));
+    assert!(bindings.eval("(require '[foo.bar :refer [z]])")
+            == Err(EvalError(~"require: foo.bar/z does not exist")));
+}
+
+#[test]
+fn test_private_defs() {
+    let mut bindings = Bindings::new();
+    bindings.eval("(ns foo.bar)");
+    bindings.eval("(defn- helper [] 1)");
+    bindings.eval("(def ^:private secret 2)");
+    bindings.eval("(defn api [] (+ (helper) secret))");
+    assert!(bindings.eval("(helper)") == Ok(Number(1)));
+    bindings.eval("(in-ns 'user)");
+    assert!(bindings.eval("(foo.bar/api)") == Ok(Number(3)));
+    assert!(bindings.eval("(foo.bar/helper)")
+            == Err(EvalError(~"foo.bar/helper is not public")));
+    assert!(bindings.eval("foo.bar/secret") == Err(EvalError(~"foo.bar/secret is not public")));
+    assert!(bindings.eval("(require '[foo.bar :refer [secret]])")
+            == Err(EvalError(~"require: foo.bar/secret is not public")));
+}
+
+#[test]
+fn test_string_library() {
+    let mut bindings = Bindings::new();
+    assert!(bindings.eval("(upper-case \"x\")") == Err(EvalError(~"Symbol Not defined")));
+    assert!(bindings.eval("(rusp.string/upper-case \"é\")") == Ok(::types::String(~"É")));
+    bindings.eval("(require '[rusp.string :as s :refer [split-lines]])");
+    bindings.eval("(def log \"GET /a 200\nGET /b 404\r\nPOST /c 500\n\")");
+    assert!(bindings.eval("(count (split-lines log))") == Ok(Number(3)));
+    bindings.eval("(defn status [line] (nth (s/split line \" \") 2))");
+    assert!(bindings.eval("(s/join \",\" (map status (split-lines log)))")
+            == Ok(::types::String(~"200,404,500")));
+    assert!(bindings.eval("(str (subs \"日本語\" 1 2) \"!\")") == Ok(::types::String(~"本!")));
+    // commas are whitespace in code, but not in strings
+    assert!(bindings.eval("(s/join \", \" [\"a\", \"b\"])") == Ok(::types::String(~"a, b")));
+    assert!(bindings.eval("(s/split \"x,y\" \",\")")
+            == Ok(vec_of(~[::types::String(~"x"), ::types::String(~"y")])));
+    assert!(bindings.eval("(count \"é,日\")") == Ok(Number(3)));
+}
+
+#[test]
+fn test_scope_frames() {
+    let mut bindings = Bindings::new();
+    // defs inside calls go in the toplevel, metadata and all
+    bindings.eval("(defn setup [] (def ^:dynamic *depth* 0) (in-ns 'made.in.fn) (def x 1))");
+    bindings.eval("(setup)");
+    assert!(bindings.eval("(binding [*depth* 1] *depth*)") == Ok(Number(1)));
+    assert!(bindings.eval("made.in.fn/x") == Ok(Number(1)));
+    assert!(bindings.current_ns() == ~"user");
+    // each recur gets a new frame, so seqs made on the way keep their values
+    bindings.eval("(defn seqs [n]
+                     (loop [i 0 v []] (if (= i n) v (recur (inc i) (conj v (lazy-seq [i]))))))");
+    assert!(bindings.eval("(apply + (map first (seqs 3)))") == Ok(Number(3)));
+    // and so does each let binding
+    assert!(bindings.eval("(let [x 1 s (lazy-seq [x]) x 2] [(first s) x])")
+            == Ok(vec_of(~[Number(1), Number(2)])));
+}
+
+#[test]
+fn test_closures() {
+    let mut bindings = Bindings::new();
+    // fns see the locals they were made with, not their caller's
+    assert!(bindings.eval("(((fn [x] (fn [y] (+ x y))) 1) 2)") == Ok(Number(3)));
+    bindings.eval("(defn adder [x] (fn [y] (+ x y)))");
+    assert!(bindings.eval("(let [add2 (adder 2) x 10] (add2 1))") == Ok(Number(3)));
+    bindings.eval("(let [x 1] (defn get-x [] x))");
+    assert!(bindings.eval("(let [x 2] (get-x))") == Ok(Number(1)));
+    bindings.eval("(defn see [] y)");
+    assert!(bindings.eval("(let [y 5] (see))").is_err());
+    // and keep them after the call that made them returns
+    bindings.eval("(defn counter [] (let [n (atom 0)] (fn [] (swap! n inc))))");
+    assert!(bindings.eval("(let [c (counter)] (c) (c))") == Ok(Number(2)));
+}
+
+#[test]
+fn test_analyze() {
+    let mut bindings = Bindings::new();
+    let (x, y) = (intern("x"), intern("y"));
+    // locals get the frame and slot they'll be in; calls are interned
+    let form = bindings.analyze(&tokenize("(let [x 1 y x] (+ x y))"), &mut ~[]);
+    assert!(form == list_of(~[Symbol(~"let"),
+                              vec_of(~[Symbol(~"x"), Number(1),
+                                       Symbol(~"y"), Local(~"x", x, 0, 0)]),
+                              list_of(~[Interned(~"+", intern("+")),
+                                        Local(~"x", x, 0, 0), Local(~"y", y, 0, 1)])]));
+    let form = bindings.analyze_body([~"x", ~"&", ~"y"], &tokenize("(when-let [x y] [x y])"));
+    assert!(form == list_of(~[Symbol(~"when-let"), vec_of(~[Symbol(~"x"), Local(~"y", y, 0, 1)]),
+                              vec_of(~[Local(~"x", x, 0, 0), Local(~"y", y, 1, 1)])]));
+    // macro calls, and names nothing is defined for yet, are left alone
+    assert!(bindings.analyze(&tokenize("(not x)"), &mut ~[])
+            == list_of(~[Symbol(~"not"), Symbol(~"x")]));
+    bindings.eval("(defn k [] (later 5))");
+    bindings.eval("(defmacro later [a] a)");
+    assert!(bindings.eval("(k)") == Ok(Number(5)));
+    // shadowed names, catch and if-let get the right value
+    assert!(bindings.eval("(let [x 1] (let [y (+ x 1) x 10] (+ x y)))") == Ok(Number(12)));
+    assert!(bindings.eval("(let [e 1] (try (throw (ex-info \"no\" {})) (catch e (ex-message e))))")
+            == Ok(::types::String(~"no")));
+    assert!(bindings.eval("(let [v 5] (if-let [v nil] 1 v))") == Ok(Number(5)));
+    assert!(bindings.eval("(let [n 2] (case n 1 :one 2 (+ n n) n))") == Ok(Number(4)));
+    // interned lookups see later definitions
+    bindings.eval("(defn g [] 1)");
+    bindings.eval("(defn h [] (g))");
+    bindings.eval("(defn g [] 2)");
+    assert!(bindings.eval("(h)") == Ok(Number(2)));
+}
+
+#[test]
+fn test_local_slots() {
+    let x = intern("x");
+    // a local is read from the slot analyze gave it, even though
+    // looking x up by name would find the later binding
+    let mut b = Bindings::new().push();
+    b.insert("x", Number(1));
+    b.insert("x", Number(2));
+    assert!(b.eval_elem(Local(~"x", x, 0, 0)).ok() == Some(Number(1)));
+    assert!(b.eval_elem(Symbol(~"x")).ok() == Some(Number(2)));
+    // and a slot that doesn't hold it isn't made up for by a lookup
+    assert!(b.eval_elem(Local(~"x", x, 0, 2)).is_err());
+    assert!(b.eval_elem(Local(~"x", x, 1, 0)).is_err());
+    // a fn redefined as a macro gets the plain symbols it was called with
+    let mut bindings = Bindings::new();
+    bindings.eval("(defn later [a] a)");
+    bindings.eval("(defn k [y] (later y))");
+    bindings.eval("(defmacro later [a] `(let [z 1] ~a))");
+    assert!(bindings.eval("(k 5)") == Ok(Number(5)));
+}
+
+// all of the above, on the vm
+compiled_suite!(test_basic_eval, test_if_fn, test_def, test_fn, test_defn, test_do, test_let,
+               test_fn_body, test_loop, test_fn_recur, test_recur_position, test_tail_calls,
+               test_apply_evaluated_args, test_variadic_fn, test_syntax_quote, test_defmacro,
+               test_not_if_not, test_macroexpand, test_errors_short_circuit, test_throw,
+               test_try_catch_finally, test_stacktrace, test_and_or, test_cond, test_when,
+               test_case, test_if_let, test_conditional_tail_position, test_truthiness,
+               test_strict_booleans, test_quote, test_eval, test_apply, test_binding,
+               test_binding_restored_on_error, test_binding_errors, test_namespaces, test_require,
+               test_private_defs, test_string_library, test_scope_frames, test_closures,
+               test_analyze, test_local_slots)
+
+#[bench]
+fn bench_loop_locals(bh: &mut ::extra::test::BenchHarness) {
+    // locals in a hot loop are read from their slots
+    let mut bindings = Bindings::new();
+    bh.iter(|| {
+        bindings.eval("(loop [i 0 acc 0] (if (< i 10000) (recur (inc i) (+ acc i)) acc))");
+    });
+}
+
+/**
+ * @brief call a small fn, with n other fns defined
+ *
+ * Entering a scope doesn't copy the toplevel, so this should take
+ * about as long for any n.
+ */
+#[allow(dead_code)]
+fn bench_call_with_defs(bh: &mut ::extra::test::BenchHarness, n: uint) {
+    let mut bindings = Bindings::new();
+    for i in range(0, n) {
+        bindings.eval(format!("(defn f{:u} [x] x)", i).as_slice());
+    }
+    bindings.eval("(defn add [x y] (let [z (+ x y)] z))");
+    bh.iter(|| {
+        bindings.eval("(add 1 2)");
+    });
 }
 
+#[bench]
+fn bench_call_10_defs(bh: &mut ::extra::test::BenchHarness) {
+    bench_call_with_defs(bh, 10);
+}
 
+#[bench]
+fn bench_call_10000_defs(bh: &mut ::extra::test::BenchHarness) {
+    bench_call_with_defs(bh, 10000);
+}
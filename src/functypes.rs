@@ -1,8 +1,16 @@
 use std::rand::Rng;
 use std::rand::os::OSRng;
+use std::rc::Rc;
 
 use types::Element;
-use types::{Function, FuncPrimitive};
+use types::{Function, FuncPrimitive, FuncNative};
+use eval::{Bindings, EvalResult};
+
+use symbol::Sym;
+
+use env::Env;
+
+use compiler::Chunk;
 
 
 pub struct RustFunc {
@@ -42,6 +50,78 @@ impl ToStr for RustFunc {
     }
 }
 
+/**
+ * @brief a builtin that can call back into the interpreter
+ *
+ * Unlike a RustFunc, implementors get the interpreter they were called
+ * from, so they can call Function and FuncPrimitive values, and they
+ * can carry their own state.
+ */
+pub trait NativeFn {
+    fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult;
+    fn clone_box(&self) -> ~NativeFn;
+}
+
+/// a stateless NativeFn, wrapping a plain fn
+struct NativeFnPtr {
+    f: fn(&mut Bindings, &[Element]) -> EvalResult
+}
+
+impl NativeFn for NativeFnPtr {
+    fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult {
+        let f = self.f;
+        f(bindings, args)
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~NativeFnPtr { f: self.f } as ~NativeFn
+    }
+}
+
+pub struct NativeFunc {
+    f: ~NativeFn,
+    tag: u64
+}
+
+impl NativeFunc {
+    pub fn new(f: fn(&mut Bindings, &[Element]) -> EvalResult) -> Element {
+        NativeFunc::with_state(~NativeFnPtr { f: f } as ~NativeFn)
+    }
+    /**
+     * @brief wrap a NativeFn that carries its own state
+     */
+    pub fn with_state(f: ~NativeFn) -> Element {
+        let mut rng = OSRng::new();
+        FuncNative(~NativeFunc {
+            f: f,
+            tag: rng.gen::<u64>()
+        })
+    }
+    pub fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult {
+        self.f.call(bindings, args)
+    }
+}
+
+impl Eq for NativeFunc {
+    fn eq(&self, other: &NativeFunc) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Clone for NativeFunc {
+    fn clone(&self) -> NativeFunc {
+        NativeFunc {
+            f: self.f.clone_box(),
+            tag: self.tag
+        }
+    }
+}
+
+impl ToStr for NativeFunc {
+    fn to_str(&self) -> ~str {
+        format!("@builtin{:u}", self.tag)
+    }
+}
+
 #[deriving(Clone, Eq)]
 pub enum ArgBinding {
     Variable(~str),
@@ -61,27 +141,53 @@ impl ToStr for ArgBinding {
 pub struct BoundFn {
     bindings: ~[ArgBinding],
     f: Element,
-    is_macro: bool
+    is_macro: bool,
+    // the namespace the body's names are resolved in, if not the caller's
+    ns: Option<Sym>,
+    // the scope the fn was made in, whose locals the body sees; None
+    // for fns made at the toplevel
+    env: Option<Rc<Env>>,
+    // the body compiled for the vm, if it has been
+    code: Option<Rc<Chunk>>
 }
 
 impl BoundFn {
     fn create_fn(bindings: &[~str], func: Element, is_macro: bool) -> Element {
-        let newbindings = bindings.map(|x| {
-            Variable(x.to_owned())
-        });
+        // everything after a & is collected into the last arg
+        let mut newbindings: ~[ArgBinding] = ~[];
+        let mut variadic = false;
+        for x in bindings.iter() {
+            if *x == ~"&" {
+                variadic = true;
+            } else if variadic {
+                newbindings.push(Variadic(x.to_owned()));
+            } else {
+                newbindings.push(Variable(x.to_owned()));
+            }
+        }
         Function(~BoundFn {
             bindings: newbindings,
             f: func,
-            is_macro: is_macro
+            is_macro: is_macro,
+            ns: None,
+            env: None,
+            code: None
         })
     }
     pub fn new(bindings: &[~str], func: Element) -> Element {
         BoundFn::create_fn(bindings, func, false)
     }
-    #[allow(dead_code)]
     pub fn new_macro(bindings: &[~str], func: Element) -> Element {
         BoundFn::create_fn(bindings, func, true)
     }
+    /**
+     * @brief the names of the params, in order, without the &
+     */
+    pub fn param_names(&self) -> ~[~str] {
+        self.bindings.iter().map(|b| match *b {
+            Variable(ref s) | Variadic(ref s) => s.clone()
+        }).collect()
+    }
 }
 
 impl ToStr for BoundFn {
@@ -94,5 +200,14 @@ impl ToStr for BoundFn {
 #[test]
 fn test_boundfn_to_str() {
     let list = ~[::types::Symbol(~"+"), ::types::Symbol(~"x"), ::types::Number(1)];
-    assert!(BoundFn::new([~"x"], ::types::List(list)).to_str() == ~"(fn [x] (+ x 1))");
+    assert!(BoundFn::new([~"x"], ::types::list_of(list)).to_str() == ~"(fn [x] (+ x 1))");
+}
+
+#[test]
+fn test_boundfn_variadic() {
+    let list = ~[::types::Symbol(~"+"), ::types::Symbol(~"x"), ::types::Number(1)];
+    match BoundFn::new([~"x", ~"&", ~"xs"], ::types::list_of(list)) {
+        Function(f) => assert!(f.bindings == ~[Variable(~"x"), Variadic(~"xs")]),
+        _ => fail!("BoundFn::new didn't return a Function")
+    }
 }
@@ -0,0 +1,41 @@
+/**
+ * @file macros.rs
+ * @brief macros shared by every module
+ *
+ * main.rs declares this module first, so the macros are visible in
+ * all the modules declared after it.
+ */
+
+/// unwrap an Ok, or return the Err from the enclosing function
+macro_rules! try_eval(
+    ($e:expr) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => return Err(e)
+        }
+    )
+)
+
+/// like try_eval!, for builtins that return their errors as Elements
+macro_rules! try_items(
+    ($e:expr) => (
+        match $e {
+            Ok(v) => v,
+            Err(e) => return e
+        }
+    )
+)
+
+/// a test that runs the given tests again, with every Bindings they
+/// make compiling to the vm; each test runs in its own task, so the
+/// others are left on the tree-walker
+#[cfg(test)]
+macro_rules! compiled_suite(
+    ($($test:ident),+) => (
+        #[test]
+        fn test_compiled_suite() {
+            ::eval::compile_by_default();
+            $($test();)+
+        }
+    )
+)
@@ -1,19 +1,33 @@
 extern mod extra;
 
-use std::c_str::CString;
+use std::c_str::{CString, ToCStr};
 use std::libc::c_char;
-use std::ptr;
 
 use extra::getopts::{optflag,getopts};
 use std::os;
 
 use eval::Bindings;
 
+#[macro_escape]
+mod macros;
 mod primitives;
 mod types;
 mod functypes;
 mod tokenizer;
 mod eval;
+mod core;
+mod namespace;
+mod persistent;
+mod env;
+mod symbol;
+mod special;
+mod strings;
+mod math;
+mod records;
+mod multimethods;
+mod protocols;
+mod compiler;
+mod vm;
 
 
 #[link(name = "readline")]
@@ -41,7 +55,7 @@ fn main()
     let args = os::args();
     let program = args[0].clone();
     let opts = ~[
-        optflag("v"), optflag("version")];
+        optflag("v"), optflag("version"), optflag("strict-booleans"), optflag("vm")];
     let matches = match getopts(args.tail(), opts) {
         Ok(m)  => { m },
         Err(f) => { fail!(f.to_err_msg()) }
@@ -50,9 +64,17 @@ fn main()
         print_version(program);
         return;
     }
+    // only accept booleans as conditional tests
+    bindings.set_strict_booleans(matches.opt_present("strict-booleans"));
+    // compile to bytecode, rather than walking forms
+    bindings.set_compiled(matches.opt_present("vm"));
+    // keep infinite seqs from hanging the REPL
+    bindings.eval("(do (in-ns 'rusp.core) (def *print-length* 100) (in-ns 'user))");
     loop {
         let line = unsafe {
-            let allocd: *c_char = readline(ptr::null());
+            // show which namespace definitions go into
+            let prompt = format!("{:s}=> ", bindings.current_ns());
+            let allocd: *c_char = prompt.with_c_str(|p| readline(p));
             let read = CString::new(allocd, true);
             let read_s = read.as_str();
             match read_s {
@@ -62,8 +84,15 @@ fn main()
         };
         match line {
             Some(s) => {
-                let evald = bindings.eval(s);
-                println(evald.to_str());
+                let printed = match bindings.eval(s) {
+                    Ok(evald) => bindings.print_str(&evald),
+                    Err(_) => Err(bindings.last_error().unwrap().clone())
+                };
+                match printed {
+                    Ok(out) => println(out),
+                    // print the whole trace
+                    Err(ex) => println(ex.to_str())
+                }
                 //println!("{:?}", evald);
             },
             None => return
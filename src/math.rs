@@ -0,0 +1,349 @@
+/**
+ * @file math.rs
+ * @brief integer math and bitwise function definitions
+ *
+ * Numbers are i64s. Anything that would overflow is an error,
+ * rather than quietly wrapping around.
+ */
+use std::i64;
+use std::num::CheckedMul;
+
+use types::Element;
+use types::EvalError;
+use types::{Number, Boolean};
+
+mod types;
+
+/**
+ * @brief the args as numbers
+ * @param name:     name of the calling function, for errors
+ * @param fewest:   the fewest args it takes
+ */
+fn nums(name: &str, list: &[Element], fewest: uint) -> Result<~[i64], Element>
+{
+    if list.len() < fewest {
+        return Err(EvalError(format!("{:s}: wrong number of args ({:u})", name, list.len())));
+    }
+    let mut is: ~[i64] = ~[];
+    for x in list.iter() {
+        match *x {
+            Number(n) => is.push(n),
+            _ => return Err(EvalError(format!("{:s}: expected numbers", name)))
+        }
+    }
+    Ok(is)
+}
+
+/**
+ * @brief the args as exactly n numbers
+ */
+fn exactly(name: &str, list: &[Element], n: uint) -> Result<~[i64], Element>
+{
+    if list.len() != n {
+        return Err(EvalError(format!("{:s}: wrong number of args ({:u})", name, list.len())));
+    }
+    nums(name, list, n)
+}
+
+fn overflow(name: &str) -> Element
+{
+    EvalError(format!("{:s}: integer overflow", name))
+}
+
+fn gcd_of(a: i64, b: i64) -> i64
+{
+    // works on negatives so that i64's minimum doesn't overflow
+    let (mut a, mut b) = (if a > 0 { -a } else { a }, if b > 0 { -b } else { b });
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+#[allow(dead_code)]
+pub fn abs(list: &[Element]) -> Element
+{
+    let is = try_items!(exactly("abs", list, 1));
+    if is[0] == i64::min_value {
+        return overflow("abs");
+    }
+    Number(if is[0] < 0 { -is[0] } else { is[0] })
+}
+
+#[allow(dead_code)]
+pub fn min(list: &[Element]) -> Element
+{
+    let is = try_items!(nums("min", list, 1));
+    Number(is.iter().fold(is[0], |a, &b| if b < a { b } else { a }))
+}
+
+#[allow(dead_code)]
+pub fn max(list: &[Element]) -> Element
+{
+    let is = try_items!(nums("max", list, 1));
+    Number(is.iter().fold(is[0], |a, &b| if b > a { b } else { a }))
+}
+
+#[allow(dead_code)]
+pub fn quot(list: &[Element]) -> Element
+{
+    // rounds toward zero
+    let is = try_items!(exactly("quot", list, 2));
+    match (is[0], is[1]) {
+        (_, 0) => EvalError(~"quot: Divide by zero"),
+        (a, -1) if a == i64::min_value => overflow("quot"),
+        (a, b) => Number(a / b)
+    }
+}
+
+#[allow(dead_code)]
+pub fn rem(list: &[Element]) -> Element
+{
+    // takes the sign of the dividend, like %
+    let is = try_items!(exactly("rem", list, 2));
+    match (is[0], is[1]) {
+        (_, 0) => EvalError(~"rem: Divide by zero"),
+        (_, -1) => Number(0),
+        (a, b) => Number(a % b)
+    }
+}
+
+#[allow(dead_code)]
+pub fn modulo(list: &[Element]) -> Element
+{
+    // takes the sign of the divisor, so (mod -10 3) is 2
+    let is = try_items!(exactly("mod", list, 2));
+    match (is[0], is[1]) {
+        (_, 0) => EvalError(~"mod: Divide by zero"),
+        (_, -1) => Number(0),
+        (a, b) => {
+            let r = a % b;
+            Number(if r != 0 && (r < 0) != (b < 0) { r + b } else { r })
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub fn pow(list: &[Element]) -> Element
+{
+    let is = try_items!(exactly("pow", list, 2));
+    let (base, exp) = (is[0], is[1]);
+    if exp < 0 {
+        return EvalError(~"pow: exponent must not be negative");
+    }
+    // square and multiply
+    let mut result: i64 = 1;
+    let mut square = base;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = match result.checked_mul(&square) {
+                Some(r) => r,
+                None => return overflow("pow")
+            };
+        }
+        exp >>= 1;
+        if exp > 0 {
+            square = match square.checked_mul(&square) {
+                Some(s) => s,
+                None => return overflow("pow")
+            };
+        }
+    }
+    Number(result)
+}
+
+#[allow(dead_code)]
+pub fn gcd(list: &[Element]) -> Element
+{
+    let is = try_items!(exactly("gcd", list, 2));
+    match gcd_of(is[0], is[1]) {
+        g if g == i64::min_value => overflow("gcd"),
+        g => Number(-g)
+    }
+}
+
+#[allow(dead_code)]
+pub fn lcm(list: &[Element]) -> Element
+{
+    let is = try_items!(exactly("lcm", list, 2));
+    let (a, b) = (is[0], is[1]);
+    if a == 0 || b == 0 {
+        return Number(0);
+    }
+    if a == i64::min_value || b == i64::min_value {
+        return overflow("lcm");
+    }
+    let (a, b) = (if a < 0 { -a } else { a }, if b < 0 { -b } else { b });
+    match (a / -gcd_of(a, b)).checked_mul(&b) {
+        Some(l) => Number(l),
+        None => overflow("lcm")
+    }
+}
+
+/**
+ * @brief test a single number
+ */
+fn check_num(name: &str, list: &[Element], test: |i64| -> bool) -> Element
+{
+    let is = try_items!(exactly(name, list, 1));
+    Boolean(test(is[0]))
+}
+
+#[allow(dead_code)]
+pub fn is_even(list: &[Element]) -> Element
+{
+    check_num("even?", list, |n| n % 2 == 0)
+}
+
+#[allow(dead_code)]
+pub fn is_odd(list: &[Element]) -> Element
+{
+    check_num("odd?", list, |n| n % 2 != 0)
+}
+
+#[allow(dead_code)]
+pub fn is_zero(list: &[Element]) -> Element
+{
+    check_num("zero?", list, |n| n == 0)
+}
+
+#[allow(dead_code)]
+pub fn is_pos(list: &[Element]) -> Element
+{
+    check_num("pos?", list, |n| n > 0)
+}
+
+#[allow(dead_code)]
+pub fn is_neg(list: &[Element]) -> Element
+{
+    check_num("neg?", list, |n| n < 0)
+}
+
+#[allow(dead_code)]
+pub fn bit_and(list: &[Element]) -> Element
+{
+    let is = try_items!(nums("bit-and", list, 2));
+    Number(is.iter().fold(-1, |a, &b| a & b))
+}
+
+#[allow(dead_code)]
+pub fn bit_or(list: &[Element]) -> Element
+{
+    let is = try_items!(nums("bit-or", list, 2));
+    Number(is.iter().fold(0, |a, &b| a | b))
+}
+
+#[allow(dead_code)]
+pub fn bit_xor(list: &[Element]) -> Element
+{
+    let is = try_items!(nums("bit-xor", list, 2));
+    Number(is.iter().fold(0, |a, &b| a ^ b))
+}
+
+#[allow(dead_code)]
+pub fn bit_not(list: &[Element]) -> Element
+{
+    let is = try_items!(exactly("bit-not", list, 1));
+    Number(!is[0])
+}
+
+/**
+ * @brief the shift count, which must fit in an i64
+ */
+fn shift(name: &str, n: i64) -> Result<uint, Element>
+{
+    if n < 0 || n > 63 {
+        return Err(EvalError(format!("{:s}: shift out of range ({:d})", name, n)));
+    }
+    Ok(n as uint)
+}
+
+#[allow(dead_code)]
+pub fn bit_shift_left(list: &[Element]) -> Element
+{
+    // bits shifted past the top are dropped
+    let is = try_items!(exactly("bit-shift-left", list, 2));
+    Number(is[0] << try_items!(shift("bit-shift-left", is[1])))
+}
+
+#[allow(dead_code)]
+pub fn bit_shift_right(list: &[Element]) -> Element
+{
+    // keeps the sign, so negative numbers stay negative
+    let is = try_items!(exactly("bit-shift-right", list, 2));
+    Number(is[0] >> try_items!(shift("bit-shift-right", is[1])))
+}
+
+
+#[test]
+fn test_abs_min_max() {
+    assert!(::eval::eval("(abs -5)") == Number(5));
+    assert!(::eval::eval("(abs 5)") == Number(5));
+    assert!(abs([Number(i64::min_value)]) == EvalError(~"abs: integer overflow"));
+    assert!(::eval::eval("(min 3 1 2)") == Number(1));
+    assert!(::eval::eval("(max 3 -1 7 2)") == Number(7));
+    assert!(::eval::eval("(max)") == EvalError(~"max: wrong number of args (0)"));
+    assert!(::eval::eval("(min 1 \"a\")") == EvalError(~"min: expected numbers"));
+}
+
+#[test]
+fn test_quot_rem_mod() {
+    assert!(::eval::eval("(quot 10 3)") == Number(3));
+    assert!(::eval::eval("(quot -10 3)") == Number(-3));
+    assert!(::eval::eval("(rem 10 3)") == Number(1));
+    assert!(::eval::eval("(rem -10 3)") == Number(-1));
+    assert!(::eval::eval("(rem 10 -3)") == Number(1));
+    assert!(::eval::eval("(mod 10 3)") == Number(1));
+    assert!(::eval::eval("(mod -10 3)") == Number(2));
+    assert!(::eval::eval("(mod 10 -3)") == Number(-2));
+    assert!(::eval::eval("(mod -10 -3)") == Number(-1));
+    assert!(::eval::eval("(mod -9 3)") == Number(0));
+    assert!(::eval::eval("(mod 1 0)") == EvalError(~"mod: Divide by zero"));
+    assert!(::eval::eval("(quot 1 0)") == EvalError(~"quot: Divide by zero"));
+    assert!(quot([Number(i64::min_value), Number(-1)]) == EvalError(~"quot: integer overflow"));
+    assert!(modulo([Number(i64::min_value), Number(-1)]) == Number(0));
+}
+
+#[test]
+fn test_pow_gcd_lcm() {
+    assert!(::eval::eval("(pow 2 10)") == Number(1024));
+    assert!(::eval::eval("(pow -3 3)") == Number(-27));
+    assert!(::eval::eval("(pow 7 0)") == Number(1));
+    assert!(::eval::eval("(pow 2 62)") == Number(1 << 62));
+    assert!(::eval::eval("(pow 2 63)") == EvalError(~"pow: integer overflow"));
+    assert!(::eval::eval("(pow 2 -1)") == EvalError(~"pow: exponent must not be negative"));
+    assert!(::eval::eval("(gcd 12 18)") == Number(6));
+    assert!(::eval::eval("(gcd -12 18)") == Number(6));
+    assert!(::eval::eval("(gcd 0 5)") == Number(5));
+    assert!(::eval::eval("(gcd 0 0)") == Number(0));
+    assert!(::eval::eval("(lcm 4 6)") == Number(12));
+    assert!(::eval::eval("(lcm -4 6)") == Number(12));
+    assert!(::eval::eval("(lcm 0 6)") == Number(0));
+}
+
+#[test]
+fn test_number_predicates() {
+    assert!(::eval::eval("[(even? 4) (even? -3) (odd? -3) (odd? 0)]")
+            == ::types::vec_of(~[Boolean(true), Boolean(false), Boolean(true), Boolean(false)]));
+    assert!(::eval::eval("[(zero? 0) (pos? 1) (pos? 0) (neg? -1)]")
+            == ::types::vec_of(~[Boolean(true), Boolean(true), Boolean(false), Boolean(true)]));
+    assert!(::eval::eval("(zero? nil)") == EvalError(~"zero?: expected numbers"));
+}
+
+#[test]
+fn test_bitwise() {
+    assert!(::eval::eval("(bit-and 12 10)") == Number(8));
+    assert!(::eval::eval("(bit-and 15 6 4)") == Number(4));
+    assert!(::eval::eval("(bit-or 12 10)") == Number(14));
+    assert!(::eval::eval("(bit-xor 12 10)") == Number(6));
+    assert!(::eval::eval("(bit-not 0)") == Number(-1));
+    assert!(::eval::eval("(bit-shift-left 1 10)") == Number(1024));
+    assert!(::eval::eval("(bit-shift-right 1024 3)") == Number(128));
+    assert!(::eval::eval("(bit-shift-right -16 2)") == Number(-4));
+    assert!(::eval::eval("(bit-shift-left 1 64)")
+            == EvalError(~"bit-shift-left: shift out of range (64)"));
+    assert!(::eval::eval("(bit-and 1)") == EvalError(~"bit-and: wrong number of args (1)"));
+}
@@ -0,0 +1,184 @@
+/**
+ * @file multimethods.rs
+ * @brief choosing and calling the methods of a multimethod
+ *
+ * A multimethod calls its dispatch fn with its args, then runs the
+ * method whose dispatch value the result isa?. If several match, the
+ * one that derives from, or is preferred over, all the others wins;
+ * if none does, it is an error. If none match, the :default method
+ * runs, if there is one.
+ */
+use types::Element;
+use types::{Keyword, Multi, MultiRef, Boolean, nil};
+
+use eval::{Bindings, EvalResult, Exception, eval_error};
+
+mod types;
+
+/**
+ * @brief whether method value x should win over method value y
+ */
+fn dominates(bindings: &Bindings, m: &MultiRef, x: &Element, y: &Element) -> bool
+{
+    m.prefers(x, y) || bindings.isa(x, y)
+}
+
+/**
+ * @brief the method of a multimethod to run for a dispatch value
+ */
+fn find_method(bindings: &Bindings, m: &MultiRef, value: &Element)
+    -> Result<Element, Exception>
+{
+    let matching: ~[(Element, Element)] = m.methods().move_iter()
+        .filter(|&(ref k, _)| bindings.isa(value, k)).collect();
+    for &(ref k, ref f) in matching.iter() {
+        if matching.iter().all(|&(ref other, _)| other == k || dominates(bindings, m, k, other)) {
+            return Ok(f.clone());
+        }
+    }
+    if matching.len() > 0 {
+        let keys: ~[~str] = matching.iter().map(|&(ref k, _)| k.to_str()).collect();
+        return eval_error(format!("{:s}: {:s} matches {:s}, and none is preferred",
+                                  m.name(), value.to_str(), keys.connect(" and ")));
+    }
+    let default = Keyword(~"default");
+    match m.methods().move_iter().find(|&(ref k, _)| *k == default) {
+        Some((_, f)) => Ok(f),
+        None => eval_error(format!("{:s}: no method for dispatch value {:s}",
+                                   m.name(), value.to_str()))
+    }
+}
+
+#[allow(dead_code)]
+/**
+ * @brief call a multimethod
+ * @param m:        the multimethod
+ * @param args:     the evaluated args, for both the dispatch fn and the method
+ */
+pub fn call_multi(bindings: &mut Bindings, m: &MultiRef, args: ~[Element]) -> EvalResult
+{
+    let value = try_eval!(bindings.apply(m.dispatch(), args.clone()));
+    let f = try_eval!(find_method(&*bindings, m, &value));
+    bindings.apply(f, args)
+}
+
+#[allow(dead_code)]
+pub fn prefer_method(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (prefer-method m x y): when both match, x's method wins
+    match args {
+        [Multi(ref m), ref x, ref y] => {
+            if m.prefers(y, x) {
+                return eval_error(format!("prefer-method: {:s} is already preferred to {:s}",
+                                          y.to_str(), x.to_str()));
+            }
+            m.prefer(x.clone(), y.clone());
+            Ok(Multi(m.clone()))
+        },
+        [_, _, _] => eval_error(~"prefer-method: expected a multimethod"),
+        _ => eval_error(format!("prefer-method: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn remove_method(_: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [Multi(ref m), ref value] => {
+            m.remove_method(value);
+            Ok(Multi(m.clone()))
+        },
+        [_, _] => eval_error(~"remove-method: expected a multimethod"),
+        _ => eval_error(format!("remove-method: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn derive(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    // (derive child parent): child isa? parent from now on
+    match args {
+        [ref child, ref parent] => {
+            if bindings.isa(parent, child) {
+                return eval_error(format!("derive: {:s} already derives from {:s}",
+                                          parent.to_str(), child.to_str()));
+            }
+            bindings.derive(child.clone(), parent.clone());
+            Ok(nil)
+        },
+        _ => eval_error(format!("derive: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_a(bindings: &mut Bindings, args: &[Element]) -> EvalResult
+{
+    match args {
+        [ref child, ref parent] => Ok(Boolean(bindings.isa(child, parent))),
+        _ => eval_error(format!("isa?: wrong number of args ({:u})", args.len()))
+    }
+}
+
+#[test]
+fn test_multimethods() {
+    let mut bindings = ::eval::Bindings::new();
+    bindings.eval("(defmulti area :shape)");
+    bindings.eval("(defmethod area :square [s] (* (:side s) (:side s)))");
+    bindings.eval("(defmethod area :rect [r] (* (:w r) (:h r)))");
+    assert!(bindings.eval("(area {:shape :square :side 3})") == Ok(::types::Number(9)));
+    assert!(bindings.eval("(area {:shape :rect :w 2 :h 5})") == Ok(::types::Number(10)));
+    assert!(bindings.eval("(area {:shape :circle})")
+            == Err(::types::EvalError(~"area: no method for dispatch value :circle")));
+    bindings.eval("(defmethod area :default [_] 0)");
+    assert!(bindings.eval("(area {:shape :circle})") == Ok(::types::Number(0)));
+    // a method can be replaced or removed
+    bindings.eval("(defmethod area :rect [r] -1)");
+    assert!(bindings.eval("(area {:shape :rect :w 2 :h 5})") == Ok(::types::Number(-1)));
+    bindings.eval("(remove-method area :rect)");
+    assert!(bindings.eval("(area {:shape :rect :w 2 :h 5})") == Ok(::types::Number(0)));
+    assert!(bindings.eval("(str area)") == Ok(::types::String(~"#multifn[area]")));
+    assert!(bindings.eval("(defmethod nothing :a [x] x)").is_err());
+}
+
+#[test]
+fn test_multimethod_dispatch() {
+    let mut bindings = ::eval::Bindings::new();
+    // any fn can dispatch, and gets all the args
+    bindings.eval("(defmulti combine (fn [a b] [(count a) (count b)]))");
+    bindings.eval("(defmethod combine [1 1] [a b] :singles)");
+    bindings.eval("(defmethod combine :default [a b] :other)");
+    assert!(bindings.eval("(combine [1] \"x\")") == Ok(Keyword(~"singles")));
+    assert!(bindings.eval("(combine [] [])") == Ok(Keyword(~"other")));
+    bindings.eval("(defmulti kind (fn [x] (if (record? x) (record-type x) :plain)))");
+    bindings.eval("(defrecord Point [x y])");
+    bindings.eval("(defmethod kind 'user.Point [p] (:x p))");
+    assert!(bindings.eval("(kind (->Point 4 5))") == Ok(::types::Number(4)));
+    // the methods are called on the vm too
+    bindings.set_compiled(true);
+    assert!(bindings.eval("(kind (->Point 6 5))") == Ok(::types::Number(6)));
+}
+
+#[test]
+fn test_prefer_method() {
+    let mut bindings = ::eval::Bindings::new();
+    bindings.eval("(derive :square :rect)");
+    bindings.eval("(derive :square :rhombus)");
+    assert!(bindings.eval("[(isa? :square :rect) (isa? :rect :square) (isa? [:square] [:rect])]")
+            == Ok(::types::vec_of(~[Boolean(true), Boolean(false), Boolean(true)])));
+    assert!(bindings.eval("(derive :rect :square)").is_err());
+    bindings.eval("(defmulti describe (fn [x] x))");
+    bindings.eval("(defmethod describe :rect [_] :rect)");
+    assert!(bindings.eval("(describe :square)") == Ok(Keyword(~"rect")));
+    bindings.eval("(defmethod describe :rhombus [_] :rhombus)");
+    assert!(bindings.eval("(describe :square)") == Err(::types::EvalError(
+        ~"describe: :square matches :rect and :rhombus, and none is preferred")));
+    bindings.eval("(prefer-method describe :rhombus :rect)");
+    assert!(bindings.eval("(describe :square)") == Ok(Keyword(~"rhombus")));
+    assert!(bindings.eval("(prefer-method describe :rect :rhombus)").is_err());
+    // an exact match beats what it derives from
+    bindings.eval("(defmethod describe :square [_] :square)");
+    assert!(bindings.eval("(describe :square)") == Ok(Keyword(~"square")));
+}
+
+// all of the above, on the vm
+compiled_suite!(test_multimethods, test_multimethod_dispatch, test_prefer_method)
@@ -0,0 +1,94 @@
+/**
+ * @file namespace.rs
+ * @brief namespaces, which keep the definitions of different libraries
+ *        apart
+ *
+ * Toplevel definitions are stored under their qualified name, like
+ * ``rusp.core/map``. A namespace records how the short names used
+ * inside it map onto those qualified names.
+ */
+use std::hashmap::HashMap;
+
+/// where the builtins live; every namespace can see them
+pub static CORE_NS: &'static str = "rusp.core";
+
+/// where the string library lives
+pub static STRING_NS: &'static str = "rusp.string";
+
+/// where the REPL starts out
+pub static USER_NS: &'static str = "user";
+
+#[deriving(Clone)]
+pub struct Namespace {
+    name: ~str,
+    // alias -> namespace name, from :as
+    priv aliases: HashMap<~str, ~str>,
+    // short name -> qualified name, from :refer
+    priv refers: HashMap<~str, ~str>
+}
+
+impl Namespace {
+    pub fn new(name: &str) -> Namespace {
+        Namespace {
+            name: name.to_owned(),
+            aliases: HashMap::new(),
+            refers: HashMap::new()
+        }
+    }
+    pub fn add_alias(&mut self, alias: &str, ns: &str) {
+        self.aliases.insert(alias.to_owned(), ns.to_owned());
+    }
+    pub fn refer(&mut self, name: &str, qualified: &str) {
+        self.refers.insert(name.to_owned(), qualified.to_owned());
+    }
+    /**
+     * @brief the namespace an alias stands for
+     */
+    pub fn alias(&self, alias: &str) -> Option<~str> {
+        self.aliases.find(&alias.to_owned()).map(|ns| ns.clone())
+    }
+    /**
+     * @brief the qualified name a referred short name stands for
+     */
+    pub fn referred(&self, name: &str) -> Option<~str> {
+        self.refers.find(&name.to_owned()).map(|q| q.clone())
+    }
+}
+
+/**
+ * @brief split a ``ns/name`` symbol into its parts
+ * @return None for unqualified symbols, including ``/`` itself
+ */
+pub fn split_symbol<'a>(sym: &'a str) -> Option<(&'a str, &'a str)>
+{
+    match sym.find('/') {
+        Some(i) if i > 0 && i < sym.len() - 1 => Some((sym.slice_to(i), sym.slice_from(i + 1))),
+        _ => None
+    }
+}
+
+pub fn qualify(ns: &str, name: &str) -> ~str
+{
+    format!("{:s}/{:s}", ns, name)
+}
+
+
+#[test]
+fn test_split_symbol() {
+    assert!(split_symbol("foo.bar/baz") == Some(("foo.bar", "baz")));
+    assert!(split_symbol("baz") == None);
+    assert!(split_symbol("/") == None);
+    assert!(split_symbol("a/") == None);
+    assert!(qualify("rusp.core", "map") == ~"rusp.core/map");
+}
+
+#[test]
+fn test_namespace() {
+    let mut ns = Namespace::new("user");
+    ns.add_alias("s", "rusp.string");
+    ns.refer("upper", "rusp.string/upper");
+    assert!(ns.alias("s") == Some(~"rusp.string"));
+    assert!(ns.alias("t") == None);
+    assert!(ns.referred("upper") == Some(~"rusp.string/upper"));
+    assert!(ns.referred("lower") == None);
+}
@@ -0,0 +1,468 @@
+/**
+ * @file persistent.rs
+ * @brief persistent collections, which share structure between versions
+ *
+ * Adding to a collection makes a new one rather than changing the
+ * old, but the two share everything they have in common, so copies
+ * are cheap and old versions stay valid.
+ */
+use std::rc::Rc;
+
+use types::Element;
+
+mod types;
+
+/// bits of an index used at each level of the trie
+static BITS: uint = 5;
+/// children per trie node
+static WIDTH: uint = 1 << BITS;
+static MASK: uint = WIDTH - 1;
+
+/**
+ * @brief a vector, stored as a 32-way trie
+ *
+ * The last (up to) 32 items are kept in a tail outside the trie, so
+ * most pushes only copy the tail. A push that fills the tail copies
+ * the path from the root to the new leaf, which is O(log32 n).
+ */
+#[deriving(Clone)]
+pub struct PVec {
+    priv cnt: uint,
+    // bits to shift an index by at the root
+    priv shift: uint,
+    priv root: Rc<Node>,
+    priv tail: ~[Element]
+}
+
+enum Node {
+    Branch(~[Rc<Node>]),
+    Leaf(~[Element])
+}
+
+/**
+ * @brief a path of branches, with node at the bottom
+ */
+fn new_path(level: uint, node: Rc<Node>) -> Rc<Node>
+{
+    if level == 0 {
+        node
+    } else {
+        Rc::new(Branch(~[new_path(level - BITS, node)]))
+    }
+}
+
+/**
+ * @brief copy the path down to item i, with x in its place
+ */
+fn set_in(level: uint, node: &Rc<Node>, i: uint, x: Element) -> Rc<Node>
+{
+    match *node.borrow() {
+        Branch(ref kids) => {
+            let mut kids = kids.clone();
+            let sub = (i >> level) & MASK;
+            let child = set_in(level - BITS, &kids[sub], i, x);
+            kids[sub] = child;
+            Rc::new(Branch(kids))
+        },
+        Leaf(ref items) => {
+            let mut items = items.clone();
+            items[i & MASK] = x;
+            Rc::new(Leaf(items))
+        }
+    }
+}
+
+impl PVec {
+    pub fn new() -> PVec {
+        PVec { cnt: 0, shift: BITS, root: Rc::new(Branch(~[])), tail: ~[] }
+    }
+    pub fn from_vec(items: ~[Element]) -> PVec {
+        let mut v = PVec::new();
+        for x in items.move_iter() {
+            v = v.push(x);
+        }
+        v
+    }
+    pub fn len(&self) -> uint {
+        self.cnt
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cnt == 0
+    }
+    /**
+     * @brief the index of the first item in the tail
+     */
+    fn tail_offset(&self) -> uint {
+        if self.cnt < WIDTH { 0 } else { ((self.cnt - 1) >> BITS) << BITS }
+    }
+    pub fn get<'a>(&'a self, i: uint) -> Option<&'a Element> {
+        if i >= self.cnt {
+            return None;
+        }
+        if i >= self.tail_offset() {
+            return Some(&self.tail[i - self.tail_offset()]);
+        }
+        let mut node: &'a Node = self.root.borrow();
+        let mut level = self.shift;
+        loop {
+            match *node {
+                Branch(ref kids) => {
+                    node = kids[(i >> level) & MASK].borrow();
+                    level -= BITS;
+                },
+                Leaf(ref items) => return Some(&items[i & MASK])
+            }
+        }
+    }
+    /**
+     * @brief a new vector with x added to the end
+     */
+    pub fn push(&self, x: Element) -> PVec {
+        if self.cnt - self.tail_offset() < WIDTH {
+            let mut tail = self.tail.clone();
+            tail.push(x);
+            return PVec {
+                cnt: self.cnt + 1,
+                shift: self.shift,
+                root: self.root.clone(),
+                tail: tail
+            };
+        }
+        // the tail is full, so it moves into the trie
+        let leaf = Rc::new(Leaf(self.tail.clone()));
+        let (root, shift) = if (self.cnt >> BITS) > (1 << self.shift) {
+            // no room left under the root, so the trie gets taller
+            let kids = ~[self.root.clone(), new_path(self.shift, leaf)];
+            (Rc::new(Branch(kids)), self.shift + BITS)
+        } else {
+            (self.push_tail(self.shift, &self.root, leaf), self.shift)
+        };
+        PVec { cnt: self.cnt + 1, shift: shift, root: root, tail: ~[x] }
+    }
+    /**
+     * @brief a new vector with item i replaced by x
+     *
+     * Only the path down to i is copied. Fails if i is out of bounds.
+     */
+    pub fn set(&self, i: uint, x: Element) -> PVec {
+        if i >= self.cnt {
+            fail!("PVec: index {:u} out of bounds ({:u})", i, self.cnt);
+        }
+        let offset = self.tail_offset();
+        if i >= offset {
+            let mut tail = self.tail.clone();
+            tail[i - offset] = x;
+            return PVec { cnt: self.cnt, shift: self.shift, root: self.root.clone(), tail: tail };
+        }
+        PVec {
+            cnt: self.cnt,
+            shift: self.shift,
+            root: set_in(self.shift, &self.root, i, x),
+            tail: self.tail.clone()
+        }
+    }
+    /**
+     * @brief copy the path down to where leaf goes, and put it there
+     */
+    fn push_tail(&self, level: uint, parent: &Rc<Node>, leaf: Rc<Node>) -> Rc<Node> {
+        let mut kids = match *parent.borrow() {
+            Branch(ref kids) => kids.clone(),
+            Leaf(_) => fail!("PVec: leaf above the bottom of the trie")
+        };
+        let sub = ((self.cnt - 1) >> level) & MASK;
+        let child = if level == BITS {
+            leaf
+        } else if sub < kids.len() {
+            self.push_tail(level - BITS, &kids[sub], leaf)
+        } else {
+            new_path(level - BITS, leaf)
+        };
+        if sub < kids.len() {
+            kids[sub] = child;
+        } else {
+            kids.push(child);
+        }
+        Rc::new(Branch(kids))
+    }
+    pub fn iter<'a>(&'a self) -> PVecItems<'a> {
+        PVecItems { vec: self, i: 0 }
+    }
+    pub fn to_vec(&self) -> ~[Element] {
+        self.iter().map(|x| x.clone()).collect()
+    }
+}
+
+pub struct PVecItems<'a> {
+    priv vec: &'a PVec,
+    priv i: uint
+}
+
+impl<'a> Iterator<&'a Element> for PVecItems<'a> {
+    fn next(&mut self) -> Option<&'a Element> {
+        let x = self.vec.get(self.i);
+        if x.is_some() {
+            self.i += 1;
+        }
+        x
+    }
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let left = self.vec.len() - self.i;
+        (left, Some(left))
+    }
+}
+
+impl Index<uint, Element> for PVec {
+    fn index(&self, i: &uint) -> Element {
+        match self.get(*i) {
+            Some(x) => x.clone(),
+            None => fail!("PVec: index {:u} out of bounds ({:u})", *i, self.cnt)
+        }
+    }
+}
+
+impl Eq for PVec {
+    fn eq(&self, other: &PVec) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl FromIterator<Element> for PVec {
+    fn from_iterator<T: Iterator<Element>>(iterator: &mut T) -> PVec {
+        let mut v = PVec::new();
+        for x in *iterator {
+            v = v.push(x);
+        }
+        v
+    }
+}
+
+/**
+ * @brief a singly linked list, whose tails are shared
+ *
+ * Adding to the front and taking the rest are O(1).
+ */
+#[deriving(Clone)]
+pub struct ConsList {
+    priv head: Option<Rc<ConsCell>>,
+    priv cnt: uint
+}
+
+struct ConsCell {
+    first: Element,
+    rest: ConsList
+}
+
+impl ConsList {
+    pub fn new() -> ConsList {
+        ConsList { head: None, cnt: 0 }
+    }
+    pub fn from_vec(items: ~[Element]) -> ConsList {
+        let mut l = ConsList::new();
+        for x in items.move_rev_iter() {
+            l = l.cons(x);
+        }
+        l
+    }
+    pub fn len(&self) -> uint {
+        self.cnt
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cnt == 0
+    }
+    /**
+     * @brief a new list with x on the front
+     */
+    pub fn cons(&self, x: Element) -> ConsList {
+        ConsList {
+            head: Some(Rc::new(ConsCell { first: x, rest: self.clone() })),
+            cnt: self.cnt + 1
+        }
+    }
+    pub fn first<'a>(&'a self) -> Option<&'a Element> {
+        self.head.as_ref().map(|cell| &cell.borrow().first)
+    }
+    /**
+     * @brief everything after the first item; empty if there isn't one
+     */
+    pub fn rest(&self) -> ConsList {
+        match self.head {
+            Some(ref cell) => cell.borrow().rest.clone(),
+            None => ConsList::new()
+        }
+    }
+    pub fn get<'a>(&'a self, i: uint) -> Option<&'a Element> {
+        self.iter().nth(i)
+    }
+    pub fn iter<'a>(&'a self) -> ConsItems<'a> {
+        ConsItems { list: self }
+    }
+    pub fn to_vec(&self) -> ~[Element] {
+        self.iter().map(|x| x.clone()).collect()
+    }
+}
+
+pub struct ConsItems<'a> {
+    priv list: &'a ConsList
+}
+
+impl<'a> Iterator<&'a Element> for ConsItems<'a> {
+    fn next(&mut self) -> Option<&'a Element> {
+        match self.list.head {
+            Some(ref cell) => {
+                let cell: &'a ConsCell = cell.borrow();
+                self.list = &cell.rest;
+                Some(&cell.first)
+            },
+            None => None
+        }
+    }
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.list.len(), Some(self.list.len()))
+    }
+}
+
+impl Drop for ConsList {
+    fn drop(&mut self) {
+        // letting each cell drop the next would recurse once per cell,
+        // so free the cells no one else has in a loop instead
+        let mut next = self.head.take();
+        while next.is_some() {
+            let rest;
+            let cell_ref;
+            {
+                let cell = next.take_unwrap();
+                // held here, so freeing cell only drops a reference to it
+                rest = cell.borrow().rest.head.clone();
+                cell_ref = cell.downgrade();
+            }
+            if cell_ref.upgrade().is_some() {
+                // still in another list, along with everything after it
+                break;
+            }
+            next = rest;
+        }
+    }
+}
+
+impl Index<uint, Element> for ConsList {
+    fn index(&self, i: &uint) -> Element {
+        match self.get(*i) {
+            Some(x) => x.clone(),
+            None => fail!("ConsList: index {:u} out of bounds ({:u})", *i, self.cnt)
+        }
+    }
+}
+
+impl Eq for ConsList {
+    fn eq(&self, other: &ConsList) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl FromIterator<Element> for ConsList {
+    fn from_iterator<T: Iterator<Element>>(iterator: &mut T) -> ConsList {
+        ConsList::from_vec(iterator.collect())
+    }
+}
+
+
+#[test]
+fn test_pvec() {
+    let mut v = PVec::new();
+    // enough to need three levels of trie
+    for i in range(0, 40000) {
+        v = v.push(::types::Number(i));
+    }
+    assert!(v.len() == 40000);
+    assert!(v.get(0) == Some(&::types::Number(0)));
+    assert!(v.get(31) == Some(&::types::Number(31)));
+    assert!(v.get(32) == Some(&::types::Number(32)));
+    assert!(v.get(1057) == Some(&::types::Number(1057)));
+    assert!(v[39999] == ::types::Number(39999));
+    assert!(v.get(40000) == None);
+    assert!(v.iter().enumerate().all(|(i, x)| *x == ::types::Number(i as i64)));
+}
+
+#[test]
+fn test_pvec_sharing() {
+    let a = PVec::from_vec(range(0, 100).map(|i| ::types::Number(i)).collect());
+    let b = a.push(::types::Number(100));
+    let c = a.push(::types::Number(-1));
+    // older versions are unchanged
+    assert!(a.len() == 100 && b.len() == 101 && c.len() == 101);
+    assert!(b[100] == ::types::Number(100));
+    assert!(c[100] == ::types::Number(-1));
+    assert!(a == PVec::from_vec(a.to_vec()));
+    assert!(b != c);
+}
+
+#[test]
+fn test_pvec_set() {
+    let a = PVec::from_vec(range(0, 1100).map(|i| ::types::Number(i)).collect());
+    let b = a.set(5, ::types::Number(-5)).set(1090, ::types::Number(-1090));
+    assert!(b[5] == ::types::Number(-5));
+    assert!(b[1090] == ::types::Number(-1090));
+    assert!(b[6] == ::types::Number(6) && b.len() == 1100);
+    // the old version is unchanged
+    assert!(a[5] == ::types::Number(5));
+    assert!(a[1090] == ::types::Number(1090));
+}
+
+#[test]
+fn test_cons_list() {
+    let empty = ConsList::new();
+    let l = ConsList::from_vec(~[::types::Number(1), ::types::Number(2), ::types::Number(3)]);
+    assert!(l.len() == 3);
+    assert!(l.first() == Some(&::types::Number(1)));
+    assert!(l.rest().to_vec() == ~[::types::Number(2), ::types::Number(3)]);
+    assert!(l[2] == ::types::Number(3));
+    let m = l.cons(::types::Number(0));
+    assert!(m.rest() == l);
+    assert!(m.len() == 4);
+    assert!(empty.first() == None);
+    assert!(empty.rest() == empty);
+    assert!(empty.cons(::types::nil).len() == 1);
+}
+
+#[test]
+fn test_cons_list_drop() {
+    // long lists are freed without recursing once per cell
+    let tail = {
+        let mut l = ConsList::new();
+        for i in range(0, 1000000) {
+            l = l.cons(::types::Number(i));
+        }
+        l.rest().rest()
+    };
+    // dropping a list leaves shared tails alone
+    assert!(tail.len() == 999998);
+    assert!(tail.first() == Some(&::types::Number(999997)));
+}
+
+#[bench]
+fn bench_conj_100k(bh: &mut ::extra::test::BenchHarness) {
+    bh.iter(|| {
+        let mut v = PVec::new();
+        for i in range(0, 100000) {
+            v = v.push(::types::Number(i));
+        }
+    });
+}
+
+#[bench]
+fn bench_conj_100k_rusp(bh: &mut ::extra::test::BenchHarness) {
+    // conj through the interpreter, one call per item
+    bh.iter(|| {
+        ::eval::eval("(loop [v [] i 0] (if (< i 100000) (recur (conj v i) (inc i)) (count v)))");
+    });
+}
+
+#[bench]
+fn bench_cons_100k(bh: &mut ::extra::test::BenchHarness) {
+    bh.iter(|| {
+        let mut l = ConsList::new();
+        for i in range(0, 100000) {
+            l = l.cons(::types::Number(i));
+        }
+    });
+}
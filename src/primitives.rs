@@ -6,13 +6,20 @@
  * functions compiled-in, instead of bound functions.
  */
 use std::vec;
+use std::cmp::{Ordering, Less, Equal, Greater};
 
 use types::Element;
 use types::EvalError;
 use types::{Number, String, Boolean, List, Vec, Character};
+use types::{Map, ElementMap, Record, ExInfo, ParseError, nil};
+use types::{Keyword, Symbol, Local, Interned};
+use types::{Function, FuncPrimitive, FuncNative, Lazy, Atom, Multi, Native};
+use types::{list_of, vec_of};
+use tokenizer::tokenize;
 
 mod eval;
 mod types;
+mod tokenizer;
 
 fn unwrap_to_nums(list: &[Element]) -> Option<~[i64]>
 {
@@ -153,8 +160,8 @@ pub fn concat(more: &[Element]) -> Element
     let mut unwrapped: ~[~[Element]] = ~[];
     for elem in more.iter() {
         unwrapped.push(match elem {
-            &List(ref s) => s.to_owned(),
-            &Vec(ref s) => s.to_owned(),
+            &List(ref s) => s.to_vec(),
+            &Vec(ref s) => s.to_vec(),
             &String(ref s) => s.chars().map(|x| Character(x)).collect(),
             _ => return EvalError(~"not a concatable collection type")
         });
@@ -163,7 +170,7 @@ pub fn concat(more: &[Element]) -> Element
     for &ref elem in unwrapped.iter() {
         coll = vec::append(coll, *elem);
     }
-    List(coll)
+    list_of(coll)
 }
 
 
@@ -179,6 +186,542 @@ pub fn equal(list: &[Element]) -> Element
     Boolean(list.slice_from(1).iter().all(|x| x.clone() == first))
 }
 
+#[allow(dead_code)]
+pub fn not_equal(list: &[Element]) -> Element
+{
+    if list.len() == 0 {
+        return EvalError(format!("not=: wrong number of args ({:u}) passed", list.len()));
+    }
+    match equal(list) {
+        Boolean(b) => Boolean(!b),
+        other => other
+    }
+}
+
+/**
+ * @brief what to call a value's type in error messages
+ */
+pub fn type_name(e: &Element) -> ~str
+{
+    let name = match *e {
+        nil => "nil",
+        Boolean(_) => "boolean",
+        Number(_) => "number",
+        Character(_) => "character",
+        String(_) => "string",
+        Symbol(_) | Local(..) | Interned(..) => "symbol",
+        Keyword(_) => "keyword",
+        List(_) => "list",
+        Vec(_) => "vector",
+        Map(_) => "map",
+        Record(..) => "record",
+        Function(_) | FuncPrimitive(_) | FuncNative(_) => "function",
+        Lazy(_) => "lazy-seq",
+        Atom(_) => "atom",
+        Multi(_) => "multimethod",
+        ExInfo(..) => "ex-info",
+        EvalError(_) => "error",
+        ParseError(_) => "parse-error",
+        Native(ref v) => return v.type_name().to_owned()
+    };
+    name.to_owned()
+}
+
+/**
+ * @brief order two values
+ * @param name:     name of the calling function, for errors
+ *
+ * nil comes before everything else. Otherwise only values of the
+ * same type can be compared:
+ *
+ * - booleans: false before true
+ * - numbers: numerically
+ * - characters: by code point
+ * - strings, symbols and keywords: by code point, character by
+ *   character, so a prefix comes first
+ * - vectors: item by item, with a prefix coming first
+ *
+ * Anything else is an error.
+ */
+pub fn compare_elems(name: &str, a: &Element, b: &Element) -> Result<Ordering, Element>
+{
+    match (a, b) {
+        (&nil, &nil) => Ok(Equal),
+        (&nil, _) => Ok(Less),
+        (_, &nil) => Ok(Greater),
+        (&Boolean(x), &Boolean(y)) => Ok(x.cmp(&y)),
+        (&Number(x), &Number(y)) => Ok(x.cmp(&y)),
+        (&Character(x), &Character(y)) => Ok(x.cmp(&y)),
+        (&String(ref x), &String(ref y)) => Ok(x.cmp(y)),
+        (&Symbol(ref x), &Symbol(ref y)) => Ok(x.cmp(y)),
+        (&Keyword(ref x), &Keyword(ref y)) => Ok(x.cmp(y)),
+        (&Vec(ref x), &Vec(ref y)) => {
+            for (i, j) in x.iter().zip(y.iter()) {
+                match compare_elems(name, i, j) {
+                    Ok(Equal) => (),
+                    order => return order
+                }
+            }
+            Ok(x.len().cmp(&y.len()))
+        },
+        _ if type_name(a) == type_name(b) => {
+            Err(EvalError(format!("{:s}: can't compare {:s}s", name, type_name(a))))
+        },
+        _ => Err(EvalError(format!("{:s}: can't compare {:s} with {:s}",
+                                   name, type_name(a), type_name(b))))
+    }
+}
+
+#[allow(dead_code)]
+pub fn compare(list: &[Element]) -> Element
+{
+    match list {
+        [ref a, ref b] => match try_items!(compare_elems("compare", a, b)) {
+            Less => Number(-1),
+            Equal => Number(0),
+            Greater => Number(1)
+        },
+        _ => EvalError(format!("compare: wrong number of args ({:u})", list.len()))
+    }
+}
+
+/**
+ * @brief check that each value is ordered after the last
+ * @param ok:       whether an ordering of neighbours is allowed
+ */
+fn ordered(name: &str, list: &[Element], ok: |Ordering| -> bool) -> Element
+{
+    if list.len() == 0 {
+        return EvalError(format!("{:s}: wrong number of args (0)", name));
+    }
+    let mut result = true;
+    for pair in list.windows(2) {
+        // keep checking types after the answer is known
+        let order = try_items!(compare_elems(name, &pair[0], &pair[1]));
+        result = result && ok(order);
+    }
+    Boolean(result)
+}
+
+#[allow(dead_code)]
+pub fn less(list: &[Element]) -> Element
+{
+    ordered("<", list, |o| o == Less)
+}
+
+#[allow(dead_code)]
+pub fn greater(list: &[Element]) -> Element
+{
+    ordered(">", list, |o| o == Greater)
+}
+
+#[allow(dead_code)]
+pub fn less_equal(list: &[Element]) -> Element
+{
+    ordered("<=", list, |o| o != Greater)
+}
+
+#[allow(dead_code)]
+pub fn greater_equal(list: &[Element]) -> Element
+{
+    ordered(">=", list, |o| o != Less)
+}
+
+
+
+#[allow(dead_code)]
+pub fn get(list: &[Element]) -> Element
+{
+    // (get coll key) or (get coll key not-found)
+    let list_len = list.len();
+    if list_len < 2 || list_len > 3 {
+        return EvalError(format!("get: wrong number of args ({:u})", list_len));
+    }
+    let not_found = if list_len == 3 { list[2].clone() } else { nil };
+    let found = match (&list[0], &list[1]) {
+        (&Map(ref m), key) | (&Record(_, ref m), key) => m.find(key).map(|x| x.clone()),
+        (&Vec(ref v), &Number(i)) if i >= 0 && (i as uint) < v.len() => Some(v[i as uint]),
+        _ => None
+    };
+    found.unwrap_or(not_found)
+}
+
+/**
+ * @brief a map with each key/value pair of kvs put in it
+ */
+fn assoc_map(m: &ElementMap, kvs: &[Element]) -> ElementMap
+{
+    let mut m = m.clone();
+    for kv in kvs.chunks(2) {
+        m.insert(kv[0].clone(), kv[1].clone());
+    }
+    m
+}
+
+#[allow(dead_code)]
+pub fn assoc(list: &[Element]) -> Element
+{
+    // (assoc coll key value & kvs); a record stays the same type
+    if list.len() < 3 || list.len() % 2 == 0 {
+        return EvalError(format!("assoc: wrong number of args ({:u})", list.len()));
+    }
+    let kvs = list.slice_from(1);
+    match list[0] {
+        Map(ref m) => Map(assoc_map(m, kvs)),
+        nil => Map(assoc_map(&ElementMap::new(), kvs)),
+        Record(ref name, ref fields) => Record(name.clone(), assoc_map(fields, kvs)),
+        Vec(ref v) => {
+            // an index one past the end adds to it
+            let mut v = v.clone();
+            for kv in kvs.chunks(2) {
+                v = match kv[0] {
+                    Number(i) if i >= 0 && (i as uint) < v.len() => v.set(i as uint, kv[1].clone()),
+                    Number(i) if i >= 0 && (i as uint) == v.len() => v.push(kv[1].clone()),
+                    Number(i) => return EvalError(format!("assoc: index {:d} out of range", i)),
+                    _ => return EvalError(~"assoc: vector index must be a number")
+                };
+            }
+            Vec(v)
+        },
+        ref other => EvalError(format!("assoc: can't assoc on a {:s}", type_name(other)))
+    }
+}
+
+#[allow(dead_code)]
+pub fn hash_map(list: &[Element]) -> Element
+{
+    if list.len() % 2 != 0 {
+        return EvalError(~"hash-map: expected an even number of args");
+    }
+    let pairs = list.chunks(2).map(|kv| (kv[0].clone(), kv[1].clone())).collect();
+    Map(ElementMap::from_pairs(pairs))
+}
+
+#[allow(dead_code)]
+pub fn ex_info(list: &[Element]) -> Element
+{
+    // an error with a message and a map of data, for throwing
+    match list {
+        [String(ref msg), Map(ref data)] => ExInfo(msg.clone(), ~Map(data.clone())),
+        [_, _] => EvalError(~"ex-info: expected a string and a map"),
+        _ => EvalError(format!("ex-info: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn ex_data(list: &[Element]) -> Element
+{
+    match list {
+        [ExInfo(_, ref data)] => *data.clone(),
+        [_] => nil,
+        _ => EvalError(format!("ex-data: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn ex_message(list: &[Element]) -> Element
+{
+    match list {
+        [ExInfo(ref msg, _)] => String(msg.clone()),
+        [EvalError(ref msg)] => String(msg.clone()),
+        [_] => nil,
+        _ => EvalError(format!("ex-message: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn boolean(list: &[Element]) -> Element
+{
+    // only nil and false are falsey
+    match list {
+        [nil] | [Boolean(false)] => Boolean(false),
+        [_] => Boolean(true),
+        _ => EvalError(format!("boolean: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_true(list: &[Element]) -> Element
+{
+    match list {
+        [ref x] => Boolean(*x == Boolean(true)),
+        _ => EvalError(format!("true?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_false(list: &[Element]) -> Element
+{
+    match list {
+        [ref x] => Boolean(*x == Boolean(false)),
+        _ => EvalError(format!("false?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_nil(list: &[Element]) -> Element
+{
+    match list {
+        [ref x] => Boolean(*x == nil),
+        _ => EvalError(format!("nil?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_some(list: &[Element]) -> Element
+{
+    match list {
+        [ref x] => Boolean(*x != nil),
+        _ => EvalError(format!("some?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn read_string(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s)] => match tokenize(*s) {
+            ParseError(e) => EvalError(format!("read-string: {:s}", e)),
+            form => form
+        },
+        [_] => EvalError(~"read-string: expected a string"),
+        _ => EvalError(format!("read-string: wrong number of args ({:u})", list.len()))
+    }
+}
+
+
+/**
+ * @brief the items of a collection, in sequence order
+ * @param name:     name of the calling function, for errors
+ * @param coll:     a List, Vec, String, Map, Record or nil
+ *
+ * Strings are sequences of Characters, and maps and records are
+ * sequences of [key value] vectors.
+ */
+pub fn seq_items(name: &str, coll: &Element) -> Result<~[Element], Element>
+{
+    match *coll {
+        List(ref l) => Ok(l.to_vec()),
+        Vec(ref v) => Ok(v.to_vec()),
+        String(ref s) => Ok(s.chars().map(|c| Character(c)).collect()),
+        Map(ref m) | Record(_, ref m) => {
+            Ok(m.iter().map(|&(ref k, ref v)| vec_of(~[k.clone(), v.clone()])).collect())
+        },
+        nil => Ok(~[]),
+        _ => Err(EvalError(format!("{:s}: expected a collection", name)))
+    }
+}
+
+#[allow(dead_code)]
+pub fn cons(list: &[Element]) -> Element
+{
+    match list {
+        [ref x, ref coll] => list_of(~[x.clone()] + try_items!(seq_items("cons", coll))),
+        _ => EvalError(format!("cons: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn conj(list: &[Element]) -> Element
+{
+    // add to wherever is cheapest: the front of lists, the end of vectors
+    if list.len() < 1 {
+        return EvalError(~"conj: wrong number of args (0)");
+    }
+    let xs = list.slice_from(1);
+    match list[0] {
+        List(ref l) => List(xs.iter().fold(l.clone(), |l, x| l.cons(x.clone()))),
+        nil => List(xs.rev_iter().map(|x| x.clone()).collect()),
+        Vec(ref v) => Vec(xs.iter().fold(v.clone(), |v, x| v.push(x.clone()))),
+        Map(ref m) => {
+            let mut m = m.clone();
+            for x in xs.iter() {
+                match *x {
+                    Vec(ref kv) if kv.len() == 2 => m.insert(kv[0], kv[1]),
+                    _ => return EvalError(~"conj: map entries must be [key value] vectors")
+                }
+            }
+            Map(m)
+        },
+        _ => EvalError(~"conj: expected a collection")
+    }
+}
+
+#[allow(dead_code)]
+pub fn nth(list: &[Element]) -> Element
+{
+    // (nth coll index) or (nth coll index not-found)
+    let list_len = list.len();
+    if list_len < 2 || list_len > 3 {
+        return EvalError(format!("nth: wrong number of args ({:u})", list_len));
+    }
+    let items = try_items!(seq_items("nth", &list[0]));
+    match list[1] {
+        Number(i) if i >= 0 && (i as uint) < items.len() => items[i].clone(),
+        Number(_) if list_len == 3 => list[2].clone(),
+        Number(i) => EvalError(format!("nth: index {:d} out of range", i)),
+        _ => EvalError(~"nth: index must be a number")
+    }
+}
+
+#[allow(dead_code)]
+pub fn count(list: &[Element]) -> Element
+{
+    match list {
+        [ref coll] => Number(try_items!(seq_items("count", coll)).len() as i64),
+        _ => EvalError(format!("count: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_empty(list: &[Element]) -> Element
+{
+    match list {
+        [ref coll] => Boolean(try_items!(seq_items("empty?", coll)).len() == 0),
+        _ => EvalError(format!("empty?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn rangefn(list: &[Element]) -> Element
+{
+    // (range end), (range start end) or (range start end step)
+    let (start, end, step) = match list {
+        [Number(end)] => (0, end, 1),
+        [Number(start), Number(end)] => (start, end, 1),
+        [Number(start), Number(end), Number(step)] => (start, end, step),
+        [_] | [_, _] | [_, _, _] => return EvalError(~"range: args must be numbers"),
+        _ => return EvalError(format!("range: wrong number of args ({:u})", list.len()))
+    };
+    if step == 0 {
+        return EvalError(~"range: step must not be 0");
+    }
+    let mut out: ~[Element] = ~[];
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        out.push(Number(i));
+        i += step;
+    }
+    list_of(out)
+}
+
+#[allow(dead_code)]
+pub fn take(list: &[Element]) -> Element
+{
+    match list {
+        [Number(n), ref coll] => {
+            let items = try_items!(seq_items("take", coll));
+            let n = if n < 0 { 0 } else { n as uint };
+            list_of(items.slice_to(if n < items.len() { n } else { items.len() }).to_owned())
+        },
+        [_, _] => EvalError(~"take: count must be a number"),
+        _ => EvalError(format!("take: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn dropfn(list: &[Element]) -> Element
+{
+    match list {
+        [Number(n), ref coll] => {
+            let items = try_items!(seq_items("drop", coll));
+            let n = if n < 0 { 0 } else { n as uint };
+            list_of(items.slice_from(if n < items.len() { n } else { items.len() }).to_owned())
+        },
+        [_, _] => EvalError(~"drop: count must be a number"),
+        _ => EvalError(format!("drop: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn reverse(list: &[Element]) -> Element
+{
+    match list {
+        [ref coll] => {
+            let items = try_items!(seq_items("reverse", coll));
+            List(items.rev_iter().map(|x| x.clone()).collect())
+        },
+        _ => EvalError(format!("reverse: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn partition(list: &[Element]) -> Element
+{
+    // (partition n coll) or (partition n step coll); a short last
+    // partition is dropped
+    let (n, step, coll) = match list {
+        [Number(n), ref coll] => (n, n, coll),
+        [Number(n), Number(step), ref coll] => (n, step, coll),
+        [_, _] | [_, _, _] => return EvalError(~"partition: sizes must be numbers"),
+        _ => return EvalError(format!("partition: wrong number of args ({:u})", list.len()))
+    };
+    if n <= 0 || step <= 0 {
+        return EvalError(~"partition: sizes must be positive");
+    }
+    let items = try_items!(seq_items("partition", coll));
+    let (n, step) = (n as uint, step as uint);
+    let mut out: ~[Element] = ~[];
+    let mut i = 0;
+    while i + n <= items.len() {
+        out.push(list_of(items.slice(i, i + n).to_owned()));
+        i += step;
+    }
+    list_of(out)
+}
+
+#[allow(dead_code)]
+pub fn interleave(list: &[Element]) -> Element
+{
+    // the first item of each coll, then the second, up to the shortest
+    let mut colls: ~[~[Element]] = ~[];
+    for coll in list.iter() {
+        colls.push(try_items!(seq_items("interleave", coll)));
+    }
+    let shortest = colls.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut out: ~[Element] = ~[];
+    let mut i = 0;
+    while i < shortest {
+        for c in colls.iter() {
+            out.push(c[i].clone());
+        }
+        i += 1;
+    }
+    list_of(out)
+}
+
+#[allow(dead_code)]
+pub fn zipmap(list: &[Element]) -> Element
+{
+    match list {
+        [ref keys, ref vals] => {
+            let keys = try_items!(seq_items("zipmap", keys));
+            let vals = try_items!(seq_items("zipmap", vals));
+            let pairs = keys.iter().zip(vals.iter()).map(|(k, v)| (k.clone(), v.clone())).collect();
+            Map(ElementMap::from_pairs(pairs))
+        },
+        _ => EvalError(format!("zipmap: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn frequencies(list: &[Element]) -> Element
+{
+    match list {
+        [ref coll] => {
+            let mut counts = ElementMap::new();
+            for x in try_items!(seq_items("frequencies", coll)).iter() {
+                let n = match counts.find(x) {
+                    Some(&Number(n)) => n + 1,
+                    _ => 1
+                };
+                counts.insert(x.clone(), Number(n));
+            }
+            Map(counts)
+        },
+        _ => EvalError(format!("frequencies: wrong number of args ({:u})", list.len()))
+    }
+}
 
 
 #[test]
@@ -237,8 +780,8 @@ fn test_mod() {
 
 #[test]
 fn test_concat() {
-    assert!(::eval::eval("(concat [1] [2])") == List(~[Number(1), Number(2)]));
-    assert!(::eval::eval("(concat \"ab\" \"cd\")") == List(~[Character('a'),
+    assert!(::eval::eval("(concat [1] [2])") == list_of(~[Number(1), Number(2)]));
+    assert!(::eval::eval("(concat \"ab\" \"cd\")") == list_of(~[Character('a'),
                                                      Character('b'),
                                                      Character('c'),
                                                      Character('d')]));
@@ -255,3 +798,193 @@ fn test_equal() {
     assert!(::eval::eval("(= [1 2 3] [1 2])") == Boolean(false));
 }
 
+#[test]
+fn test_not_equal() {
+    assert!(::eval::eval("(not= 1 2)") == Boolean(true));
+    assert!(::eval::eval("(not= [1 2] [1 2])") == Boolean(false));
+    assert!(::eval::eval("(not=)") == EvalError(~"not=: wrong number of args (0) passed"));
+}
+
+#[test]
+fn test_compare() {
+    assert!(::eval::eval("(compare 1 2)") == Number(-1));
+    assert!(::eval::eval("(compare 2 2)") == Number(0));
+    assert!(::eval::eval("(compare (first \"b\") (first \"a\"))") == Number(1));
+    assert!(::eval::eval("(compare \"abc\" \"abd\")") == Number(-1));
+    assert!(::eval::eval("(compare \"ab\" \"abc\")") == Number(-1));
+    // by code point, so non-ASCII sorts after ASCII
+    assert!(compare([String(~"é"), String(~"z")]) == Number(1));
+    assert!(compare([String(~"日本"), String(~"日")]) == Number(1));
+    assert!(::eval::eval("(compare :b :a)") == Number(1));
+    assert!(::eval::eval("(compare [1 2] [1 3])") == Number(-1));
+    assert!(::eval::eval("(compare [1 2 0] [1 2])") == Number(1));
+    assert!(::eval::eval("(compare [[1] \"a\"] [[1] \"a\"])") == Number(0));
+    assert!(::eval::eval("(compare nil 1)") == Number(-1));
+    assert!(::eval::eval("(compare false true)") == Number(-1));
+    assert!(::eval::eval("(compare 1 \"a\")")
+            == EvalError(~"compare: can't compare number with string"));
+    assert!(::eval::eval("(compare [1] [\"a\"])")
+            == EvalError(~"compare: can't compare number with string"));
+    assert!(::eval::eval("(compare {} {})") == EvalError(~"compare: can't compare maps"));
+}
+
+#[test]
+fn test_type_name() {
+    assert!(type_name(&Number(1)) == ~"number");
+    assert!(type_name(&::eval::eval("(atom 1)")) == ~"atom");
+    assert!(type_name(&::eval::eval("(range)")) == ~"lazy-seq");
+    assert!(type_name(&::eval::eval("(ex-info \"x\" {})")) == ~"ex-info");
+    assert!(type_name(&::eval::eval("inc")) == ~"function");
+    assert!(type_name(&EvalError(~"x")) == ~"error");
+    assert!(type_name(&Native(::types::NativeValue::new("Socket", 1u8))) == ~"Socket");
+    // errors name the real type
+    assert!(::eval::eval("(compare (atom 1) (atom 2))")
+            == EvalError(~"compare: can't compare atoms"));
+    assert!(::eval::eval("(compare 1 (atom 1))")
+            == EvalError(~"compare: can't compare number with atom"));
+}
+
+#[test]
+fn test_ordering() {
+    assert!(::eval::eval("(< 1 2 3)") == Boolean(true));
+    assert!(::eval::eval("(< 1 3 2)") == Boolean(false));
+    assert!(::eval::eval("(< 1 1)") == Boolean(false));
+    assert!(::eval::eval("(<= 1 1 2)") == Boolean(true));
+    assert!(::eval::eval("(> 3 2 1)") == Boolean(true));
+    assert!(::eval::eval("(>= 3 3 4)") == Boolean(false));
+    assert!(::eval::eval("(< 5)") == Boolean(true));
+    assert!(::eval::eval("(< \"a\" \"b\")") == Boolean(true));
+    assert!(::eval::eval("(<)") == EvalError(~"<: wrong number of args (0)"));
+    assert!(::eval::eval("(< 1 \"a\")") == EvalError(~"<: can't compare number with string"));
+    // the types are checked even once the answer is known
+    assert!(::eval::eval("(> 1 2 \"a\")") == EvalError(~">: can't compare number with string"));
+}
+
+
+#[test]
+fn test_get() {
+    assert!(::eval::eval("(get {:a 1} :a)") == Number(1));
+    assert!(::eval::eval("(get {:a 1} :b)") == nil);
+    assert!(::eval::eval("(get {:a 1} :b 2)") == Number(2));
+    assert!(::eval::eval("(get [5 6] 1)") == Number(6));
+    assert!(::eval::eval("(get [5 6] 2)") == nil);
+    assert!(::eval::eval("(get nil :a)") == nil);
+    assert!(::eval::eval("(= (hash-map :a 1 :b 2) {:b 2 :a 1})") == Boolean(true));
+}
+
+#[test]
+fn test_assoc() {
+    assert!(::eval::eval("(assoc {:a 1} :b 2 :a 3)") == ::eval::eval("{:a 3 :b 2}"));
+    assert!(::eval::eval("(assoc nil :a 1)") == ::eval::eval("{:a 1}"));
+    assert!(::eval::eval("(assoc [1 2] 0 :x 2 :y)") == ::eval::eval("[:x 2 :y]"));
+    assert!(::eval::eval("(assoc [1 2] 3 :x)") == EvalError(~"assoc: index 3 out of range"));
+    assert!(::eval::eval("(assoc {:a 1} :b)") == EvalError(~"assoc: wrong number of args (2)"));
+    assert!(::eval::eval("(assoc 1 :a 2)") == EvalError(~"assoc: can't assoc on a number"));
+}
+
+#[test]
+fn test_ex_info() {
+    let data = Map(ElementMap::from_pairs(~[(::types::Keyword(~"code"), Number(7))]));
+    assert!(::eval::eval("(ex-info \"bad\" {:code 7})") == ExInfo(~"bad", ~data.clone()));
+    assert!(::eval::eval("(ex-data (ex-info \"bad\" {:code 7}))") == data);
+    assert!(::eval::eval("(ex-message (ex-info \"bad\" {:code 7}))") == String(~"bad"));
+    assert!(::eval::eval("(ex-data 5)") == nil);
+    assert!(::eval::eval("(ex-info \"bad\" 5)") == EvalError(~"ex-info: expected a string and a map"));
+}
+
+#[test]
+fn test_boolean_predicates() {
+    assert!(::eval::eval("(boolean nil)") == Boolean(false));
+    assert!(::eval::eval("(boolean false)") == Boolean(false));
+    assert!(::eval::eval("(boolean 0)") == Boolean(true));
+    assert!(::eval::eval("(boolean [])") == Boolean(true));
+    assert!(::eval::eval("(true? true)") == Boolean(true));
+    assert!(::eval::eval("(true? 1)") == Boolean(false));
+    assert!(::eval::eval("(false? false)") == Boolean(true));
+    assert!(::eval::eval("(false? nil)") == Boolean(false));
+    assert!(::eval::eval("(nil? nil)") == Boolean(true));
+    assert!(::eval::eval("(nil? false)") == Boolean(false));
+    assert!(::eval::eval("(some? false)") == Boolean(true));
+    assert!(::eval::eval("(some? nil)") == Boolean(false));
+    assert!(::eval::eval("(nil?)") == EvalError(~"nil?: wrong number of args (0)"));
+}
+
+#[test]
+fn test_read_string() {
+    assert!(::eval::eval("(read-string \"(+ 1 2)\")") == list_of(~[::types::Symbol(~"+"),
+                                                               Number(1),
+                                                               Number(2)]));
+    assert!(::eval::eval("(read-string \"[:a nil]\")") == vec_of(~[::types::Keyword(~"a"), nil]));
+    assert!(::eval::eval("(read-string \"(1\")")
+            == EvalError(~"read-string: unbalanced parentheses"));
+    assert!(::eval::eval("(read-string 1)") == EvalError(~"read-string: expected a string"));
+}
+
+#[test]
+fn test_first_rest_next() {
+    assert!(::eval::eval("(first [1 2])") == Number(1));
+    assert!(::eval::eval("(first '(1 2))") == Number(1));
+    assert!(::eval::eval("(first \"ab\")") == Character('a'));
+    assert!(::eval::eval("(first [])") == nil);
+    assert!(::eval::eval("(first nil)") == nil);
+    assert!(::eval::eval("(rest [1 2])") == list_of(~[Number(2)]));
+    assert!(::eval::eval("(rest [])") == list_of(~[]));
+    assert!(::eval::eval("(next [1 2])") == list_of(~[Number(2)]));
+    assert!(::eval::eval("(next [1])") == nil);
+    assert!(::eval::eval("(first 1)") == EvalError(~"first: expected a collection"));
+}
+
+#[test]
+fn test_cons_conj() {
+    assert!(::eval::eval("(cons 1 [2 3])") == list_of(~[Number(1), Number(2), Number(3)]));
+    assert!(::eval::eval("(cons 1 nil)") == list_of(~[Number(1)]));
+    assert!(::eval::eval("(conj [1] 2 3)") == vec_of(~[Number(1), Number(2), Number(3)]));
+    assert!(::eval::eval("(conj '(1) 2 3)") == list_of(~[Number(3), Number(2), Number(1)]));
+    assert!(::eval::eval("(conj nil 1)") == list_of(~[Number(1)]));
+    assert!(::eval::eval("(= (conj {:a 1} [:b 2]) {:a 1 :b 2})") == Boolean(true));
+}
+
+#[test]
+fn test_nth_count_empty() {
+    assert!(::eval::eval("(nth [1 2] 1)") == Number(2));
+    assert!(::eval::eval("(nth \"abc\" 2)") == Character('c'));
+    assert!(::eval::eval("(nth [1 2] 5 :none)") == ::types::Keyword(~"none"));
+    assert!(::eval::eval("(nth [1 2] 5)") == EvalError(~"nth: index 5 out of range"));
+    assert!(::eval::eval("(count [1 2 3])") == Number(3));
+    assert!(::eval::eval("(count \"ab\")") == Number(2));
+    assert!(::eval::eval("(count {:a 1})") == Number(1));
+    assert!(::eval::eval("(count nil)") == Number(0));
+    assert!(::eval::eval("(empty? [])") == Boolean(true));
+    assert!(::eval::eval("(empty? '(1))") == Boolean(false));
+}
+
+#[test]
+fn test_range_take_drop() {
+    assert!(::eval::eval("(range 3)") == list_of(~[Number(0), Number(1), Number(2)]));
+    assert!(::eval::eval("(range 1 3)") == list_of(~[Number(1), Number(2)]));
+    assert!(::eval::eval("(range 5 0 -2)") == list_of(~[Number(5), Number(3), Number(1)]));
+    assert!(::eval::eval("(range 1 2 0)") == EvalError(~"range: step must not be 0"));
+    assert!(::eval::eval("(take 2 [1 2 3])") == list_of(~[Number(1), Number(2)]));
+    assert!(::eval::eval("(take 5 [1])") == list_of(~[Number(1)]));
+    assert!(::eval::eval("(drop 2 [1 2 3])") == list_of(~[Number(3)]));
+    assert!(::eval::eval("(drop 5 \"ab\")") == list_of(~[]));
+    assert!(::eval::eval("(reverse [1 2])") == list_of(~[Number(2), Number(1)]));
+}
+
+#[test]
+fn test_partition_interleave() {
+    assert!(::eval::eval("(partition 2 [1 2 3 4 5])") == list_of(~[list_of(~[Number(1), Number(2)]),
+                                                              list_of(~[Number(3), Number(4)])]));
+    assert!(::eval::eval("(partition 2 1 [1 2 3])") == list_of(~[list_of(~[Number(1), Number(2)]),
+                                                            list_of(~[Number(2), Number(3)])]));
+    assert!(::eval::eval("(interleave [1 2 3] \"ab\")") == list_of(~[Number(1), Character('a'),
+                                                                 Number(2), Character('b')]));
+    assert!(::eval::eval("(interleave)") == list_of(~[]));
+}
+
+#[test]
+fn test_zipmap_frequencies() {
+    assert!(::eval::eval("(= (zipmap [:a :b] [1 2 3]) {:a 1 :b 2})") == Boolean(true));
+    assert!(::eval::eval("(= (frequencies [:a :b :a]) {:a 2 :b 1})") == Boolean(true));
+    assert!(::eval::eval("(get (frequencies \"aab\") (first \"a\"))") == Number(2));
+}
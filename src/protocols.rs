@@ -0,0 +1,223 @@
+/**
+ * @file protocols.rs
+ * @brief protocols, and choosing their implementations by type
+ *
+ * A protocol is a set of fns whose implementation is picked by the
+ * type of their first arg. Types go by the names used in error
+ * messages: number, string, vector, list, map, nil and so on. Lazy
+ * seqs are looked up as lazy-seq first, then as lists, and a record by
+ * the name of its own type first, then as record.
+ *
+ * Embedders implement protocols from Rust with
+ * Bindings::extend_protocol, and give values of their own a type by
+ * wrapping them in a NativeValue.
+ */
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use types::Element;
+use types::{Symbol, Keyword, Map, ElementMap, Record, Lazy};
+#[cfg(test)]
+use types::{Native, NativeValue};
+use types::vec_of;
+
+use functypes::{NativeFn, NativeFunc};
+
+use primitives::type_name;
+
+use eval::{Bindings, EvalResult, eval_error};
+
+mod types;
+
+/**
+ * @brief a protocol's methods, and what implements them
+ *
+ * Clones share their implementations, so the protocol's fns see every
+ * extend-type.
+ */
+#[deriving(Clone)]
+pub struct ProtocolRef {
+    priv cell: Rc<RefCell<ProtocolState>>
+}
+
+struct ProtocolState {
+    name: ~str,
+    methods: ~[~str],
+    // (type, method, fn)
+    impls: ~[(~str, ~str, Element)]
+}
+
+impl ProtocolRef {
+    pub fn new(name: &str, methods: ~[~str]) -> ProtocolRef {
+        ProtocolRef { cell: Rc::new(RefCell::new(ProtocolState {
+            name: name.to_owned(),
+            methods: methods,
+            impls: ~[]
+        })) }
+    }
+    pub fn name(&self) -> ~str {
+        self.cell.borrow().with(|state| state.name.clone())
+    }
+    pub fn has_method(&self, method: &str) -> bool {
+        self.cell.borrow().with(|state| state.methods.iter().any(|m| m.as_slice() == method))
+    }
+    /**
+     * @brief implement a method for a type, replacing any it had
+     */
+    pub fn extend(&self, type_name: &str, method: &str, f: Element) {
+        self.cell.borrow().with_mut(|state| {
+            state.impls.retain(|&(ref t, ref m, _)| {
+                t.as_slice() != type_name || m.as_slice() != method
+            });
+            state.impls.push((type_name.to_owned(), method.to_owned(), f.clone()));
+        });
+    }
+    pub fn find(&self, type_name: &str, method: &str) -> Option<Element> {
+        self.cell.borrow().with(|state| {
+            state.impls.iter().find(|&&(ref t, ref m, _)| {
+                t.as_slice() == type_name && m.as_slice() == method
+            }).map(|&(_, _, ref f)| f.clone())
+        })
+    }
+    /**
+     * @brief what the protocol's name is bound to
+     * @param q:        the protocol's qualified name
+     */
+    pub fn to_map(&self, q: &str) -> Element {
+        let methods = self.cell.borrow().with(|state| {
+            state.methods.iter().map(|m| Symbol(m.clone())).collect()
+        });
+        Map(ElementMap::from_pairs(~[(Keyword(~"name"), Symbol(q.to_owned())),
+                                     (Keyword(~"methods"), vec_of(methods))]))
+    }
+}
+
+/**
+ * @brief the names a value's type goes by, most specific first
+ */
+pub fn type_names(e: &Element) -> ~[~str]
+{
+    match *e {
+        Record(ref name, _) => ~[name.clone(), ~"record"],
+        Lazy(_) => ~[~"lazy-seq", ~"list"],
+        ref other => ~[type_name(other)]
+    }
+}
+
+/// one of a protocol's fns
+struct ProtocolFn {
+    protocol: ProtocolRef,
+    method: ~str
+}
+
+impl NativeFn for ProtocolFn {
+    fn call(&self, bindings: &mut Bindings, args: &[Element]) -> EvalResult {
+        if args.len() == 0 {
+            return eval_error(format!("{:s}: wrong number of args (0)", self.method));
+        }
+        let names = type_names(&args[0]);
+        for t in names.iter() {
+            match self.protocol.find(t.as_slice(), self.method.as_slice()) {
+                Some(f) => return bindings.apply(f, args.to_owned()),
+                None => ()
+            }
+        }
+        eval_error(format!("{:s}: protocol {:s} is not implemented for {:s}",
+                           self.method, self.protocol.name(), names[0]))
+    }
+    fn clone_box(&self) -> ~NativeFn {
+        ~ProtocolFn { protocol: self.protocol.clone(), method: self.method.clone() } as ~NativeFn
+    }
+}
+
+/**
+ * @brief the fn that calls a protocol method
+ */
+pub fn protocol_fn(protocol: &ProtocolRef, method: &str) -> Element
+{
+    NativeFunc::with_state(~ProtocolFn {
+        protocol: protocol.clone(),
+        method: method.to_owned()
+    } as ~NativeFn)
+}
+
+#[test]
+fn test_protocols() {
+    let mut bindings = ::eval::Bindings::new();
+    bindings.eval("(defprotocol Describe (describe [x]) (describe-with [x prefix]))");
+    bindings.eval("(extend-protocol Describe
+                     number (describe [n] \"a number\")
+                            (describe-with [n p] (str p n))
+                     string (describe [s] (str \"a string of \" (count s))))");
+    bindings.eval("(extend-type vector Describe (describe [v] (str \"a vector of \" (count v))))");
+    bindings.eval("(extend-type list Describe (describe [l] \"a list\"))");
+    bindings.eval("(extend-type map Describe (describe [m] \"a map\"))");
+    assert!(bindings.eval("[(describe 1) (describe \"ab\") (describe [1 2 3]) (describe '(1))
+                            (describe {:a 1}) (describe (map inc [1]))]")
+            == Ok(vec_of(~[::types::String(~"a number"), ::types::String(~"a string of 2"),
+                           ::types::String(~"a vector of 3"), ::types::String(~"a list"),
+                           ::types::String(~"a map"), ::types::String(~"a list")])));
+    assert!(bindings.eval("(describe-with 5 \"n=\")") == Ok(::types::String(~"n=5")));
+    // records fall back to the record implementation
+    bindings.eval("(defrecord Point [x y])");
+    bindings.eval("(defrecord Size [w h])");
+    bindings.eval("(extend-type record Describe (describe [r] \"a record\"))");
+    bindings.eval("(extend-type Point Describe (describe [p] (str \"a point at \" (:x p))))");
+    assert!(bindings.eval("(describe (->Point 3 4))") == Ok(::types::String(~"a point at 3")));
+    assert!(bindings.eval("(describe (->Size 3 4))") == Ok(::types::String(~"a record")));
+    // an implementation can be replaced
+    bindings.eval("(extend-type number Describe (describe [n] \"still a number\"))");
+    assert!(bindings.eval("(describe 1)") == Ok(::types::String(~"still a number")));
+    assert!(bindings.eval("(describe (repeat 1))") == Ok(::types::String(~"a list")));
+    bindings.eval("(extend-type lazy-seq Describe (describe [l] \"a lazy seq\"))");
+    assert!(bindings.eval("(describe (repeat 1))") == Ok(::types::String(~"a lazy seq")));
+    assert!(bindings.eval("(describe :k)")
+            == Err(::types::EvalError(
+                ~"describe: protocol Describe is not implemented for keyword")));
+    assert!(bindings.eval("(describe-with \"s\" 1)")
+            == Err(::types::EvalError(
+                ~"describe-with: protocol Describe is not implemented for string")));
+    assert!(bindings.eval("(extend-type number Describe (nope [n] 1))")
+            == Err(::types::EvalError(~"extend-type: nope is not a method of Describe")));
+    assert!(bindings.eval("(extend-type number Nothing (describe [n] 1))")
+            == Err(::types::EvalError(~"extend-type: Nothing is not a protocol")));
+    // the protocol fns are called on the vm too
+    bindings.set_compiled(true);
+    assert!(bindings.eval("(describe (->Point 5 6))") == Ok(::types::String(~"a point at 5")));
+}
+
+#[cfg(test)]
+struct Socket {
+    port: u16
+}
+
+#[cfg(test)]
+fn socket_describe(list: &[Element]) -> Element
+{
+    match list {
+        [Native(ref v)] => match v.get::<Socket>() {
+            Some(socket) => ::types::String(format!("socket on {:u}", socket.port)),
+            None => ::types::EvalError(~"socket-describe: expected a socket")
+        },
+        _ => ::types::EvalError(~"socket-describe: expected a socket")
+    }
+}
+
+#[test]
+fn test_native_protocols() {
+    // an embedder's own type, implemented in Rust
+    let mut bindings = ::eval::Bindings::new();
+    bindings.eval("(defprotocol Describe (describe [x]))");
+    let describe = ::functypes::RustFunc::new(socket_describe);
+    assert!(bindings.extend_protocol("Describe", "Socket", "describe", describe.clone()).is_ok());
+    bindings.define("sock", Native(NativeValue::new("Socket", Socket { port: 80 })), []);
+    assert!(bindings.eval("(describe sock)") == Ok(::types::String(~"socket on 80")));
+    assert!(bindings.eval("(str sock)") == Ok(::types::String(~"#object[Socket]")));
+    assert!(bindings.eval("(describe (atom 1))")
+            == Err(::types::EvalError(~"describe: protocol Describe is not implemented for atom")));
+    assert!(bindings.extend_protocol("Describe", "Socket", "close", describe.clone()).is_err());
+    assert!(bindings.extend_protocol("Nothing", "Socket", "describe", describe).is_err());
+}
+
+// all of the above, on the vm
+compiled_suite!(test_protocols, test_native_protocols)
@@ -0,0 +1,129 @@
+/**
+ * @file records.rs
+ * @brief builtins behind defrecord
+ *
+ * A record is a map with a type. The fields a record type declares
+ * always come first, in order, and compare like map entries, but a
+ * record is never equal to a plain map or to a record of another type.
+ * A type's name is qualified with the namespace that defined it, as in
+ * #user.Point{:x 1 :y 2}. Records always print that way, as Clojure's
+ * do, rather than as #Point{...} in their own namespace, so what is
+ * printed reads back as the same type from any namespace. The dot
+ * follows Clojure's class names; vars are still written ns/name.
+ */
+use types::Element;
+use types::EvalError;
+use types::{Symbol, Keyword, Boolean, Vec, Map, Record, ElementMap, nil};
+
+mod types;
+
+#[allow(dead_code)]
+pub fn record(list: &[Element]) -> Element
+{
+    // (record Name [:field ...] fields): fields missing from the map
+    // are nil, and keys it has that aren't declared come last
+    let (name, declared, given) = match list {
+        [Symbol(ref name), Vec(ref declared), Map(ref given)]
+        | [Symbol(ref name), Vec(ref declared), Record(_, ref given)] => {
+            (name, declared, given.clone())
+        },
+        [Symbol(ref name), Vec(ref declared), nil] => (name, declared, ElementMap::new()),
+        [_, _, _] => return EvalError(~"record: expected a name, a vector of fields and a map"),
+        _ => return EvalError(format!("record: wrong number of args ({:u})", list.len()))
+    };
+    let mut fields = ElementMap::new();
+    for field in declared.iter() {
+        match *field {
+            Keyword(_) => {
+                fields.insert(field.clone(), given.find(field).map(|v| v.clone()).unwrap_or(nil));
+            },
+            _ => return EvalError(~"record: fields must be keywords")
+        }
+    }
+    for &(ref k, ref v) in given.iter() {
+        fields.insert(k.clone(), v.clone());
+    }
+    Record(name.clone(), fields)
+}
+
+#[allow(dead_code)]
+pub fn is_record(list: &[Element]) -> Element
+{
+    match list {
+        [Record(..)] => Boolean(true),
+        [_] => Boolean(false),
+        _ => EvalError(format!("record?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn record_type(list: &[Element]) -> Element
+{
+    // the name of a record's type, or nil for anything else
+    match list {
+        [Record(ref name, _)] => Symbol(name.clone()),
+        [_] => nil,
+        _ => EvalError(format!("record-type: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[test]
+fn test_record() {
+    let fields = ::types::vec_of(~[Keyword(~"x"), Keyword(~"y")]);
+    let given = ElementMap::from_pairs(~[(Keyword(~"z"), ::types::Number(3)),
+                                         (Keyword(~"x"), ::types::Number(1))]);
+    let r = record([Symbol(~"Point"), fields.clone(), Map(given)]);
+    assert!(r.to_str() == ~"#Point{:x 1 :y nil :z 3}");
+    assert!(record([Symbol(~"Point"), fields.clone(), nil]).to_str() == ~"#Point{:x nil :y nil}");
+    assert!(record([Symbol(~"Point"), fields, ::types::Number(1)])
+            == EvalError(~"record: expected a name, a vector of fields and a map"));
+    assert!(is_record([r.clone()]) == Boolean(true));
+    assert!(is_record([Map(ElementMap::new())]) == Boolean(false));
+    assert!(record_type([r]) == Symbol(~"Point"));
+    assert!(record_type([nil]) == nil);
+}
+
+#[test]
+fn test_defrecord() {
+    let mut bindings = ::eval::Bindings::new();
+    bindings.eval("(defrecord Point [x y])");
+    assert!(bindings.eval("(->Point 1 2)").map(|p| p.to_str()) == Ok(~"#user.Point{:x 1 :y 2}"));
+    assert!(bindings.eval("(map->Point {:y 2 :x 1})") == bindings.eval("(->Point 1 2)"));
+    assert!(bindings.eval("(:y (->Point 1 2))") == Ok(::types::Number(2)));
+    assert!(bindings.eval("(:z (->Point 1 2) :none)") == Ok(Keyword(~"none")));
+    assert!(bindings.eval("(get (->Point 1 2) :x)") == Ok(::types::Number(1)));
+    assert!(bindings.eval("[(Point? (->Point 1 2)) (Point? {:x 1 :y 2}) (record? (->Point 1 2))]")
+            == Ok(::types::vec_of(~[Boolean(true), Boolean(false), Boolean(true)])));
+    // records aren't maps, and compare by type
+    assert!(bindings.eval("(= (->Point 1 2) {:x 1 :y 2})") == Ok(Boolean(false)));
+    bindings.eval("(defrecord Size [x y])");
+    assert!(bindings.eval("(= (->Point 1 2) (->Size 1 2))") == Ok(Boolean(false)));
+    assert!(bindings.eval("(= (->Point 1 2) (->Point 1 2))") == Ok(Boolean(true)));
+    // assoc keeps the type
+    assert!(bindings.eval("(assoc (->Point 1 2) :y 5 :z 6)").map(|p| p.to_str())
+            == Ok(~"#user.Point{:x 1 :y 5 :z 6}"));
+    assert!(bindings.eval("(count (->Point 1 2))") == Ok(::types::Number(2)));
+    // printing and reading give back the same record
+    assert!(bindings.eval("(= (read-string (str (->Point \"a\" [1 :b]))) (->Point \"a\" [1 :b]))")
+            == Ok(Boolean(true)));
+    assert!(bindings.eval("#Point{:x 1 :y 2}") == bindings.eval("(->Point 1 2)"));
+    // literals get the declared fields, like map->Point
+    assert!(bindings.eval("(= #user.Point{:x 1} (map->Point {:x 1}))") == Ok(Boolean(true)));
+    assert!(bindings.eval("(= (read-string \"[#Point{:y 2}]\") [(->Point nil 2)])")
+            == Ok(Boolean(true)));
+    assert!(bindings.eval("#Nope{:x 1}") == Err(EvalError(~"#Nope: no such record type")));
+    // the type is qualified with the namespace it was defined in
+    assert!(bindings.eval("(record-type (->Point 1 2))") == Ok(Symbol(~"user.Point")));
+    bindings.eval("(ns geo)");
+    bindings.eval("(defrecord Point [lat lng])");
+    assert!(bindings.eval("(= (->Point 1 2) (user/->Point 1 2))") == Ok(Boolean(false)));
+    assert!(bindings.eval("(:lat #Point{:lat 1})") == Ok(::types::Number(1)));
+    assert!(bindings.eval("(:x #user.Point{:x 1})") == Ok(::types::Number(1)));
+    bindings.eval("(ns user)");
+    assert!(bindings.eval("(->Point 1)").is_err());
+    assert!(bindings.eval("(defrecord Bad [x x])")
+            == Err(EvalError(~"defrecord: fields must be distinct symbols")));
+}
+
+// all of the above, on the vm
+compiled_suite!(test_record, test_defrecord)
@@ -0,0 +1,59 @@
+/**
+ * @file special.rs
+ * @brief the names the evaluator handles itself instead of calling
+ *
+ * The tree-walker, analyze, check_recur and the compiler all look a
+ * head up here once and match on what comes back, so they can't
+ * disagree about which names are special.
+ */
+
+/// a special form
+#[deriving(Clone, Eq)]
+pub enum Special {
+    If, Do, Let, Loop, Recur, Fn, Quote,
+    Def, Defn, DefnPrivate, Defmacro, Defmulti, Defmethod,
+    Defprotocol, ExtendType, ExtendProtocol, Defrecord, Ns,
+    And, Or, When, WhenNot, Cond, Case, IfLet, WhenLet,
+    Binding, LazySeq, SyntaxQuote, Unquote, UnquoteSplicing,
+    Throw, Try, Stacktrace, Catch, Finally
+}
+
+static SPECIAL_FORMS: &'static [(&'static str, Special)] = &[
+    ("if", If), ("do", Do), ("let", Let), ("loop", Loop), ("recur", Recur), ("fn", Fn),
+    ("quote", Quote), ("def", Def), ("defn", Defn), ("defn-", DefnPrivate),
+    ("defmacro", Defmacro), ("defmulti", Defmulti), ("defmethod", Defmethod),
+    ("defprotocol", Defprotocol), ("extend-type", ExtendType),
+    ("extend-protocol", ExtendProtocol), ("defrecord", Defrecord), ("ns", Ns),
+    ("and", And), ("or", Or), ("when", When), ("when-not", WhenNot), ("cond", Cond),
+    ("case", Case), ("if-let", IfLet), ("when-let", WhenLet), ("binding", Binding),
+    ("lazy-seq", LazySeq), ("syntax-quote", SyntaxQuote), ("unquote", Unquote),
+    ("unquote-splicing", UnquoteSplicing), ("throw", Throw), ("try", Try),
+    ("stacktrace", Stacktrace), ("catch", Catch), ("finally", Finally),
+];
+
+/**
+ * @brief the special form a name stands for, if any
+ */
+pub fn special_form(name: &str) -> Option<Special>
+{
+    for &(n, form) in SPECIAL_FORMS.iter() {
+        if n == name {
+            return Some(form);
+        }
+    }
+    None
+}
+
+
+#[test]
+fn test_special_form() {
+    assert!(special_form("if") == Some(If));
+    assert!(special_form("defn-") == Some(DefnPrivate));
+    assert!(special_form("unquote-splicing") == Some(UnquoteSplicing));
+    assert!(special_form("map") == None);
+    assert!(special_form("") == None);
+    // every name resolves to its own form
+    for &(name, form) in SPECIAL_FORMS.iter() {
+        assert!(special_form(name) == Some(form));
+    }
+}
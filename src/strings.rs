@@ -0,0 +1,319 @@
+/**
+ * @file strings.rs
+ * @brief string function definitions
+ *
+ * ``str`` and ``subs`` live in rusp.core; the rest live in
+ * rusp.string. Strings are indexed by character, not by byte, so
+ * non-ASCII text can be cut up safely.
+ */
+use types::Element;
+use types::EvalError;
+use types::{String, Character, Number, Boolean, Vec, nil};
+
+use primitives::seq_items;
+
+mod types;
+
+/**
+ * @brief the text of a value, as str joins it
+ *
+ * Strings and characters are used as they are, and nil is empty.
+ * Anything else is printed.
+ */
+fn stringify(e: &Element) -> ~str
+{
+    match *e {
+        String(ref s) => s.clone(),
+        Character(c) => c.to_str(),
+        nil => ~"",
+        ref other => other.to_str()
+    }
+}
+
+/**
+ * @brief the text of a string or character argument
+ */
+fn text(e: &Element) -> Option<~str>
+{
+    match *e {
+        String(ref s) => Some(s.clone()),
+        Character(c) => Some(c.to_str()),
+        _ => None
+    }
+}
+
+/**
+ * @brief the byte offset of a character index into s
+ * @return None if the index is past the end of s
+ */
+fn byte_index(s: &str, chars: uint) -> Option<uint>
+{
+    if chars == s.char_len() {
+        return Some(s.len());
+    }
+    s.char_indices().nth(chars).map(|(i, _)| i)
+}
+
+#[allow(dead_code)]
+pub fn strfn(list: &[Element]) -> Element
+{
+    let mut out = ~"";
+    for e in list.iter() {
+        out.push_str(stringify(e));
+    }
+    String(out)
+}
+
+#[allow(dead_code)]
+pub fn subs(list: &[Element]) -> Element
+{
+    let (s, start, end) = match list {
+        [String(ref s), Number(start)] => (s, start, s.char_len() as i64),
+        [String(ref s), Number(start), Number(end)] => (s, start, end),
+        [_, _] | [_, _, _] => return EvalError(~"subs: expected a string and indexes"),
+        _ => return EvalError(format!("subs: wrong number of args ({:u})", list.len()))
+    };
+    if start < 0 {
+        return EvalError(format!("subs: index out of range ({:d})", start));
+    }
+    if end < start {
+        return EvalError(format!("subs: index out of range ({:d})", end));
+    }
+    match (byte_index(s.as_slice(), start as uint), byte_index(s.as_slice(), end as uint)) {
+        (Some(from), Some(to)) => String(s.slice(from, to).to_owned()),
+        (None, _) => EvalError(format!("subs: index out of range ({:d})", start)),
+        (_, None) => EvalError(format!("subs: index out of range ({:d})", end))
+    }
+}
+
+#[allow(dead_code)]
+pub fn upper_case(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s)] => String(s.chars().map(|c| c.to_uppercase()).collect()),
+        [_] => EvalError(~"upper-case: expected a string"),
+        _ => EvalError(format!("upper-case: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn lower_case(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s)] => String(s.chars().map(|c| c.to_lowercase()).collect()),
+        [_] => EvalError(~"lower-case: expected a string"),
+        _ => EvalError(format!("lower-case: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn split(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s), ref sep] => {
+            let sep = match text(sep) {
+                Some(sep) => sep,
+                None => return EvalError(~"split: separator must be a string")
+            };
+            // an empty separator splits out every character
+            let mut parts: ~[~str] = if sep.is_empty() {
+                s.chars().map(|c| c.to_str()).collect()
+            } else {
+                s.split_str(sep.as_slice()).map(|p| p.to_owned()).collect()
+            };
+            // like Clojure, trailing empty strings are dropped
+            while parts.len() > 0 && parts[parts.len() - 1].is_empty() {
+                parts.pop();
+            }
+            Vec(parts.move_iter().map(|p| String(p)).collect())
+        },
+        [_, _] => EvalError(~"split: expected a string"),
+        _ => EvalError(format!("split: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn join(list: &[Element]) -> Element
+{
+    let (sep, coll) = match list {
+        [ref coll] => (~"", coll),
+        [ref sep, ref coll] => (stringify(sep), coll),
+        _ => return EvalError(format!("join: wrong number of args ({:u})", list.len()))
+    };
+    let items = try_items!(seq_items("join", coll));
+    let parts: ~[~str] = items.iter().map(|e| stringify(e)).collect();
+    String(parts.connect(sep.as_slice()))
+}
+
+#[allow(dead_code)]
+pub fn trim(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s)] => String(s.trim().to_owned()),
+        [_] => EvalError(~"trim: expected a string"),
+        _ => EvalError(format!("trim: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn starts_with(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s), String(ref prefix)] => Boolean(s.starts_with(prefix.as_slice())),
+        [_, _] => EvalError(~"starts-with?: expected two strings"),
+        _ => EvalError(format!("starts-with?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn ends_with(list: &[Element]) -> Element
+{
+    match list {
+        [String(ref s), String(ref suffix)] => Boolean(s.ends_with(suffix.as_slice())),
+        [_, _] => EvalError(~"ends-with?: expected two strings"),
+        _ => EvalError(format!("ends-with?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn index_of(list: &[Element]) -> Element
+{
+    // the index and the search start are counted in characters
+    let (s, value, from) = match list {
+        [String(ref s), ref value] => (s, value, 0),
+        [String(ref s), ref value, Number(from)] => (s, value, if from < 0 { 0 } else { from }),
+        [_, _] | [_, _, _] => return EvalError(~"index-of: expected a string"),
+        _ => return EvalError(format!("index-of: wrong number of args ({:u})", list.len()))
+    };
+    let value = match text(value) {
+        Some(v) => v,
+        None => return EvalError(~"index-of: can only search for a string or character")
+    };
+    let start = match byte_index(s.as_slice(), from as uint) {
+        Some(i) => i,
+        None => return nil
+    };
+    match s.slice_from(start).find_str(value.as_slice()) {
+        Some(i) => Number((from as uint + s.slice(start, start + i).char_len()) as i64),
+        None => nil
+    }
+}
+
+#[allow(dead_code)]
+pub fn replace(list: &[Element]) -> Element
+{
+    // every match is replaced
+    match list {
+        [String(ref s), ref from, ref to] => match (text(from), text(to)) {
+            (Some(ref from), _) if from.is_empty() => {
+                EvalError(~"replace: can't replace an empty string")
+            },
+            (Some(from), Some(to)) => String(s.replace(from.as_slice(), to.as_slice())),
+            _ => EvalError(~"replace: match and replacement must be strings or characters")
+        },
+        [_, _, _] => EvalError(~"replace: expected a string"),
+        _ => EvalError(format!("replace: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn split_lines(list: &[Element]) -> Element
+{
+    // both \n and \r\n end a line
+    match list {
+        [String(ref s)] => Vec(s.lines_any().map(|l| String(l.to_owned())).collect()),
+        [_] => EvalError(~"split-lines: expected a string"),
+        _ => EvalError(format!("split-lines: wrong number of args ({:u})", list.len()))
+    }
+}
+
+#[allow(dead_code)]
+pub fn is_blank(list: &[Element]) -> Element
+{
+    match list {
+        [nil] => Boolean(true),
+        [String(ref s)] => Boolean(s.chars().all(|c| c.is_whitespace())),
+        [_] => EvalError(~"blank?: expected a string"),
+        _ => EvalError(format!("blank?: wrong number of args ({:u})", list.len()))
+    }
+}
+
+
+#[test]
+fn test_str() {
+    assert!(strfn([String(~"a"), Number(1), nil, Character('é'), ::types::Keyword(~"k")])
+            == String(~"a1é:k"));
+    assert!(strfn([::types::vec_of(~[String(~"x")])]) == String(~"[\"x\"]"));
+    assert!(strfn([]) == String(~""));
+    assert!(::eval::eval("(str \"n=\" (+ 1 2))") == String(~"n=3"));
+}
+
+#[test]
+fn test_subs() {
+    assert!(subs([String(~"héllo"), Number(1), Number(3)]) == String(~"él"));
+    assert!(subs([String(~"日本語"), Number(1)]) == String(~"本語"));
+    assert!(subs([String(~"日本語"), Number(3)]) == String(~""));
+    assert!(subs([String(~"日本語"), Number(4)]) == EvalError(~"subs: index out of range (4)"));
+    assert!(subs([String(~"abc"), Number(2), Number(1)])
+            == EvalError(~"subs: index out of range (1)"));
+}
+
+#[test]
+fn test_case() {
+    assert!(upper_case([String(~"héllo wörld")]) == String(~"HÉLLO WÖRLD"));
+    assert!(lower_case([String(~"ÀÉÎ abc")]) == String(~"àéî abc"));
+    assert!(upper_case([Number(1)]) == EvalError(~"upper-case: expected a string"));
+}
+
+#[test]
+fn test_split_join() {
+    assert!(split([String(~"a,b,,c,,"), String(~",")])
+            == ::types::vec_of(~[String(~"a"), String(~"b"), String(~""), String(~"c")]));
+    assert!(split([String(~"α→β"), String(~"→")])
+            == ::types::vec_of(~[String(~"α"), String(~"β")]));
+    assert!(split([String(~"αβ"), String(~"")])
+            == ::types::vec_of(~[String(~"α"), String(~"β")]));
+    assert!(join([::types::vec_of(~[String(~"a"), Number(1), nil])]) == String(~"a1"));
+    assert!(join([String(~", "), ::types::list_of(~[String(~"x"), String(~"ü")])])
+            == String(~"x, ü"));
+    assert!(join([String(~"-"), nil]) == String(~""));
+}
+
+#[test]
+fn test_trim_blank() {
+    assert!(trim([String(~" \t héllo \n")]) == String(~"héllo"));
+    assert!(is_blank([nil]) == Boolean(true));
+    assert!(is_blank([String(~" \t\n")]) == Boolean(true));
+    assert!(is_blank([String(~" x ")]) == Boolean(false));
+}
+
+#[test]
+fn test_starts_ends_with() {
+    assert!(starts_with([String(~"über"), String(~"ü")]) == Boolean(true));
+    assert!(starts_with([String(~"über"), String(~"u")]) == Boolean(false));
+    assert!(ends_with([String(~"café"), String(~"fé")]) == Boolean(true));
+}
+
+#[test]
+fn test_index_of() {
+    assert!(index_of([String(~"日本語本"), String(~"本")]) == Number(1));
+    assert!(index_of([String(~"日本語本"), Character('本'), Number(2)]) == Number(3));
+    assert!(index_of([String(~"日本語"), String(~"x")]) == nil);
+    assert!(index_of([String(~"日本語"), String(~"本"), Number(9)]) == nil);
+}
+
+#[test]
+fn test_replace() {
+    assert!(replace([String(~"a-b-c"), Character('-'), String(~"→")]) == String(~"a→b→c"));
+    assert!(replace([String(~"ERROR: x; ERROR: y"), String(~"ERROR"), String(~"WARN")])
+            == String(~"WARN: x; WARN: y"));
+    assert!(replace([String(~"abc"), String(~""), String(~"x")])
+            == EvalError(~"replace: can't replace an empty string"));
+}
+
+#[test]
+fn test_split_lines() {
+    assert!(split_lines([String(~"ä\r\nb\nc")])
+            == ::types::vec_of(~[String(~"ä"), String(~"b"), String(~"c")]));
+    assert!(split_lines([String(~"")]) == ::types::vec_of(~[]));
+}
@@ -0,0 +1,77 @@
+/**
+ * @file symbol.rs
+ * @brief interned symbols
+ *
+ * Each distinct name is given a small number the first time it is
+ * seen, so scopes can compare and hash names as numbers instead of
+ * strings. The table is kept per task and never shrinks.
+ */
+use std::hashmap::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::local_data;
+
+/// an interned name
+#[deriving(Clone, Eq, IterBytes)]
+pub struct Sym(uint);
+
+struct Interner {
+    ids: HashMap<~str, Sym>,
+    names: ~[~str]
+}
+
+local_data_key!(INTERNER: Rc<RefCell<Interner>>)
+
+/**
+ * @brief this task's table of names
+ */
+fn interner() -> Rc<RefCell<Interner>>
+{
+    match local_data::get(INTERNER, |table| table.map(|t| t.clone())) {
+        Some(table) => table,
+        None => {
+            let table = Rc::new(RefCell::new(Interner { ids: HashMap::new(), names: ~[] }));
+            local_data::set(INTERNER, table.clone());
+            table
+        }
+    }
+}
+
+/**
+ * @brief the id of a name, giving it one if it doesn't have one yet
+ */
+pub fn intern(name: &str) -> Sym
+{
+    interner().borrow().with_mut(|table| {
+        match table.ids.find_equiv(&name) {
+            Some(&sym) => sym,
+            None => {
+                let sym = Sym(table.names.len());
+                table.names.push(name.to_owned());
+                table.ids.insert(name.to_owned(), sym);
+                sym
+            }
+        }
+    })
+}
+
+/**
+ * @brief the name an id was given for
+ */
+pub fn name_of(sym: Sym) -> ~str
+{
+    let Sym(i) = sym;
+    interner().borrow().with(|table| table.names[i].clone())
+}
+
+
+#[test]
+fn test_intern() {
+    let a = intern("rusp.core/map");
+    let b = intern("map");
+    assert!(a != b);
+    assert!(intern("rusp.core/map") == a);
+    assert!(intern((~"ma" + "p").as_slice()) == b);
+    assert!(name_of(a) == ~"rusp.core/map");
+    assert!(name_of(intern("日本語")) == ~"日本語");
+}
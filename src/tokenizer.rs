@@ -1,28 +1,34 @@
 use types::ParseError;
 use types::Symbol;
-use types::{Element, Number, String, Boolean, List, Vec};
+use types::{Element, Number, String, Boolean, List, Vec, Map, Record, Keyword};
+use types::ElementMap;
+use types::{list_of, vec_of};
 use types::nil;
 
 mod types;
 
+/// whitespace between tokens, where commas count as whitespace
+fn is_separator(c: char) -> bool
+{
+    c.is_whitespace() || c == ','
+}
+
 fn tokenize_firstpass(s: &str) -> Option<~[~str]>
 {
     let mut v: ~[~str] = ~[];
-    let mut index = 0;
     let mut tok_start = 0;
     let mut inside_string = false;
     let mut stringbuilder = ~"";
-    let ss = s.trim().replace(",", " ");
-    while index < ss.len() {
-        let c: char = ss.char_at(index);
-        if "()[] ".contains(c.to_str()) && !inside_string {
+    // indexes are byte offsets, so slicing stays on char boundaries
+    for (index, c) in s.char_indices() {
+        if !inside_string && ("()[]{}".contains_char(c) || is_separator(c)) {
             if index != tok_start {
-                v.push(ss.slice(tok_start, index).to_owned());
+                v.push(s.slice(tok_start, index).to_owned());
             }
-            if c != ' ' {
+            if !is_separator(c) {
                 v.push(c.to_str());
             }
-            tok_start = index + 1;
+            tok_start = index + c.len_utf8_bytes();
         } else if c == '"' {
             stringbuilder.push_char('"');
             if inside_string {
@@ -34,55 +40,123 @@ fn tokenize_firstpass(s: &str) -> Option<~[~str]>
         } else if inside_string {
             stringbuilder.push_char(c);
         }
-        index += 1;
     }
     if inside_string {
         return None;
     }
-    if tok_start != index {
-        v.push(ss.slice(tok_start, index).to_owned());
+    if tok_start != s.len() {
+        v.push(s.slice_from(tok_start).to_owned());
     }
     return Some(v);
 }
 
+/// reader macros that wrap the form after them, longest prefix first
+static READER_MACROS: &'static [(&'static str, &'static str)] = &[
+    ("~@", "unquote-splicing"),
+    ("~", "unquote"),
+    ("`", "syntax-quote"),
+    ("'", "quote"),
+    ("@", "deref"),
+];
+
+fn reader_macro(token: &str) -> Option<(&'static str, &'static str)>
+{
+    for &(prefix, name) in READER_MACROS.iter() {
+        if token.starts_with(prefix) {
+            return Some((prefix, name));
+        }
+    }
+    None
+}
+
+fn wrap_reader_macros(pending: &mut ~[&'static str], elem: Element) -> Element
+{
+    // the innermost reader macro is the last one read
+    let mut wrapped = elem;
+    while pending.len() > 0 {
+        let name = pending.pop();
+        wrapped = list_of(~[Symbol(name.to_owned()), wrapped]);
+    }
+    wrapped
+}
+
 fn do_tokenize_structure(tokens: &[~str], start_index: uint, num_parens: uint) -> (uint, Element)
 {
     let mut v: ~[Element] = ~[];
+    let mut pending: ~[&'static str] = ~[];
+    // the type of a record literal, like #Point{:x 1}, whose map is next
+    let mut tag: Option<~str> = None;
     let mut index = start_index;
     while index < tokens.len() {
         let token = tokens[index].clone();
-        if token == ~"(" || token == ~"[" {
+        if token == ~"(" || token == ~"[" || token == ~"{" {
             // indent
             let close_paren = match token {
                 ~"(" => ~")",
                 ~"[" => ~"]",
+                ~"{" => ~"}",
                 _ => return (tokens.len(), ParseError(~"unknown parenthesis open type"))
             };
             let (next_index, elem) = do_tokenize_structure(tokens, index+1, num_parens+1);
-            v.push(elem);
+            match elem {
+                ParseError(_) => return (tokens.len(), elem),
+                _ => ()
+            }
+            let elem = match (tag, elem) {
+                (Some(name), Map(fields)) => Record(name, fields),
+                (_, elem) => elem
+            };
+            tag = None;
+            v.push(wrap_reader_macros(&mut pending, elem));
             index = next_index;
             if index >= tokens.len() {
                 break;
             } else if tokens[index] != close_paren {
                 return (tokens.len(), ParseError(~"unmatched parentheses"));
             }
-        } else if token == ~")" || token == ~"]" {
+        } else if token == ~")" || token == ~"]" || token == ~"}" {
             // outdent
             if num_parens <= 0 {
                 return (tokens.len(), ParseError(~"unbalanced parentheses"));
             }
-            let elem_type = match token {
-                ~"]" => Vec,
-                ~")" => List,
+            if pending.len() > 0 {
+                return (tokens.len(), ParseError(~"reader macro not followed by a form"));
+            }
+            let elem = match token {
+                ~"]" => vec_of(v),
+                ~")" => list_of(v),
+                ~"}" => match map_from_forms(v) {
+                    Some(m) => m,
+                    None => return (tokens.len(),
+                                    ParseError(~"map literal must have an even number of forms"))
+                },
                 _ => fail!("unknown close brace")
             };
-            return (index, elem_type(v));
+            return (index, elem);
         } else {
-            // another element
-            if token.starts_with("\"") && token.ends_with("\"") {
-                v.push(String(token.slice(1, token.len()-1).to_owned()));
-            } else {
-                v.push(Symbol(token.to_owned()));
+            // another element, possibly behind some reader macros
+            let mut rest = token.as_slice();
+            loop {
+                match reader_macro(rest) {
+                    Some((prefix, name)) => {
+                        pending.push(name);
+                        rest = rest.slice_from(prefix.len());
+                    },
+                    None => break
+                }
+            }
+            if rest.len() > 1 && rest.starts_with("#") {
+                if index + 1 >= tokens.len() || tokens[index + 1] != ~"{" {
+                    return (tokens.len(), ParseError(~"record tag must be followed by a map"));
+                }
+                tag = Some(rest.slice_from(1).to_owned());
+            } else if rest.len() > 0 {
+                let elem = if rest.starts_with("\"") && rest.ends_with("\"") {
+                    String(rest.slice(1, rest.len()-1).to_owned())
+                } else {
+                    Symbol(rest.to_owned())
+                };
+                v.push(wrap_reader_macros(&mut pending, elem));
             }
         }
         index += 1;
@@ -90,11 +164,24 @@ fn do_tokenize_structure(tokens: &[~str], start_index: uint, num_parens: uint) -
     if num_parens > 0 {
         return (tokens.len(), ParseError(~"unbalanced parentheses"));
     }
+    if pending.len() > 0 {
+        return (tokens.len(), ParseError(~"reader macro not followed by a form"));
+    }
     match v.len() {
         0 => (index, nil),
         1 => (index, v[0]),
-        _ => (index, List(v))
+        _ => (index, list_of(v))
+    }
+}
+
+
+fn map_from_forms(forms: ~[Element]) -> Option<Element>
+{
+    if forms.len() % 2 != 0 {
+        return None;
     }
+    let pairs = forms.chunks(2).map(|kv| (kv[0].clone(), kv[1].clone())).collect();
+    Some(Map(ElementMap::from_pairs(pairs)))
 }
 
 
@@ -110,7 +197,7 @@ fn tokenize_infer_types(token: Element) -> Element
     match token {
         List(l) => {
             if l.len() > 0 {
-                List(l.map(|x| tokenize_infer_types(x.clone())))
+                List(l.iter().map(|x| tokenize_infer_types(x.clone())).collect())
             } else {
                 List(l)
             }
@@ -118,6 +205,10 @@ fn tokenize_infer_types(token: Element) -> Element
         Symbol(s) => {
             if s == ~"true" || s == ~"false" {
                 Boolean(s == ~"true")
+            } else if s == ~"nil" {
+                nil
+            } else if s.len() > 1 && s.starts_with(":") {
+                Keyword(s.slice_from(1).to_owned())
             } else {
                 match from_str::<i64>(s) {
                     Some(i) => Number(i),
@@ -126,16 +217,22 @@ fn tokenize_infer_types(token: Element) -> Element
             }
         },
         Vec(s) => {
-            let mut v: ~[Element] = ~[];
-            for elem in s.iter() {
-                v.push(tokenize_infer_types(elem.clone()));
-            }
-            Vec(v)
-        }
+            Vec(s.iter().map(|elem| tokenize_infer_types(elem.clone())).collect())
+        },
+        Map(m) => Map(infer_map_types(&m)),
+        Record(name, fields) => Record(name, infer_map_types(&fields)),
         _ => token
     }
 }
 
+fn infer_map_types(m: &ElementMap) -> ElementMap
+{
+    let pairs = m.iter().map(|&(ref k, ref v)| {
+        (tokenize_infer_types(k.clone()), tokenize_infer_types(v.clone()))
+    }).collect();
+    ElementMap::from_pairs(pairs)
+}
+
 #[allow(dead_code)]
 pub fn tokenize(s: &str) -> Element
 {
@@ -177,9 +274,20 @@ fn test_tokenizer_firstpass() {
     assert!(tokenize_firstpass("[]") == Some(~[~"[", ~"]"]));
     assert!(tokenize_firstpass("[1 2]") == Some(~[~"[", ~"1", ~"2", ~"]"]));
     assert!(tokenize_firstpass("[1, 2]") == Some(~[~"[", ~"1", ~"2", ~"]"]));
+    // maps
+    assert!(tokenize_firstpass("{:a 1}") == Some(~[~"{", ~":a", ~"1", ~"}"]));
     // strings
     assert!(tokenize_firstpass("\"\"") == Some(~[~"\"\""]));
     assert!(tokenize_firstpass("\"hello\"") == Some(~[~"\"hello\""]));
+    // commas inside strings are kept
+    assert!(tokenize_firstpass("(str \"a, b\" 1,2)") == Some(~[~"(", ~"str", ~"\"a, b\"",
+                                                              ~"1", ~"2", ~")"]));
+    // any whitespace separates tokens
+    assert!(tokenize_firstpass("(+ 1\n\t2)") == Some(~[~"(", ~"+", ~"1", ~"2", ~")"]));
+    // non-ASCII text
+    assert!(tokenize_firstpass("(subs \"日本語\" 1)") == Some(~[~"(", ~"subs", ~"\"日本語\"",
+                                                               ~"1", ~")"]));
+    assert!(tokenize_firstpass("(λ é)") == Some(~[~"(", ~"λ", ~"é", ~")"]));
 }
 
 #[test]
@@ -187,17 +295,17 @@ fn test_tokenizer_structure() {
     let test1 = tokenize_structure([]);
     assert!(test1 == nil);
     let test2 = tokenize_structure([~"(", ~"+", ~")"]);
-    assert!(test2 == List(~[Symbol(~"+")]));
+    assert!(test2 == list_of(~[Symbol(~"+")]));
     let test3 = tokenize_structure([~"(", ~"+", ~"1", ~"2", ~")"]);
-    assert!(test3 == List(~[Symbol(~"+"), Symbol(~"1"), Symbol(~"2")]));
+    assert!(test3 == list_of(~[Symbol(~"+"), Symbol(~"1"), Symbol(~"2")]));
     let test4 = tokenize_structure([~"1"]);
     assert!(test4 == Symbol(~"1"));
     let test5 = tokenize_structure([~"\"hello\""]);
     assert!(test5 == String(~"hello"));
     let test6 = tokenize_structure([~"[", ~"]"]);
-    assert!(test6 == Vec(~[]));
+    assert!(test6 == vec_of(~[]));
     let test7 = tokenize_structure([~"[", ~"1", ~"]"]);
-    assert!(test7 == Vec(~[Symbol(~"1")]));
+    assert!(test7 == vec_of(~[Symbol(~"1")]));
 }
 
 
@@ -239,22 +347,82 @@ fn test_tokenizer_structure_errors() {
 fn test_tokenizer_inference() {
     assert!(tokenize_infer_types(nil) == nil);
     assert!(tokenize_infer_types(Symbol(~"1")) == Number(1));
-    assert!(tokenize_infer_types(List(~[Symbol(~"+"), Symbol(~"1")]))
-            == List(~[Symbol(~"+"), Number(1)]));
+    assert!(tokenize_infer_types(list_of(~[Symbol(~"+"), Symbol(~"1")]))
+            == list_of(~[Symbol(~"+"), Number(1)]));
     assert!(tokenize_infer_types(String(~"hello"))
             == String(~"hello"));
-    assert!(tokenize_infer_types(Vec(~[Symbol(~"1"), Symbol(~"2")]))
-            == Vec(~[Number(1), Number(2)]));
+    assert!(tokenize_infer_types(vec_of(~[Symbol(~"1"), Symbol(~"2")]))
+            == vec_of(~[Number(1), Number(2)]));
 }
 
 #[test]
 fn test_tokenizer() {
     assert!(tokenize("") == nil);
-    assert!(tokenize("(+ 1 1)") == List(~[Symbol(~"+"), Number(1), Number(1)]));
-    assert!(tokenize("(- 5 1)") == List(~[Symbol(~"-"), Number(5), Number(1)]));
+    assert!(tokenize("(+ 1 1)") == list_of(~[Symbol(~"+"), Number(1), Number(1)]));
+    assert!(tokenize("(- 5 1)") == list_of(~[Symbol(~"-"), Number(5), Number(1)]));
     assert!(tokenize("1") == Number(1));
     assert!(tokenize("\"hello\"") == String(~"hello"));
-    assert!(tokenize("[1 2 3]") == Vec(~[Number(1), Number(2), Number(3)]));
+    assert!(tokenize("[1 2 3]") == vec_of(~[Number(1), Number(2), Number(3)]));
+    assert!(tokenize(":a") == Keyword(~"a"));
+    assert!(tokenize("nil") == nil);
+    assert!(tokenize("{}") == Map(ElementMap::new()));
+    assert!(tokenize("{:a 1, :b [2]}")
+            == Map(ElementMap::from_pairs(~[(Keyword(~"a"), Number(1)),
+                                            (Keyword(~"b"), vec_of(~[Number(2)]))])));
+    match tokenize("{:a}") {
+        ParseError(_) => (),
+        e => fail!("{:?} != ParseError", e)
+    }
+}
+
+#[test]
+fn test_tokenizer_records() {
+    assert!(tokenize("#Point{:x 1 :y \"a b\"}")
+            == Record(~"Point", ElementMap::from_pairs(~[(Keyword(~"x"), Number(1)),
+                                                         (Keyword(~"y"), String(~"a b"))])));
+    assert!(tokenize("[#P{} '#P{:a x}]")
+            == vec_of(~[Record(~"P", ElementMap::new()),
+                        list_of(~[Symbol(~"quote"),
+                                  Record(~"P", ElementMap::from_pairs(~[(Keyword(~"a"),
+                                                                         Symbol(~"x"))]))])]));
+    match tokenize("#Point[1 2]") {
+        ParseError(_) => (),
+        e => fail!("{:?} != ParseError", e)
+    }
+}
+
+#[test]
+fn test_tokenizer_reader_macros() {
+    assert!(tokenize("`a") == list_of(~[Symbol(~"syntax-quote"), Symbol(~"a")]));
+    assert!(tokenize("~a") == list_of(~[Symbol(~"unquote"), Symbol(~"a")]));
+    assert!(tokenize("~@a") == list_of(~[Symbol(~"unquote-splicing"), Symbol(~"a")]));
+    assert!(tokenize("`(+ ~x 1)") == list_of(~[Symbol(~"syntax-quote"),
+                                               list_of(~[Symbol(~"+"),
+                                                         list_of(~[Symbol(~"unquote"),
+                                                                   Symbol(~"x")]),
+                                                         Number(1)])]));
+    assert!(tokenize("`[~(inc 1)]")
+            == list_of(~[Symbol(~"syntax-quote"),
+                         vec_of(~[list_of(~[Symbol(~"unquote"),
+                                            list_of(~[Symbol(~"inc"), Number(1)])])])]));
+    assert!(tokenize("`~a") == list_of(~[Symbol(~"syntax-quote"),
+                                      list_of(~[Symbol(~"unquote"), Symbol(~"a")])]));
+    assert!(tokenize("'a") == list_of(~[Symbol(~"quote"), Symbol(~"a")]));
+    assert!(tokenize("@a") == list_of(~[Symbol(~"deref"), Symbol(~"a")]));
+    assert!(tokenize("~@a") == list_of(~[Symbol(~"unquote-splicing"), Symbol(~"a")]));
+    assert!(tokenize("@(f)") == list_of(~[Symbol(~"deref"), list_of(~[Symbol(~"f")])]));
+    assert!(tokenize("'(1 a)")
+            == list_of(~[Symbol(~"quote"), list_of(~[Number(1), Symbol(~"a")])]));
+    assert!(tokenize("''a") == list_of(~[Symbol(~"quote"),
+                                      list_of(~[Symbol(~"quote"), Symbol(~"a")])]));
+    match tokenize("(+ 1 ~)") {
+        ParseError(_) => (),
+        e => fail!("{:?} != ParseError", e)
+    }
+    match tokenize("`") {
+        ParseError(_) => (),
+        e => fail!("{:?} != ParseError", e)
+    }
 }
 
 #[test]
@@ -1,31 +1,425 @@
-use functypes::{RustFunc, BoundFn};
+use std::vec;
+use std::borrow;
+use std::util;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::any::{Any, AnyRefExt};
+
+use functypes::{RustFunc, NativeFunc, BoundFn};
+use persistent::{ConsList, PVec};
+
+use symbol::Sym;
 
 #[deriving(Clone, Eq)]
 pub enum Element {
     Symbol(~str),
+    Keyword(~str),
     Number(i64),
     String(~str),
     Character(char),
     Boolean(bool),
     ParseError(~str),
     EvalError(~str),
-    List(~[Element]),
-    Vec(~[Element]),
+    ExInfo(~str, ~Element),
+    List(ConsList),
+    Vec(PVec),
+    Map(ElementMap),
+    // an instance of a record type: the type's name, and its fields in
+    // the order they were declared, then any others assoc'd on
+    Record(~str, ElementMap),
     Function(~BoundFn),
     FuncPrimitive(~RustFunc),
+    FuncNative(~NativeFunc),
+    Lazy(LazySeq),
+    Atom(AtomRef),
+    // a fn that picks which of its methods to run by the value its
+    // dispatch fn returns
+    Multi(MultiRef),
+    // a value of a type an embedder defined in Rust
+    Native(NativeValue),
+    // a local, resolved before evaluation to how many frames out it
+    // is and its slot there; the name is kept for printing
+    Local(~str, Sym, uint, uint),
+    // any other symbol, interned before evaluation
+    Interned(~str, Sym),
     nil
 }
 
+/**
+ * @brief a List of items
+ */
+pub fn list_of(items: ~[Element]) -> Element
+{
+    List(ConsList::from_vec(items))
+}
+
+/**
+ * @brief a Vec of items
+ */
+pub fn vec_of(items: ~[Element]) -> Element
+{
+    Vec(PVec::from_vec(items))
+}
+
+/**
+ * @brief a sequence that is realized on demand
+ *
+ * Clones share their cells, so each part of the sequence is only
+ * realized once. Calling the thunk needs an interpreter, so the
+ * realizing itself is done by Bindings::realize.
+ */
+#[deriving(Clone)]
+pub struct LazySeq {
+    priv cell: Rc<RefCell<LazyState>>
+}
+
+/// the thunk making the seq, or the realized first item and the rest
+enum LazyState {
+    Unrealized(Element),
+    Realized(Option<(Element, Element)>)
+}
+
+impl LazySeq {
+    /**
+     * @brief a seq that calls thunk with no args when it's first needed
+     */
+    pub fn new(thunk: Element) -> LazySeq {
+        LazySeq { cell: Rc::new(RefCell::new(Unrealized(thunk))) }
+    }
+    /**
+     * @brief an already realized seq of first followed by rest
+     */
+    pub fn cons(first: Element, rest: Element) -> LazySeq {
+        LazySeq { cell: Rc::new(RefCell::new(Realized(Some((first, rest))))) }
+    }
+    /**
+     * @brief the realized first item and rest, or None if unrealized
+     *
+     * Some(None) is a realized empty seq.
+     */
+    pub fn peek(&self) -> Option<Option<(Element, Element)>> {
+        self.cell.borrow().with(|state| match *state {
+            Realized(ref cell) => Some(cell.clone()),
+            Unrealized(_) => None
+        })
+    }
+    pub fn thunk(&self) -> Option<Element> {
+        self.cell.borrow().with(|state| match *state {
+            Unrealized(ref thunk) => Some(thunk.clone()),
+            Realized(_) => None
+        })
+    }
+    pub fn set_realized(&self, cell: Option<(Element, Element)>) {
+        self.cell.borrow().with_mut(|state| *state = Realized(cell.clone()));
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        // like ConsList, free a long realized chain in a loop: step on
+        // to each next cell, until one is still held somewhere else
+        loop {
+            let next = match self.cell.borrow().try_borrow() {
+                Some(state) => match *state.get() {
+                    Realized(Some((_, Lazy(ref rest)))) => rest.cell.clone(),
+                    _ => break
+                },
+                // being realized; whoever is doing it still holds the seq
+                None => break
+            };
+            let cell_ref = util::replace(&mut self.cell, next).downgrade();
+            if cell_ref.upgrade().is_some() {
+                break;
+            }
+        }
+    }
+}
+
+impl Eq for LazySeq {
+    fn eq(&self, other: &LazySeq) -> bool {
+        // the same seq; compare contents with = instead
+        borrow::ref_eq(self.cell.borrow(), other.cell.borrow())
+    }
+}
+
+/**
+ * @brief a reference to a value that can be changed
+ *
+ * Clones refer to the same atom, and atoms are only equal to
+ * themselves. Running validators and watches needs an interpreter, so
+ * that is done by the atom builtins.
+ */
+#[deriving(Clone)]
+pub struct AtomRef {
+    priv cell: Rc<RefCell<AtomState>>
+}
+
+struct AtomState {
+    value: Element,
+    validator: Option<Element>,
+    watches: ~[(Element, Element)]
+}
+
+impl AtomRef {
+    pub fn new(value: Element) -> AtomRef {
+        AtomRef { cell: Rc::new(RefCell::new(AtomState {
+            value: value,
+            validator: None,
+            watches: ~[]
+        })) }
+    }
+    pub fn value(&self) -> Element {
+        self.cell.borrow().with(|state| state.value.clone())
+    }
+    pub fn set_value(&self, value: Element) {
+        self.cell.borrow().with_mut(|state| state.value = value.clone());
+    }
+    pub fn validator(&self) -> Option<Element> {
+        self.cell.borrow().with(|state| state.validator.clone())
+    }
+    pub fn set_validator(&self, validator: Option<Element>) {
+        self.cell.borrow().with_mut(|state| state.validator = validator.clone());
+    }
+    /**
+     * @brief the watch fns, by key, in the order they were added
+     */
+    pub fn watches(&self) -> ~[(Element, Element)] {
+        self.cell.borrow().with(|state| state.watches.clone())
+    }
+    pub fn add_watch(&self, key: Element, f: Element) {
+        self.remove_watch(&key);
+        self.cell.borrow().with_mut(|state| state.watches.push((key.clone(), f.clone())));
+    }
+    pub fn remove_watch(&self, key: &Element) {
+        self.cell.borrow().with_mut(|state| state.watches.retain(|&(ref k, _)| k != key));
+    }
+}
+
+impl Eq for AtomRef {
+    fn eq(&self, other: &AtomRef) -> bool {
+        borrow::ref_eq(self.cell.borrow(), other.cell.borrow())
+    }
+}
+
+/**
+ * @brief a multimethod
+ *
+ * Like atoms, clones share their methods, so a defmethod is seen by
+ * every reference to the multimethod. Choosing a method needs an
+ * interpreter, so that is done by Bindings::apply.
+ */
+#[deriving(Clone)]
+pub struct MultiRef {
+    priv cell: Rc<RefCell<MultiState>>
+}
+
+struct MultiState {
+    name: ~str,
+    dispatch: Element,
+    methods: ~[(Element, Element)],
+    // (x, y) when x wins over y if both match
+    prefers: ~[(Element, Element)]
+}
+
+impl MultiRef {
+    pub fn new(name: &str, dispatch: Element) -> MultiRef {
+        MultiRef { cell: Rc::new(RefCell::new(MultiState {
+            name: name.to_owned(),
+            dispatch: dispatch,
+            methods: ~[],
+            prefers: ~[]
+        })) }
+    }
+    pub fn name(&self) -> ~str {
+        self.cell.borrow().with(|state| state.name.clone())
+    }
+    pub fn dispatch(&self) -> Element {
+        self.cell.borrow().with(|state| state.dispatch.clone())
+    }
+    /**
+     * @brief the methods, by dispatch value, in the order they were added
+     */
+    pub fn methods(&self) -> ~[(Element, Element)] {
+        self.cell.borrow().with(|state| state.methods.clone())
+    }
+    pub fn add_method(&self, value: Element, f: Element) {
+        self.remove_method(&value);
+        self.cell.borrow().with_mut(|state| state.methods.push((value.clone(), f.clone())));
+    }
+    pub fn remove_method(&self, value: &Element) {
+        self.cell.borrow().with_mut(|state| state.methods.retain(|&(ref v, _)| v != value));
+    }
+    pub fn prefers(&self, x: &Element, y: &Element) -> bool {
+        self.cell.borrow().with(|state| {
+            state.prefers.iter().any(|&(ref a, ref b)| a == x && b == y)
+        })
+    }
+    pub fn prefer(&self, x: Element, y: Element) {
+        self.cell.borrow().with_mut(|state| state.prefers.push((x.clone(), y.clone())));
+    }
+}
+
+impl Eq for MultiRef {
+    fn eq(&self, other: &MultiRef) -> bool {
+        borrow::ref_eq(self.cell.borrow(), other.cell.borrow())
+    }
+}
+
+/**
+ * @brief a Rust value, with the name of its type
+ *
+ * The name is what errors call the type, and what protocols are
+ * extended to. Like atoms, clones share the value, and it is only
+ * equal to itself.
+ */
+#[deriving(Clone)]
+pub struct NativeValue {
+    priv type_name: ~str,
+    priv value: Rc<~Any>
+}
+
+impl NativeValue {
+    pub fn new<T: 'static>(type_name: &str, value: T) -> NativeValue {
+        NativeValue { type_name: type_name.to_owned(), value: Rc::new(~value as ~Any) }
+    }
+    pub fn type_name<'a>(&'a self) -> &'a str {
+        self.type_name.as_slice()
+    }
+    /**
+     * @brief the Rust value, if it is a T
+     */
+    pub fn get<'a, T: 'static>(&'a self) -> Option<&'a T> {
+        let value: &'a Any = &**self.value.borrow();
+        value.as_ref::<T>()
+    }
+}
+
+impl Eq for NativeValue {
+    fn eq(&self, other: &NativeValue) -> bool {
+        borrow::ref_eq(self.value.borrow(), other.value.borrow())
+    }
+}
+
+/**
+ * @brief print the realized part of a lazy seq
+ *
+ * Walks the cells in a loop, so long seqs don't use up the stack.
+ */
+fn lazy_to_str(seq: &LazySeq) -> ~str
+{
+    let mut items: ~[~str] = ~[];
+    let mut current = seq.clone();
+    loop {
+        let rest = match current.peek() {
+            Some(Some((first, rest))) => {
+                items.push(first.to_str());
+                rest
+            },
+            Some(None) => break,
+            None => {
+                items.push(~"...");
+                break;
+            }
+        };
+        current = match rest {
+            Lazy(next) => next,
+            List(l) => {
+                items.push_all_move(l.iter().map(|x| x.to_str()).collect());
+                break;
+            },
+            Vec(v) => {
+                items.push_all_move(v.iter().map(|x| x.to_str()).collect());
+                break;
+            },
+            String(s) => {
+                items.push_all_move(s.chars().map(|c| Character(c).to_str()).collect());
+                break;
+            },
+            nil => break,
+            other => {
+                items.push(other.to_str());
+                break;
+            }
+        };
+    }
+    ~"(" + items.connect(" ") + ")"
+}
+
+/**
+ * @brief map from elements to elements
+ *
+ * Entries are kept in insertion order, and a key is only ever
+ * present once. Equality doesn't depend on the order.
+ */
+#[deriving(Clone)]
+pub struct ElementMap {
+    priv entries: ~[(Element, Element)]
+}
+
+impl ElementMap {
+    pub fn new() -> ElementMap {
+        ElementMap { entries: ~[] }
+    }
+    /**
+     * @brief build a map from key/value pairs, later keys winning
+     */
+    pub fn from_pairs(pairs: ~[(Element, Element)]) -> ElementMap {
+        let mut m = ElementMap::new();
+        for (k, v) in pairs.move_iter() {
+            m.insert(k, v);
+        }
+        m
+    }
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+    pub fn find<'a>(&'a self, key: &Element) -> Option<&'a Element> {
+        for &(ref k, ref v) in self.entries.iter() {
+            if k == key {
+                return Some(v);
+            }
+        }
+        None
+    }
+    pub fn insert(&mut self, key: Element, value: Element) {
+        for entry in self.entries.mut_iter() {
+            match *entry {
+                (ref k, ref mut v) if *k == key => {
+                    *v = value;
+                    return;
+                },
+                _ => ()
+            }
+        }
+        self.entries.push((key, value));
+    }
+    pub fn iter<'a>(&'a self) -> vec::VecIterator<'a, (Element, Element)> {
+        self.entries.iter()
+    }
+}
+
+impl Eq for ElementMap {
+    fn eq(&self, other: &ElementMap) -> bool {
+        self.len() == other.len() && self.entries.iter().all(|&(ref k, ref v)| {
+            other.find(k) == Some(v)
+        })
+    }
+}
+
 impl ToStr for Element {
     fn to_str(&self) -> ~str {
         match self.clone() {
-            Symbol(s) => s.clone(),
+            Symbol(s) | Local(s, _, _, _) | Interned(s, _) => s.clone(),
+            Keyword(k) => format!(":{:s}", k),
             Number(n) => n.to_str(),
             String(s) => format!("\"{:s}\"", s),
             Character(c) => c.to_str(),
             Boolean(b) => b.to_str(),
             ParseError(p) => format!("Parse Error: {:s}", p),
             EvalError(e) => format!("Eval Error: {:s}", e),
+            ExInfo(msg, data) => {
+                ~"#error {:message " + String(msg).to_str() + " :data " + data.to_str() + "}"
+            },
             List(l) => {
                 let form = l.iter().fold(~"", |a, b| a + " " + b.to_str());
                 ~"(" + form.trim() + ")"
@@ -34,8 +428,20 @@ impl ToStr for Element {
                 let form = v.iter().fold(~"", |a, b| a + " " + b.to_str());
                 ~"[" + form.trim() + "]"
             },
+            Map(m) => {
+                let form = m.iter().fold(~"", |a, &(ref k, ref v)| {
+                    a + " " + k.to_str() + " " + v.to_str()
+                });
+                ~"{" + form.trim() + "}"
+            },
+            Record(name, fields) => ~"#" + name + Map(fields).to_str(),
             Function(f) => f.to_str(),
             FuncPrimitive(f) => f.to_str(),
+            FuncNative(f) => f.to_str(),
+            Lazy(seq) => lazy_to_str(&seq),
+            Atom(a) => ~"#atom[" + a.value().to_str() + "]",
+            Multi(m) => ~"#multifn[" + m.name() + "]",
+            Native(v) => ~"#object[" + v.type_name() + "]",
             _ => format!("{:?}", self)
         }
     }
@@ -48,6 +454,118 @@ fn test_element_tostr() {
     assert!(String(~"test string").to_str() == ~"\"test string\"");
     assert!(Character('c').to_str() == ~"c");
     assert!(Boolean(true).to_str() == ~"true");
-    assert!(List(~[Symbol(~"+"), Number(1), Number(2)]).to_str() == ~"(+ 1 2)");
-    assert!(Vec(~[Number(1), Number(2)]).to_str() == ~"[1 2]");
+    assert!(list_of(~[Symbol(~"+"), Number(1), Number(2)]).to_str() == ~"(+ 1 2)");
+    assert!(vec_of(~[Number(1), Number(2)]).to_str() == ~"[1 2]");
+    assert!(Keyword(~"a").to_str() == ~":a");
+    let m = ElementMap::from_pairs(~[(Keyword(~"a"), Number(1)), (Keyword(~"b"), Number(2))]);
+    assert!(Map(m.clone()).to_str() == ~"{:a 1 :b 2}");
+    assert!(ExInfo(~"oops", ~Map(m.clone())).to_str()
+            == ~"#error {:message \"oops\" :data {:a 1 :b 2}}");
+    assert!(Record(~"Point", m.clone()).to_str() == ~"#Point{:a 1 :b 2}");
+    // records compare by type as well as fields
+    assert!(Record(~"Point", m.clone()) != Map(m.clone()));
+    assert!(Record(~"Point", m.clone()) != Record(~"Size", m));
+}
+
+#[test]
+fn test_element_map() {
+    let mut m = ElementMap::new();
+    m.insert(Keyword(~"a"), Number(1));
+    m.insert(Keyword(~"b"), Number(2));
+    m.insert(Keyword(~"a"), Number(3));
+    assert!(m.len() == 2);
+    assert!(m.find(&Keyword(~"a")) == Some(&Number(3)));
+    assert!(m.find(&Keyword(~"c")) == None);
+    // order doesn't matter for equality
+    let other = ElementMap::from_pairs(~[(Keyword(~"b"), Number(2)), (Keyword(~"a"), Number(3))]);
+    assert!(m == other);
+    assert!(m != ElementMap::from_pairs(~[(Keyword(~"a"), Number(3))]));
+}
+
+#[test]
+fn test_lazy_seq_tostr() {
+    let unrealized = LazySeq::new(nil);
+    assert!(Lazy(unrealized.clone()).to_str() == ~"(...)");
+    let seq = LazySeq::cons(Number(1), Lazy(LazySeq::cons(Number(2), Lazy(unrealized.clone()))));
+    assert!(Lazy(seq.clone()).to_str() == ~"(1 2 ...)");
+    // clones share their cells
+    unrealized.set_realized(None);
+    assert!(Lazy(seq).to_str() == ~"(1 2)");
+    assert!(Lazy(LazySeq::cons(Number(1), list_of(~[Number(2)]))).to_str() == ~"(1 2)");
+    assert!(unrealized == unrealized.clone());
+    assert!(LazySeq::new(nil) != LazySeq::new(nil));
+}
+
+#[test]
+fn test_lazy_seq_drop() {
+    // a long realized seq is freed without recursing once per cell
+    let tail = {
+        let mut seq = nil;
+        for i in range(0, 1000000) {
+            seq = Lazy(LazySeq::cons(Number(i), seq));
+        }
+        match seq {
+            Lazy(ref s) => match s.peek() {
+                Some(Some((_, rest))) => rest,
+                _ => nil
+            },
+            _ => nil
+        }
+    };
+    // but what is still held elsewhere is kept
+    match tail {
+        Lazy(ref s) => assert!(match s.peek() {
+            Some(Some((x, _))) => x == Number(999998),
+            _ => false
+        }),
+        _ => fail!("the tail was dropped")
+    }
+}
+
+#[test]
+fn test_atom_ref() {
+    let a = AtomRef::new(Number(1));
+    let b = a.clone();
+    b.set_value(Number(2));
+    assert!(a.value() == Number(2));
+    assert!(Atom(a.clone()).to_str() == ~"#atom[2]");
+    // atoms compare by identity
+    assert!(a == b);
+    assert!(AtomRef::new(Number(2)) != a);
+    a.add_watch(Keyword(~"w"), Number(1));
+    a.add_watch(Keyword(~"w"), Number(2));
+    assert!(a.watches() == ~[(Keyword(~"w"), Number(2))]);
+    a.remove_watch(&Keyword(~"w"));
+    assert!(a.watches() == ~[]);
+}
+
+#[test]
+fn test_multi_ref() {
+    let m = MultiRef::new("area", Keyword(~"shape"));
+    let n = m.clone();
+    n.add_method(Keyword(~"square"), Number(1));
+    n.add_method(Keyword(~"circle"), Number(2));
+    n.add_method(Keyword(~"square"), Number(3));
+    assert!(m.methods() == ~[(Keyword(~"circle"), Number(2)), (Keyword(~"square"), Number(3))]);
+    m.remove_method(&Keyword(~"circle"));
+    assert!(n.methods() == ~[(Keyword(~"square"), Number(3))]);
+    m.prefer(Keyword(~"a"), Keyword(~"b"));
+    assert!(n.prefers(&Keyword(~"a"), &Keyword(~"b")));
+    assert!(!n.prefers(&Keyword(~"b"), &Keyword(~"a")));
+    assert!(Multi(m.clone()).to_str() == ~"#multifn[area]");
+    // multimethods compare by identity
+    assert!(m == n);
+    assert!(MultiRef::new("area", Keyword(~"shape")) != m);
+}
+
+#[test]
+fn test_native_value() {
+    let v = NativeValue::new("Port", 80u16);
+    assert!(v.type_name() == "Port");
+    assert!(v.get::<u16>() == Some(&80u16));
+    assert!(v.get::<i64>() == None);
+    assert!(Native(v.clone()).to_str() == ~"#object[Port]");
+    // native values compare by identity
+    assert!(v == v.clone());
+    assert!(NativeValue::new("Port", 80u16) != v);
 }
@@ -0,0 +1,569 @@
+/**
+ * @file vm.rs
+ * @brief a stack machine that runs compiled code
+ *
+ * Every call to a compiled fn gets a frame on the machine rather than
+ * a Rust call, so recursion through compiled fns doesn't nest the
+ * interpreter, and tail calls reuse the frame they are made from.
+ * Locals live in the same environment frames the tree-walker uses, so
+ * the two can call each other freely.
+ */
+use std::rc::Rc;
+
+use types::Element;
+use types::{Function, List, Map, ElementMap, vec_of};
+
+use env::Env;
+
+use symbol::{Sym, name_of};
+
+use functypes::BoundFn;
+
+use compiler::Chunk;
+use compiler::{Const, LoadLocal, LoadGlobal, Dup, Pop, Jump, JumpIf, JumpUnless, Case, NoMatch};
+use compiler::{PushFrame, PopFrame, Bind, MakeVec, MakeMap, MakeFn, Fallback, MacroGuard};
+use compiler::{Call, TailCall, Recur, Return};
+
+use eval::{Bindings, EvalResult, Exception, eval_error};
+
+mod types;
+
+/**
+ * @brief a call in progress
+ */
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: uint,
+    // where the frame's values start on the stack
+    base: uint,
+    // the scope and namespace the fn was called from, which the
+    // tree-walked fns it tail calls are run from too
+    parent: Rc<Env>,
+    ns: Sym,
+    // how many states were saved when the frame started
+    saved: uint,
+    // the call form, and the latest tail call, for traces
+    call: Option<Element>,
+    tail_call: Option<Element>
+}
+
+struct Machine {
+    stack: ~[Element],
+    frames: ~[Frame],
+    // the scope and namespace to go back to when each frame of locals,
+    // and each call, ends
+    saved: ~[(Rc<Env>, Sym)]
+}
+
+/**
+ * @brief run a compiled form in the bindings' scope
+ */
+pub fn eval(b: &mut Bindings, chunk: Chunk) -> EvalResult
+{
+    let mut m = Machine { stack: ~[], frames: ~[], saved: ~[] };
+    m.frames.push(Frame {
+        chunk: Rc::new(chunk),
+        pc: 0,
+        base: 0,
+        parent: b.scope.clone(),
+        ns: b.current_ns,
+        saved: 0,
+        call: None,
+        tail_call: None
+    });
+    m.run(b)
+}
+
+/**
+ * @brief call a compiled fn
+ * @param f:        the fn, which must have code
+ * @param args:     the evaluated args
+ */
+pub fn call(b: &mut Bindings, f: &BoundFn, args: ~[Element]) -> EvalResult
+{
+    let mut m = Machine { stack: ~[], frames: ~[], saved: ~[] };
+    let (scope, ns) = (b.scope.clone(), b.current_ns);
+    match m.enter(b, f, args, scope, ns, None, None) {
+        Ok(()) => m.run(b),
+        Err(e) => Err(m.unwind(b, e))
+    }
+}
+
+impl Machine {
+    fn run(&mut self, b: &mut Bindings) -> EvalResult {
+        loop {
+            match self.step(b) {
+                Ok(None) => (),
+                Ok(Some(result)) => return Ok(result),
+                Err(e) => return Err(self.unwind(b, e))
+            }
+        }
+    }
+    /**
+     * @brief run one instruction
+     * @return the result, once the outermost frame returns
+     */
+    fn step(&mut self, b: &mut Bindings) -> Result<Option<Element>, Exception> {
+        let top = self.frames.len() - 1;
+        let pc = self.frames[top].pc;
+        self.frames[top].pc = pc + 1;
+        let code = self.frames[top].chunk.clone();
+        let chunk = code.borrow();
+        match chunk.code[pc] {
+            Const(c) => self.stack.push(chunk.consts[c].clone()),
+            LoadLocal(sym, depth, slot) => {
+                let value = match b.scope.borrow().get(sym, depth, slot) {
+                    Some(value) => value,
+                    None => {
+                        let msg = format!("{:s}: local isn't in the slot it was given",
+                                          name_of(sym));
+                        return eval_error(msg);
+                    }
+                };
+                self.stack.push(value);
+            },
+            LoadGlobal(sym) => {
+                let value = try_eval!(b.eval_symbol(sym));
+                self.stack.push(value);
+            },
+            Dup => {
+                let value = self.stack[self.stack.len() - 1].clone();
+                self.stack.push(value);
+            },
+            Pop => {
+                self.stack.pop();
+            },
+            Jump(to) => self.frames[top].pc = to,
+            JumpIf(ref name, to) => {
+                let test = self.stack.pop();
+                if try_eval!(b.truthy(name.as_slice(), &test)) {
+                    self.frames[top].pc = to;
+                }
+            },
+            JumpUnless(ref name, to) => {
+                let test = self.stack.pop();
+                if !try_eval!(b.truthy(name.as_slice(), &test)) {
+                    self.frames[top].pc = to;
+                }
+            },
+            Case(c, to) => {
+                let matches = {
+                    let value = &self.stack[self.stack.len() - 1];
+                    // a list groups several constants
+                    match chunk.consts[c] {
+                        List(ref options) => options.iter().any(|o| o == value),
+                        ref constant => constant == value
+                    }
+                };
+                if matches {
+                    self.stack.pop();
+                } else {
+                    self.frames[top].pc = to;
+                }
+            },
+            NoMatch => {
+                let value = self.stack.pop();
+                return eval_error(format!("case: no clause matching {:s}", value.to_str()));
+            },
+            PushFrame => {
+                self.saved.push((b.scope.clone(), b.current_ns));
+                let scope = Env::nested(&b.scope);
+                b.scope = scope;
+            },
+            PopFrame => {
+                let last = self.saved.len() - 1;
+                self.restore(b, last);
+            },
+            Bind(sym) => {
+                let value = self.stack.pop();
+                b.scope.borrow().insert(sym, value);
+            },
+            MakeVec(n) => {
+                let items = self.pop_n(n);
+                self.stack.push(vec_of(items));
+            },
+            MakeMap(n) => {
+                let items = self.pop_n(2 * n);
+                let mut map = ElementMap::new();
+                for pair in items.chunks(2) {
+                    map.insert(pair[0].clone(), pair[1].clone());
+                }
+                self.stack.push(Map(map));
+            },
+            MakeFn(c) => self.stack.push(b.close_over(chunk.consts[c].clone())),
+            Fallback(form) => {
+                let value = try_eval!(b.eval_elem(chunk.consts[form].clone()));
+                self.stack.push(value);
+            },
+            MacroGuard(form, to) => {
+                let is_macro = match self.stack[self.stack.len() - 1] {
+                    Function(ref f) => f.is_macro,
+                    _ => false
+                };
+                if is_macro {
+                    self.stack.pop();
+                    let value = try_eval!(b.eval_elem(chunk.consts[form].clone()));
+                    self.stack.push(value);
+                    self.frames[top].pc = to;
+                }
+            },
+            Call(argc, form, leave, loop_tail) => {
+                try_eval!(self.call(b, argc, &chunk.consts[form], leave, loop_tail));
+            },
+            TailCall(argc, form) => return self.tail_call(b, argc, &chunk.consts[form]),
+            Recur(start, leave, argc, ref names, is_fn) => {
+                if argc != names.len() {
+                    if is_fn {
+                        // like the tree-walker, this isn't traced as
+                        // part of the call it recurs in
+                        self.frames[top].tail_call = None;
+                    }
+                    let msg = format!("recur: expected {:u} args, got {:u}", names.len(), argc);
+                    return eval_error(msg);
+                }
+                let args = self.pop_n(argc);
+                if leave > 0 {
+                    let at = self.saved.len() - leave;
+                    self.restore(b, at);
+                }
+                // a new frame in place of the old one, so anything that
+                // kept the old frame still sees the old values
+                let scope = match b.scope.borrow().parent() {
+                    Some(parent) => Env::nested(parent),
+                    None => fail!("recur: can't replace the toplevel")
+                };
+                b.scope = scope;
+                for (name, arg) in names.iter().zip(args.move_iter()) {
+                    b.scope.borrow().insert(*name, arg);
+                }
+                self.frames[top].pc = start;
+            },
+            Return => {
+                let result = self.stack.pop();
+                return Ok(self.leave_frame(b, result));
+            }
+        }
+        Ok(None)
+    }
+    fn pop_n(&mut self, n: uint) -> ~[Element] {
+        let from = self.stack.len() - n;
+        let items = self.stack.slice_from(from).to_owned();
+        self.stack.truncate(from);
+        items
+    }
+    /**
+     * @brief go back to a saved scope and namespace, dropping it and
+     *        any saved after it
+     */
+    fn restore(&mut self, b: &mut Bindings, at: uint) {
+        let (scope, ns) = self.saved[at].clone();
+        b.scope = scope;
+        b.current_ns = ns;
+        self.saved.truncate(at);
+    }
+    /**
+     * @brief start running a compiled fn in a new frame, nested in
+     *        the scope it was made in
+     * @param parent:   the scope it is called from
+     * @param ns:       the namespace to call it from
+     */
+    fn enter(&mut self, b: &mut Bindings, f: &BoundFn, args: ~[Element], parent: Rc<Env>,
+             ns: Sym, call: Option<Element>, tail_call: Option<Element>)
+        -> Result<(), Exception>
+    {
+        self.saved.push((b.scope.clone(), b.current_ns));
+        b.scope = b.fn_scope(f);
+        b.current_ns = ns;
+        self.frames.push(Frame {
+            chunk: f.code.get_ref().clone(),
+            pc: 0,
+            base: self.stack.len(),
+            parent: parent,
+            ns: ns,
+            saved: self.saved.len(),
+            call: call,
+            tail_call: tail_call
+        });
+        try_eval!(b.bind_args(f, args));
+        Ok(())
+    }
+    /**
+     * @brief end the frame on top, handing its result to the frame
+     *        below
+     * @return the result, if it was the outermost frame
+     */
+    fn leave_frame(&mut self, b: &mut Bindings, result: Element) -> Option<Element> {
+        let frame = self.frames.pop();
+        self.stack.truncate(frame.base);
+        if frame.saved > 0 {
+            self.restore(b, frame.saved - 1);
+        }
+        if self.frames.is_empty() {
+            return Some(result);
+        }
+        self.stack.push(result);
+        None
+    }
+    /**
+     * @brief pop a fn and its args, and call it
+     * @param form:         the call form, for traces
+     * @param leave:        how many frames of locals out the scope it
+     *                      is called from is
+     * @param loop_tail:    whether it is in tail position of a loop
+     *
+     * Bound fns run in a frame nested in the scope they were made in,
+     * wherever they are called from, as in the tree-walker; builtins
+     * run in the current one.
+     */
+    fn call(&mut self, b: &mut Bindings, argc: uint, form: &Element, leave: uint,
+            loop_tail: bool) -> Result<(), Exception>
+    {
+        let args = self.pop_n(argc);
+        let (scope, ns) = if leave == 0 {
+            (b.scope.clone(), b.current_ns)
+        } else {
+            self.saved[self.saved.len() - leave].clone()
+        };
+        let result = match self.stack.pop() {
+            Function(fptr) => {
+                if !fptr.is_macro && fptr.code.is_some() {
+                    let (call, tail_call) = if loop_tail {
+                        (None, Some(form.clone()))
+                    } else {
+                        (Some(form.clone()), None)
+                    };
+                    return self.enter(b, fptr, args, scope, ns, call, tail_call);
+                }
+                // the tree-walker runs it, from the same scope
+                let (old_scope, old_ns) = (b.scope.clone(), b.current_ns);
+                b.scope = scope;
+                b.current_ns = ns;
+                let result = b.apply(Function(fptr), args);
+                b.scope = old_scope;
+                b.current_ns = old_ns;
+                result
+            },
+            f => b.apply(f, args)
+        };
+        match result {
+            Ok(value) => {
+                self.stack.push(value);
+                Ok(())
+            },
+            Err(e) => Err(e.unwound_through(form))
+        }
+    }
+    /**
+     * @brief pop a fn and its args, and call it in place of the fn
+     *        running in the frame on top
+     */
+    fn tail_call(&mut self, b: &mut Bindings, argc: uint, form: &Element)
+        -> Result<Option<Element>, Exception>
+    {
+        let args = self.pop_n(argc);
+        let fptr = match self.stack.pop() {
+            Function(fptr) => fptr,
+            f => {
+                // builtins run where they are, like any other call
+                let result = match b.apply(f, args) {
+                    Ok(value) => value,
+                    Err(e) => return Err(e.unwound_through(form))
+                };
+                return Ok(self.leave_frame(b, result));
+            }
+        };
+        let top = self.frames.len() - 1;
+        // only the latest tail call shows up in a trace
+        self.frames[top].tail_call = Some(form.clone());
+        let base = self.frames[top].base;
+        let saved = self.frames[top].saved;
+        self.stack.truncate(base);
+        self.saved.truncate(saved);
+        b.current_ns = self.frames[top].ns;
+        if !fptr.is_macro && fptr.code.is_some() {
+            b.scope = b.fn_scope(fptr);
+            self.frames[top].chunk = fptr.code.get_ref().clone();
+            self.frames[top].pc = 0;
+            try_eval!(b.bind_args(fptr, args));
+            Ok(None)
+        } else {
+            b.scope = self.frames[top].parent.clone();
+            let result = try_eval!(b.apply(Function(fptr), args));
+            Ok(self.leave_frame(b, result))
+        }
+    }
+    /**
+     * @brief add the calls being unwound through to an exception's
+     *        trace, and go back to the state the machine started in
+     */
+    fn unwind(&mut self, b: &mut Bindings, e: Exception) -> Exception {
+        let mut e = e;
+        for frame in self.frames.rev_iter() {
+            match frame.tail_call {
+                Some(ref call) => e = e.unwound_through(call),
+                None => ()
+            }
+            match frame.call {
+                Some(ref call) => e = e.unwound_through(call),
+                None => ()
+            }
+        }
+        if self.saved.len() > 0 {
+            self.restore(b, 0);
+        }
+        self.frames.truncate(0);
+        self.stack.truncate(0);
+        e
+    }
+}
+
+
+/**
+ * @brief evaluate a program with the tree-walker and on the vm
+ * @return the printed results or errors, tree-walker's first
+ */
+#[cfg(test)]
+fn both_ways(program: &str) -> (~str, ~str)
+{
+    let run = |compiled: bool| {
+        let mut b = Bindings::new();
+        b.set_compiled(compiled);
+        let printed = match b.eval(program) {
+            Ok(value) => b.print_str(&value),
+            Err(_) => Err(b.last_error().unwrap().clone())
+        };
+        match printed {
+            Ok(out) => out,
+            Err(ex) => ex.to_str()
+        }
+    };
+    (run(false), run(true))
+}
+
+#[test]
+fn test_vm_matches_tree_walker() {
+    let programs = [
+        "(do (defn fact [n] (if (< n 2) 1 (* n (fact (- n 1))))) (fact 20))",
+        "(do (defn fib [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (fib 15))",
+        "(loop [i 0 acc []] (if (< i 5) (recur (inc i) (conj acc (* i i))) acc))",
+        "(do (defn count-down [n] (if (= n 0) :done (count-down (dec n)))) (count-down 100000))",
+        "(do (defn ev? [n] (if (= n 0) true (od? (dec n))))
+             (defn od? [n] (if (= n 0) false (ev? (dec n))))
+             (ev? 10001))",
+        "(let [x 1 y (+ x 1)] (let [x 10] [x y {:a x :b (str y)}]))",
+        "(do (defn sum [& xs] (reduce + 0 xs)) [(sum) (sum 1 2 3)])",
+        "(do (defn f [x] (fn [y] (+ x y))) ((f 1) 2))",
+        "(map (fn [x] (* x 2)) (filter odd? (range 10)))",
+        "(do (defn lz [n] (lazy-seq (cons n (lz (inc n))))) (take 3 (lz 1)))",
+        "(let [a (atom 0)] (reduce (fn [_ i] (swap! a + i)) nil (range 10)) @a)",
+        "(cond (= 1 2) :a (= 1 1) :b :else :c)",
+        "[(case 2 1 :one (2 3) :two-or-three :other) (case 9 1 :one :other)]",
+        "(case 9 1 :one)",
+        "[(and 1 2 nil 3) (or nil false 4) (and) (or)]",
+        "[(if-let [x (get {:a 1} :a)] (inc x) :none) (when-let [x nil] x) (when-not false 1)]",
+        "(try (throw (ex-info \"boom\" {:x 1})) (catch e (ex-data e)) (finally 2))",
+        "(do (defmacro unless [t & body] `(if ~t nil (do ~@body))) (unless false 1 2))",
+        "(do (defn g [n] (unless-later n)) (defmacro unless-later [t] `(if ~t 1 2)) (g nil))",
+        "(do (defn loop-in [n] (loop [i 0] (when (< i n) (recur (inc i))))) (loop-in 10))",
+        "(do (defn r [n] (if (> n 0) (recur (dec n)) :done)) (r 50000))",
+        "(do (defn r [n] (recur)) (r 1))",
+        "(loop [i 0] (recur))",
+        "(do (defn bad [x] (+ x :a)) (defn outer [x] (bad x)) (defn top [] (inc (outer 1))) (top))",
+        "(do (defn h [x] (undefined-thing x)) (h 1))",
+        "(do (defn two [a b] a) (two 1))",
+        "(do (defn see [] x) (defn caller [x] (see)) (caller 5))",
+        "(do (defn see [] x) (let [x 5] (see)))",
+        "(do (def ^:dynamic *d* 1) (defn d [] *d*) [(binding [*d* 2] (d)) (d)])",
+        "(do (ns a.b) (defn- hidden [] 1) (defn shown [] (hidden)) (ns user)
+             (require '[a.b :as ab]) [(ab/shown) (ns-missing)])",
+        "(do (ns a.b) (defn- hidden [] 1) (ns user) (require '[a.b :as ab]) (ab/hidden))",
+        "(if true)",
+        "(let [x] x)",
+        "(quote 1 2)",
+        "(when-let [x] 1)",
+        "(if 1 2 3 4)"
+    ];
+    for program in programs.iter() {
+        let (walked, compiled) = both_ways(*program);
+        if walked != compiled {
+            fail!("{:s}\n  tree-walker: {:s}\n  vm: {:s}", *program, walked, compiled);
+        }
+    }
+}
+
+#[test]
+fn test_vm_strict_booleans() {
+    let mut b = Bindings::new();
+    b.set_compiled(true);
+    b.set_strict_booleans(true);
+    assert!(b.eval("(if 1 2 3)").is_err());
+    assert!(b.last_error().unwrap().to_str() == ~"if: test must be boolean");
+    assert!(b.eval("(loop [i 0] (if (< i 3) (recur (inc i)) i))") == Ok(::types::Number(3)));
+}
+
+#[test]
+fn test_vm_closures() {
+    // fns see the locals they were made with, not their caller's
+    let programs = [
+        ("(do (defn f [x] (fn [y] (+ x y))) ((f 1) 2))", ~"3"),
+        ("(do (defn adder [x] (fn [y] (+ x y))) (let [add2 (adder 2) x 10] (add2 1)))", ~"3"),
+        ("(do (let [x 1] (defn get-x [] x)) (let [x 2] (get-x)))", ~"1"),
+        ("(do (defn see [] x) (defn caller [x] (see)) (caller 5))", ~"Symbol Not defined"),
+        ("(do (defn counter [] (let [n (atom 0)] (fn [] (swap! n inc))))
+              (let [c (counter)] (c) (c)))", ~"2")
+    ];
+    for &(program, ref expected) in programs.iter() {
+        let (walked, compiled) = both_ways(program);
+        // errors are followed by their traces
+        assert!(walked.starts_with(*expected));
+        assert!(compiled.starts_with(*expected));
+    }
+}
+
+#[test]
+fn test_vm_deep_recursion() {
+    // calls between compiled fns don't nest Rust calls
+    let mut b = Bindings::new();
+    b.set_compiled(true);
+    b.eval("(defn depth [n] (if (= n 0) 0 (inc (depth (dec n)))))");
+    assert!(b.eval("(depth 10000)") == Ok(::types::Number(10000)));
+}
+
+#[cfg(test)]
+fn bench_fib(bh: &mut ::extra::test::BenchHarness, compiled: bool) {
+    let mut b = Bindings::new();
+    b.set_compiled(compiled);
+    b.eval("(defn fib [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))");
+    bh.iter(|| {
+        b.eval("(fib 18)");
+    });
+}
+
+#[bench]
+fn bench_fib_tree_walker(bh: &mut ::extra::test::BenchHarness) {
+    bench_fib(bh, false);
+}
+
+#[bench]
+fn bench_fib_vm(bh: &mut ::extra::test::BenchHarness) {
+    bench_fib(bh, true);
+}
+
+#[cfg(test)]
+fn bench_sum_loop(bh: &mut ::extra::test::BenchHarness, compiled: bool) {
+    let mut b = Bindings::new();
+    b.set_compiled(compiled);
+    bh.iter(|| {
+        b.eval("(loop [i 0 acc 0] (if (< i 10000) (recur (inc i) (+ acc (* i i))) acc))");
+    });
+}
+
+#[bench]
+fn bench_sum_loop_tree_walker(bh: &mut ::extra::test::BenchHarness) {
+    bench_sum_loop(bh, false);
+}
+
+#[bench]
+fn bench_sum_loop_vm(bh: &mut ::extra::test::BenchHarness) {
+    bench_sum_loop(bh, true);
+}